@@ -39,6 +39,8 @@ pub enum IcError {
 }
 
 /// The result of [ws_close].
+pub type CanisterWsCloseResultWithIcError = Result<(), IcError>;
+/// The result of the canister method 'ws_close'.
 pub type _CanisterWsCloseResult = Result<(), String>;
 /// The result of [ws_get_messages].
 pub type CanisterWsGetMessagesResultWithIcError = Result<CanisterOutputCertifiedMessages, IcError>;
@@ -52,6 +54,12 @@ pub struct CanisterWsOpenArguments {
     pub gateway_principal: Principal,
 }
 
+/// The arguments for the canister method 'ws_message'.
+#[derive(CandidType, Clone, Deserialize, Serialize, Eq, PartialEq, Debug)]
+pub struct CanisterWsMessageArguments {
+    pub msg: WebsocketMessage,
+}
+
 /// The arguments for [ws_close].
 #[derive(CandidType, Clone, Deserialize, Serialize, Eq, PartialEq, Debug)]
 pub struct CanisterWsCloseArguments {
@@ -168,17 +176,18 @@ pub async fn ws_close(
     agent: &Agent,
     canister_id: &Principal,
     args: CanisterWsCloseArguments,
-) -> _CanisterWsCloseResult {
-    let args = candid::encode_args((args,)).map_err(|e| e.to_string())?;
+) -> CanisterWsCloseResultWithIcError {
+    let args = candid::encode_args((args,)).map_err(IcError::Candid)?;
 
     let res = agent
         .update(canister_id, "ws_close")
         .with_arg(args)
         .call_and_wait()
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(IcError::Agent)?;
 
-    Decode!(&res, _CanisterWsCloseResult).map_err(|e| e.to_string())?
+    let res = Decode!(&res, _CanisterWsCloseResult).map_err(IcError::Candid)?;
+    res.map_err(IcError::Cdk)
 }
 
 #[cfg(not(feature = "mock-server"))]
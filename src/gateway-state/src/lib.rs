@@ -1,8 +1,16 @@
 use canister_utils::{ClientKey, IcWsCanisterMessage};
 use dashmap::{mapref::entry::Entry, DashMap};
 use ic_agent::export::Principal;
-use std::sync::Arc;
-use tokio::sync::mpsc::Sender;
+use std::{
+    net::IpAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+use tokio::sync::{mpsc::Sender, Notify};
 use tracing::Span;
 
 /// State of the WS Gateway that can be shared between threads
@@ -33,43 +41,59 @@ impl GatewayState {
     /// This is sufficient to prevent the function from yielding while holding the lock.
     ///
     /// In order to not starve other tasks, make sure to keep the critical section as short as possible.
+    ///
+    /// Returns an error, without inserting the client, if the canister already has 'max_clients' clients
+    /// or if another session has the same client key and the policy rejects the duplicate keys.
+    /// If the policy replaces the duplicate keys, the session being replaced is requested to close.
     pub fn insert_client_channel_and_get_new_poller_state(
         &self,
         canister_id: CanisterPrincipal,
         client_key: ClientKey,
-        client_channel_tx: Sender<IcWsCanisterMessage>,
-        client_session_span: Span,
-    ) -> Option<PollerState> {
+        client_sender: ClientSender,
+        max_clients: Option<usize>,
+        duplicate_client_key_policy: DuplicateClientKeyPolicy,
+    ) -> Result<Option<PollerState>, ClientInsertionError> {
         // START OF THE CRITICAL SECTION
         match self.inner.data.entry(canister_id) {
             Entry::Occupied(mut entry) => {
                 // the poller has already been started
                 // if the poller is active, add client key and sender end of the channel to the poller state
                 let poller_state = entry.get_mut();
-                poller_state.insert(
-                    client_key,
-                    ClientSender {
-                        sender: client_channel_tx.clone(),
-                        span: client_session_span,
-                    },
-                );
+                if let Some(duplicate_client) = poller_state.clients.get(&client_key) {
+                    match duplicate_client_key_policy {
+                        DuplicateClientKeyPolicy::Reject => {
+                            return Err(ClientInsertionError::DuplicateClientKey)
+                        },
+                        // the replaced session closes the connection with its client once it receives the request
+                        // if it is already closing, the request is redundant and can be ignored
+                        DuplicateClientKeyPolicy::Replace => {
+                            let _ = duplicate_client
+                                .close_sender
+                                .try_send(ClientCloseReason::Replaced);
+                        },
+                    }
+                } else if let Some(max_clients) = max_clients {
+                    // the number of clients is checked while holding the lock so that concurrent sessions cannot exceed the limit
+                    // a replaced client does not count towards the limit
+                    if poller_state.clients.len() >= max_clients {
+                        return Err(ClientInsertionError::MaxClientsReached);
+                    }
+                }
+                poller_state.clients.insert(client_key, client_sender);
                 // the poller shall not be started again
-                None
+                Ok(None)
             },
             Entry::Vacant(entry) => {
+                if max_clients == Some(0) {
+                    return Err(ClientInsertionError::MaxClientsReached);
+                }
                 // the poller has not been started yet
                 // initialize the poller state and add client key and sender end of the channel
-                let poller_state = Arc::new(DashMap::with_capacity_and_shard_amount(1024, 1024));
-                poller_state.insert(
-                    client_key,
-                    ClientSender {
-                        sender: client_channel_tx.clone(),
-                        span: client_session_span,
-                    },
-                );
+                let poller_state = Arc::new(PollerStateInner::new());
+                poller_state.clients.insert(client_key, client_sender);
                 entry.insert(Arc::clone(&poller_state));
                 // the poller shall be started
-                Some(poller_state)
+                Ok(Some(poller_state))
             },
         }
         // END OF THE CRITICAL SECTION
@@ -89,15 +113,25 @@ impl GatewayState {
     /// This is sufficient to prevent the function from yielding while holding the lock.
     ///
     /// In order to not starve other tasks, make sure to keep the critical section as short as possible.
-    pub fn remove_client(&self, canister_id: CanisterPrincipal, client_key: ClientKey) {
+    ///
+    /// The client is removed only if its entry belongs to the session identified by 'session_token'.
+    pub fn remove_client(
+        &self,
+        canister_id: CanisterPrincipal,
+        client_key: ClientKey,
+        session_token: SessionToken,
+    ) {
         // START OF THE CRITICAL SECTION
         if let Entry::Occupied(mut entry) = self.inner.data.entry(canister_id) {
             let poller_state = entry.get_mut();
-            if poller_state.remove(&client_key).is_none() {
-                // as the client was connected, the poller state must contain an entry for 'client_key'
-                // if this is encountered it might indicate a race condition
-                unreachable!("Client key not found in poller state");
-            }
+            // as the client was connected, the poller state contains an entry for 'client_key'
+            // unless the session has been replaced by another session with the same client key in the meantime
+            // if so, the entry belongs to the other session and must not be removed
+            poller_state
+                .clients
+                .remove_if(&client_key, |_, client_sender| {
+                    client_sender.session_token == session_token
+                });
             // even if this is the last client session for the canister, do not remove the canister from the gateway state
             // this will be done by the poller task
         }
@@ -123,10 +157,14 @@ impl GatewayState {
     /// This is sufficient to prevent the function from yielding while holding the lock.
     ///
     /// In order to not starve other tasks, make sure to keep the critical section as short as possible.
+    ///
+    /// The client is removed only if its entry belongs to the session identified by 'session_token',
+    /// so that a session never removes the entry of another session with the same client key.
     pub fn remove_client_if_exists(
         &self,
         canister_id: CanisterPrincipal,
         client_key: ClientKey,
+        session_token: SessionToken,
     ) -> ClientRemovalResult {
         // START OF THE CRITICAL SECTION
         if let Entry::Occupied(mut entry) = self.inner.data.entry(canister_id) {
@@ -136,7 +174,11 @@ impl GatewayState {
             // this will be done by the poller task
             // returns 'ClientRemovalResult::Removed' if the client was removed, 'ClientRemovalResult::Vacant' if there was no such client
             return {
-                match poller_state.remove(&client_key) {
+                match poller_state
+                    .clients
+                    .remove_if(&client_key, |_, client_sender| {
+                        client_sender.session_token == session_token
+                    }) {
                     Some(_) => ClientRemovalResult::Removed(client_key),
                     None => ClientRemovalResult::Vacant,
                 }
@@ -167,16 +209,31 @@ impl GatewayState {
         // remove_if returns None if the condition is not met, otherwise it returns the Some(<entry>)
         // if Some, the poller state is empty and therefore the poller shall terminate - return 'CanisterRemovalResult::Empty'
         // if None, the poller state is not empty and therefore there are still clients connected and the poller shall not terminate - return 'CanisterRemovalResult::NotEmpty'
-        match self
-            .inner
-            .data
-            .remove_if(&canister_id, |_, poller_state| poller_state.is_empty())
-        {
+        match self.inner.data.remove_if(&canister_id, |_, poller_state| {
+            poller_state.clients.is_empty()
+        }) {
             Some(_) => CanisterRemovalResult::Empty,
             None => CanisterRemovalResult::NotEmpty,
         }
     }
 
+    /// SAFETY:
+    ///
+    /// The [Dashmap::get](https://docs.rs/dashmap/5.5.3/src/dashmap/lib.rs.html#1013-1020) method gets a read lock on the shard in which the entry is.
+    ///
+    /// The lock is released as soon as the notifier is cloned, at the end of this function.
+    ///
+    /// Returns None if the canister is not being polled, e.g. because its poller has failed.
+    pub fn get_client_activity_notifier(
+        &self,
+        canister_id: CanisterPrincipal,
+    ) -> Option<ClientActivityNotifier> {
+        self.inner
+            .data
+            .get(&canister_id)
+            .map(|poller_state| Arc::clone(&poller_state.client_activity))
+    }
+
     /// SAFETY:
     ///
     /// The [Dashmap::remove](https://docs.rs/dashmap/5.5.3/src/dashmap/lib.rs.html#930-942) method gets a write lock on the whole shard in which the entry is.
@@ -191,6 +248,75 @@ impl GatewayState {
             unreachable!("failed canister not found in gateway state");
         }
     }
+
+    /// SAFETY:
+    ///
+    /// The [Dashmap::iter](https://docs.rs/dashmap/5.5.3/src/dashmap/lib.rs.html#388-390) method gets a read lock on each shard while iterating over its entries.
+    ///
+    /// The lock of each shard is released as soon as its poller states are cloned.
+    ///
+    /// Returns the principal and the poller state of each canister being polled, sorted by canister principal.
+    pub fn get_poller_states(&self) -> Vec<(CanisterPrincipal, PollerState)> {
+        let mut poller_states: Vec<(CanisterPrincipal, PollerState)> = self
+            .inner
+            .data
+            .iter()
+            .map(|entry| (*entry.key(), Arc::clone(entry.value())))
+            .collect();
+        poller_states.sort_by_key(|(canister_id, _)| *canister_id);
+        poller_states
+    }
+
+    /// SAFETY:
+    ///
+    /// The [Dashmap::get](https://docs.rs/dashmap/5.5.3/src/dashmap/lib.rs.html#1013-1020) method gets a read lock on the shard in which the entry is.
+    ///
+    /// The lock is released as soon as the poller state is cloned, at the end of this function.
+    ///
+    /// Returns None if the canister is not being polled.
+    pub fn get_poller_state(&self, canister_id: CanisterPrincipal) -> Option<PollerState> {
+        self.inner
+            .data
+            .get(&canister_id)
+            .map(|poller_state| Arc::clone(&poller_state))
+    }
+
+    /// Requests the session of the client to close
+    /// The session removes the client from the gateway state and notifies the canister once it is closed
+    /// Returns false if the client is not connected to the canister
+    pub fn close_client(
+        &self,
+        canister_id: CanisterPrincipal,
+        client_key: &ClientKey,
+        close_reason: ClientCloseReason,
+    ) -> bool {
+        let Some(poller_state) = self.get_poller_state(canister_id) else {
+            return false;
+        };
+        let Some(client_sender) = poller_state.clients.get(client_key) else {
+            return false;
+        };
+        // if the session is already closing, the request is redundant and can be ignored
+        let _ = client_sender.close_sender.try_send(close_reason);
+        true
+    }
+
+    /// Requests the sessions of all the clients of the canister to close
+    /// Returns the number of clients requested to close, or None if the canister is not being polled
+    pub fn close_canister_clients(
+        &self,
+        canister_id: CanisterPrincipal,
+        close_reason: ClientCloseReason,
+    ) -> Option<usize> {
+        let poller_state = self.get_poller_state(canister_id)?;
+        let mut closed_clients_count = 0;
+        for client_sender in poller_state.clients.iter() {
+            // if the session is already closing, the request is redundant and can be ignored
+            let _ = client_sender.close_sender.try_send(close_reason);
+            closed_clients_count += 1;
+        }
+        Some(closed_clients_count)
+    }
 }
 
 /// State of the WS Gateway consisting of the principal of each canister being polled
@@ -210,9 +336,125 @@ impl GatewayStateInner {
     }
 }
 
-/// State of each poller consisting of the keys of the clients connected to the poller
-/// and the state associated to each client
-pub type PollerState = Arc<DashMap<ClientKey, ClientSender>>;
+/// State of each poller consisting of the keys of the clients connected to the poller,
+/// the state associated to each client,
+/// the notifier used by the client sessions to signal that a client has sent a message to the canister
+/// and the status of the polling, which can be inspected and paused by the operators of the gateway
+pub type PollerState = Arc<PollerStateInner>;
+
+pub struct PollerStateInner {
+    pub clients: DashMap<ClientKey, ClientSender>,
+    pub client_activity: ClientActivityNotifier,
+    /// Status of the polling, updated by the poller after each polling iteration
+    status: Mutex<PollerStatus>,
+    /// Whether the poller stopped polling until it is resumed
+    is_paused: AtomicBool,
+    /// Notified when the poller is resumed
+    pub resumed: Notify,
+}
+
+impl PollerStateInner {
+    fn new() -> Self {
+        Self {
+            clients: DashMap::with_capacity_and_shard_amount(1024, 1024),
+            client_activity: Arc::new(Notify::new()),
+            status: Mutex::new(PollerStatus::default()),
+            is_paused: AtomicBool::new(false),
+            resumed: Notify::new(),
+        }
+    }
+
+    /// Returns a copy of the status of the polling
+    pub fn get_status(&self) -> PollerStatus {
+        self.lock_status().clone()
+    }
+
+    /// Updates the status of the polling
+    pub fn update_status(&self, update: impl FnOnce(&mut PollerStatus)) {
+        update(&mut self.lock_status());
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused.load(Ordering::Acquire)
+    }
+
+    /// Stops the polling until the poller is resumed, the clients stay connected in the meantime
+    /// Returns false if the poller was already paused
+    pub fn pause(&self) -> bool {
+        !self.is_paused.swap(true, Ordering::AcqRel)
+    }
+
+    /// Resumes the polling from the nonce at which it was paused
+    /// Returns false if the poller was not paused
+    pub fn resume(&self) -> bool {
+        let was_paused = self.is_paused.swap(false, Ordering::AcqRel);
+        if was_paused {
+            self.resumed.notify_waiters();
+        }
+        was_paused
+    }
+
+    fn lock_status(&self) -> std::sync::MutexGuard<'_, PollerStatus> {
+        self.status
+            .lock()
+            .expect("poller status lock should not be poisoned")
+    }
+}
+
+/// Status of the polling of a canister
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PollerStatus {
+    /// Nonce of the next message to be polled from the canister
+    pub next_message_nonce: u64,
+    /// Time at which the last polling iteration completed, None if the canister has not been polled yet
+    pub last_poll_time: Option<SystemTime>,
+    /// Number of consecutive polling iterations which failed
+    pub error_streak: u32,
+    /// Error of the last polling iteration, None if it succeeded
+    pub last_error: Option<String>,
+}
+
+/// Notifier used by the client sessions to wake up the poller of their canister
+pub type ClientActivityNotifier = Arc<Notify>;
+
+/// Error returned when a client cannot be added to the poller state
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientInsertionError {
+    /// The canister reached the maximum number of clients
+    MaxClientsReached,
+    /// Another session has the same client key and the policy rejects the duplicate keys
+    DuplicateClientKey,
+}
+
+/// Policy applied when a session is opened with the same client key as a session already in the poller state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateClientKeyPolicy {
+    /// The new session is rejected and the existing one is kept
+    Reject,
+    /// The existing session is requested to close and is replaced by the new one
+    Replace,
+}
+
+impl DuplicateClientKeyPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicateClientKeyPolicy::Reject => "reject",
+            DuplicateClientKeyPolicy::Replace => "replace",
+        }
+    }
+}
+
+impl FromStr for DuplicateClientKeyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(DuplicateClientKeyPolicy::Reject),
+            "replace" => Ok(DuplicateClientKeyPolicy::Replace),
+            _ => Err(format!("Invalid duplicate client key policy: {}", s)),
+        }
+    }
+}
 
 /// Determines whether the client was removed from the poller state or if there was no such client
 pub enum ClientRemovalResult {
@@ -230,12 +472,84 @@ pub enum CanisterRemovalResult {
     NotEmpty,
 }
 
-/// State of each client consisting of the sender side of the channel used to send canister updates to the client
-/// and the span associated to the client session
+/// State of each client consisting of the sender side of the channel used to send canister updates to the client,
+/// the span associated to the client session,
+/// the sender side of the channel used to request the client session to be closed,
+/// the token identifying the session which owns the entry
+/// and the metadata of the connection of the client
 #[derive(Debug)]
 pub struct ClientSender {
     pub sender: Sender<IcWsCanisterMessage>,
     pub span: ClientSessionSpan,
+    pub close_sender: Sender<ClientCloseReason>,
+    pub session_token: SessionToken,
+    /// IP address of the client, None if it is not known
+    pub client_ip: Option<IpAddr>,
+    /// Time at which the client was added to the poller state
+    pub connected_at: SystemTime,
+    /// Set by the poller once the client missed some of the canister messages
+    /// The following messages are not relayed to the client, as they would be out of order
+    lost_messages: AtomicBool,
+}
+
+impl ClientSender {
+    pub fn new(
+        sender: Sender<IcWsCanisterMessage>,
+        span: ClientSessionSpan,
+        close_sender: Sender<ClientCloseReason>,
+        session_token: SessionToken,
+        client_ip: Option<IpAddr>,
+    ) -> Self {
+        Self {
+            sender,
+            span,
+            close_sender,
+            session_token,
+            client_ip,
+            connected_at: SystemTime::now(),
+            lost_messages: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if the client missed some of the canister messages
+    pub fn has_lost_messages(&self) -> bool {
+        self.lost_messages.load(Ordering::Relaxed)
+    }
+
+    /// Stops the relaying of the canister messages to the client until its session removes it from the poller state
+    pub fn set_lost_messages(&self) {
+        self.lost_messages.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Token identifying the session which added a client to the poller state, unique across the sessions of the gateway
+pub type SessionToken = u64;
+
+/// Reason for which the client session is requested to be closed by the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientCloseReason {
+    /// The client does not read the canister messages as fast as they are relayed by the poller
+    SlowClient,
+    /// The canister the client is connecting to reached the maximum number of clients
+    TooManyClients,
+    /// The canister the client is connecting to is not allowed by the gateway
+    CanisterNotAllowed,
+    /// The client sent messages faster than allowed by the rate limit
+    RateLimited,
+    /// The client asked to resume a session which is not waiting to be resumed
+    SessionNotResumable,
+    /// Another session has the same client key and the policy rejects the duplicate keys
+    DuplicateClientKey,
+    /// The session has been replaced by a new session with the same client key
+    Replaced,
+    /// The client sent an envelope which does not belong to its session
+    InvalidEnvelope,
+    /// The client missed some of the canister messages as the nonces of the canister were not consecutive
+    MessagesLost,
+    /// The client has been kicked by an operator of the gateway
+    Kicked,
+    /// The client did not respond to a ping in time
+    Unresponsive,
 }
 
 pub type ClientSessionSpan = Span;
@@ -253,14 +567,14 @@ mod tests {
     };
 
     #[tokio::test]
-    async fn should_insert_new_client_channels_and_get_new_poller_state_once() {
-        let clients_count = 1000;
+    async fn should_not_insert_more_than_max_clients() {
+        let max_clients = 10;
         let gateway_state = GatewayState::new();
         let canister_id = Principal::from_text("aaaaa-aa").unwrap();
         thread::scope(|s| {
             let mut handles = Vec::new();
-            for i in 0..clients_count {
-                let client_key = ClientKey::new(Principal::anonymous(), i);
+            for i in 0..max_clients * 2 {
+                let client_key = ClientKey::new(Principal::anonymous(), i as u64);
                 let handle = s.spawn(|| {
                     let (client_channel_tx, _): (
                         Sender<IcWsCanisterMessage>,
@@ -270,14 +584,233 @@ mod tests {
                     gateway_state.insert_client_channel_and_get_new_poller_state(
                         canister_id,
                         client_key,
-                        client_channel_tx,
-                        Span::current(),
+                        ClientSender::new(
+                            client_channel_tx,
+                            Span::current(),
+                            mpsc::channel(1).0,
+                            0,
+                            None,
+                        ),
+                        Some(max_clients),
+                        DuplicateClientKeyPolicy::Reject,
                     )
                 });
                 handles.push(handle);
             }
+            let inserted_count = handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(Result::is_ok)
+                .count();
+            assert_eq!(inserted_count, max_clients);
+        });
+    }
+
+    fn insert_client(
+        gateway_state: &GatewayState,
+        client_key: ClientKey,
+        session_token: SessionToken,
+        duplicate_client_key_policy: DuplicateClientKeyPolicy,
+    ) -> (
+        Result<Option<PollerState>, ClientInsertionError>,
+        Receiver<ClientCloseReason>,
+    ) {
+        let (client_close_tx, client_close_rx) = mpsc::channel(1);
+        let result = gateway_state.insert_client_channel_and_get_new_poller_state(
+            Principal::from_text("aaaaa-aa").unwrap(),
+            client_key,
+            ClientSender::new(
+                mpsc::channel(100).0,
+                Span::current(),
+                client_close_tx,
+                session_token,
+                None,
+            ),
+            Some(1),
+            duplicate_client_key_policy,
+        );
+        (result, client_close_rx)
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_client_key() {
+        let gateway_state = GatewayState::new();
+        let client_key = ClientKey::new(Principal::anonymous(), 0);
+        let (result, mut client_close_rx) = insert_client(
+            &gateway_state,
+            client_key.clone(),
+            1,
+            DuplicateClientKeyPolicy::Reject,
+        );
+        let poller_state = result.unwrap().expect("must be some");
+
+        let (result, _) = insert_client(
+            &gateway_state,
+            client_key.clone(),
+            2,
+            DuplicateClientKeyPolicy::Reject,
+        );
+        assert_eq!(Some(ClientInsertionError::DuplicateClientKey), result.err());
+        // the existing session is kept and not requested to close
+        assert_eq!(
+            1,
+            poller_state.clients.get(&client_key).unwrap().session_token
+        );
+        assert!(client_close_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_replace_duplicate_client_key() {
+        let gateway_state = GatewayState::new();
+        let client_key = ClientKey::new(Principal::anonymous(), 0);
+        let (result, mut client_close_rx) = insert_client(
+            &gateway_state,
+            client_key.clone(),
+            1,
+            DuplicateClientKeyPolicy::Replace,
+        );
+        let poller_state = result.unwrap().expect("must be some");
+
+        // the replaced client does not count towards the maximum number of clients
+        let (result, _) = insert_client(
+            &gateway_state,
+            client_key.clone(),
+            2,
+            DuplicateClientKeyPolicy::Replace,
+        );
+        assert_eq!(Ok(false), result.map(|poller_state| poller_state.is_some()));
+        assert_eq!(
+            2,
+            poller_state.clients.get(&client_key).unwrap().session_token
+        );
+        assert_eq!(Ok(ClientCloseReason::Replaced), client_close_rx.try_recv());
+    }
+
+    #[tokio::test]
+    async fn should_remove_client_only_with_its_session_token() {
+        let gateway_state = GatewayState::new();
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let client_key = ClientKey::new(Principal::anonymous(), 0);
+        insert_client(
+            &gateway_state,
+            client_key.clone(),
+            1,
+            DuplicateClientKeyPolicy::Replace,
+        )
+        .0
+        .unwrap();
+        insert_client(
+            &gateway_state,
+            client_key.clone(),
+            2,
+            DuplicateClientKeyPolicy::Replace,
+        )
+        .0
+        .unwrap();
+
+        // the replaced session cannot remove the entry of the session which replaced it
+        gateway_state.remove_client(canister_id, client_key.clone(), 1);
+        assert!(matches!(
+            gateway_state.remove_client_if_exists(canister_id, client_key.clone(), 1),
+            ClientRemovalResult::Vacant
+        ));
+        assert!(matches!(
+            gateway_state.remove_client_if_exists(canister_id, client_key.clone(), 2),
+            ClientRemovalResult::Removed(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_request_clients_to_close() {
+        let gateway_state = GatewayState::new();
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let client_key = ClientKey::new(Principal::anonymous(), 0);
+        let (result, mut client_close_rx) = insert_client(
+            &gateway_state,
+            client_key.clone(),
+            1,
+            DuplicateClientKeyPolicy::Reject,
+        );
+        result.unwrap();
+
+        assert!(!gateway_state.close_client(
+            canister_id,
+            &ClientKey::new(Principal::anonymous(), 1),
+            ClientCloseReason::Kicked
+        ));
+        assert!(gateway_state.close_client(canister_id, &client_key, ClientCloseReason::Kicked));
+        assert_eq!(Ok(ClientCloseReason::Kicked), client_close_rx.try_recv());
+
+        assert_eq!(
+            Some(1),
+            gateway_state.close_canister_clients(canister_id, ClientCloseReason::Kicked)
+        );
+        assert_eq!(Ok(ClientCloseReason::Kicked), client_close_rx.try_recv());
+        assert_eq!(
+            None,
+            gateway_state.close_canister_clients(Principal::anonymous(), ClientCloseReason::Kicked)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_pause_and_resume_poller() {
+        let gateway_state = GatewayState::new();
+        let (result, _) = insert_client(
+            &gateway_state,
+            ClientKey::new(Principal::anonymous(), 0),
+            1,
+            DuplicateClientKeyPolicy::Reject,
+        );
+        let poller_state = result.unwrap().expect("must be some");
+        assert!(!poller_state.is_paused());
+
+        assert!(poller_state.pause());
+        assert!(!poller_state.pause());
+        assert!(poller_state.is_paused());
+
+        let resumed = poller_state.resumed.notified();
+        assert!(poller_state.resume());
+        assert!(!poller_state.resume());
+        assert!(!poller_state.is_paused());
+        // the poller waiting for the resumption is woken up
+        resumed.await;
+    }
+
+    #[tokio::test]
+    async fn should_insert_new_client_channels_and_get_new_poller_state_once() {
+        let clients_count = 1000;
+        let gateway_state = GatewayState::new();
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        thread::scope(|s| {
+            let mut handles = Vec::new();
+            for i in 0..clients_count {
+                let client_key = ClientKey::new(Principal::anonymous(), i);
+                let handle = s.spawn(|| {
+                    let (client_channel_tx, _): (
+                        Sender<IcWsCanisterMessage>,
+                        Receiver<IcWsCanisterMessage>,
+                    ) = mpsc::channel(100);
+
+                    gateway_state
+                        .insert_client_channel_and_get_new_poller_state(
+                            canister_id,
+                            client_key,
+                            ClientSender::new(
+                                client_channel_tx,
+                                Span::current(),
+                                mpsc::channel(1).0,
+                                0,
+                                None,
+                            ),
+                            None,
+                            DuplicateClientKeyPolicy::Reject,
+                        )
+                        .expect("no limit on the number of clients")
+                });
+                handles.push(handle);
+            }
             let mut count = 0;
-            let mut poller_state: Option<Arc<DashMap<ClientKey, ClientSender>>> = None;
+            let mut poller_state: Option<PollerState> = None;
             for h in handles.into_iter() {
                 if let Some(state) = h.join().unwrap() {
                     poller_state = Some(state.clone());
@@ -286,7 +819,7 @@ mod tests {
             }
             assert_eq!(count, 1);
             assert_eq!(
-                poller_state.expect("must be some").len(),
+                poller_state.expect("must be some").clients.len(),
                 clients_count as usize
             );
         });
@@ -308,12 +841,21 @@ mod tests {
                     ) = mpsc::channel(100);
 
                     let start = Instant::now();
-                    gateway_state.insert_client_channel_and_get_new_poller_state(
-                        canister_id,
-                        client_key,
-                        client_channel_tx,
-                        Span::current(),
-                    );
+                    gateway_state
+                        .insert_client_channel_and_get_new_poller_state(
+                            canister_id,
+                            client_key,
+                            ClientSender::new(
+                                client_channel_tx,
+                                Span::current(),
+                                mpsc::channel(1).0,
+                                0,
+                                None,
+                            ),
+                            None,
+                            DuplicateClientKeyPolicy::Reject,
+                        )
+                        .expect("no limit on the number of clients");
                     Instant::now() - start
                 });
                 handles.push(handle);
@@ -346,12 +888,21 @@ mod tests {
             ) = mpsc::channel(100);
 
             let start = Instant::now();
-            gateway_state.insert_client_channel_and_get_new_poller_state(
-                canister_id,
-                client_key,
-                client_channel_tx,
-                Span::current(),
-            );
+            gateway_state
+                .insert_client_channel_and_get_new_poller_state(
+                    canister_id,
+                    client_key,
+                    ClientSender::new(
+                        client_channel_tx,
+                        Span::current(),
+                        mpsc::channel(1).0,
+                        0,
+                        None,
+                    ),
+                    None,
+                    DuplicateClientKeyPolicy::Reject,
+                )
+                .expect("no limit on the number of clients");
             tot += Instant::now() - start;
         }
         let average_idle = tot / iterations as u32;
@@ -368,19 +919,29 @@ mod tests {
         let start = Instant::now();
         let mut tot = Duration::from_secs(0);
         for i in 0..iterations {
-            let client_key = ClientKey::new(Principal::anonymous(), i);
+            // the clients inserted while idle are still connected, so these must use different keys
+            let client_key = ClientKey::new(Principal::anonymous(), iterations + i);
             let (client_channel_tx, _): (
                 Sender<IcWsCanisterMessage>,
                 Receiver<IcWsCanisterMessage>,
             ) = mpsc::channel(100);
 
             let start = Instant::now();
-            gateway_state.insert_client_channel_and_get_new_poller_state(
-                canister_id,
-                client_key,
-                client_channel_tx,
-                Span::current(),
-            );
+            gateway_state
+                .insert_client_channel_and_get_new_poller_state(
+                    canister_id,
+                    client_key,
+                    ClientSender::new(
+                        client_channel_tx,
+                        Span::current(),
+                        mpsc::channel(1).0,
+                        0,
+                        None,
+                    ),
+                    None,
+                    DuplicateClientKeyPolicy::Reject,
+                )
+                .expect("no limit on the number of clients");
             tot += Instant::now() - start;
         }
         let average_busy = tot / iterations as u32;
@@ -430,12 +991,21 @@ mod tests {
                         Receiver<IcWsCanisterMessage>,
                     ) = mpsc::channel(100);
 
-                    gateway_state.insert_client_channel_and_get_new_poller_state(
-                        canister_id,
-                        client_key,
-                        client_channel_tx,
-                        Span::current(),
-                    );
+                    gateway_state
+                        .insert_client_channel_and_get_new_poller_state(
+                            canister_id,
+                            client_key,
+                            ClientSender::new(
+                                client_channel_tx,
+                                Span::current(),
+                                mpsc::channel(1).0,
+                                0,
+                                None,
+                            ),
+                            None,
+                            DuplicateClientKeyPolicy::Reject,
+                        )
+                        .expect("no limit on the number of clients");
                 }
             });
         }
@@ -485,12 +1055,21 @@ mod tests {
                         Receiver<IcWsCanisterMessage>,
                     ) = mpsc::channel(100);
 
-                    gateway_state.insert_client_channel_and_get_new_poller_state(
-                        canister_id,
-                        client_key,
-                        client_channel_tx,
-                        Span::current(),
-                    );
+                    gateway_state
+                        .insert_client_channel_and_get_new_poller_state(
+                            canister_id,
+                            client_key,
+                            ClientSender::new(
+                                client_channel_tx,
+                                Span::current(),
+                                mpsc::channel(1).0,
+                                0,
+                                None,
+                            ),
+                            None,
+                            DuplicateClientKeyPolicy::Reject,
+                        )
+                        .expect("no limit on the number of clients");
                     // simulates 100 clients connecting each second
                     thread::sleep(Duration::from_millis(10));
                 }
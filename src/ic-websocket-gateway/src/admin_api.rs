@@ -0,0 +1,360 @@
+use candid::Principal;
+use canister_utils::ClientKey;
+use gateway_state::{CanisterPrincipal, ClientCloseReason, GatewayState, PollerState};
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use metrics::counter;
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    fs,
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+/// Error returned to the requests which cannot be served by the admin API
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdminApiError {
+    /// The request does not present the token of the admin API
+    Unauthorized,
+    /// The route, the canister or the client does not exist
+    NotFound(String),
+    /// A parameter of the route is not valid
+    BadRequest(String),
+}
+
+impl AdminApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AdminApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            AdminApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AdminApiError::Unauthorized => String::from("Missing or invalid bearer token"),
+            AdminApiError::NotFound(message) | AdminApiError::BadRequest(message) => {
+                message.clone()
+            },
+        }
+    }
+}
+
+/// Canister being polled, as returned by the admin API
+#[derive(Debug, Serialize)]
+struct CanisterView {
+    canister_id: String,
+    clients_count: usize,
+    poller: PollerView,
+    /// Only returned when a single canister is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clients: Option<Vec<ClientView>>,
+}
+
+/// Status of the poller of a canister, as returned by the admin API
+#[derive(Debug, Serialize)]
+struct PollerView {
+    next_message_nonce: u64,
+    /// Milliseconds since the Unix epoch
+    last_poll_time_ms: Option<u64>,
+    error_streak: u32,
+    last_error: Option<String>,
+    is_paused: bool,
+}
+
+/// Client connected to a canister, as returned by the admin API
+#[derive(Debug, Serialize)]
+struct ClientView {
+    client_key: String,
+    client_principal: String,
+    client_nonce: u64,
+    session_token: u64,
+    client_ip: Option<String>,
+    /// Milliseconds since the Unix epoch
+    connected_at_ms: u64,
+}
+
+/// HTTP API used by the operators of the gateway to inspect the canisters and clients and to control them
+/// The requests must present the token of the API in the 'Authorization: Bearer' header
+pub struct AdminApi {
+    /// State of the gateway
+    gateway_state: GatewayState,
+    /// Token the requests must present
+    token: String,
+}
+
+impl AdminApi {
+    pub fn new(gateway_state: GatewayState, token: String) -> Self {
+        Self {
+            gateway_state,
+            token,
+        }
+    }
+
+    /// Serves the request and returns the JSON response
+    pub fn handle_request(&self, request: &Request<Body>) -> Response<Body> {
+        let response = if self.is_authorized(request) {
+            self.route_request(request)
+        } else {
+            Err(AdminApiError::Unauthorized)
+        };
+        response.unwrap_or_else(|e| {
+            let mut response = json_response(
+                e.status_code(),
+                &serde_json::json!({ "error": e.message() }),
+            );
+            if e == AdminApiError::Unauthorized {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            response
+        })
+    }
+
+    fn route_request(&self, request: &Request<Body>) -> Result<Response<Body>, AdminApiError> {
+        let segments: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+        match (request.method(), segments.as_slice()) {
+            (&Method::GET, ["canisters"]) => Ok(self.list_canisters()),
+            (&Method::GET, ["canisters", canister_id]) => self.get_canister(canister_id),
+            (&Method::POST, ["canisters", canister_id, "kick"]) => {
+                self.kick_canister_clients(canister_id)
+            },
+            (&Method::POST, ["canisters", canister_id, "clients", client_key, "kick"]) => {
+                self.kick_client(canister_id, client_key)
+            },
+            (&Method::POST, ["canisters", canister_id, "pause"]) => {
+                self.set_poller_paused(canister_id, true)
+            },
+            (&Method::POST, ["canisters", canister_id, "resume"]) => {
+                self.set_poller_paused(canister_id, false)
+            },
+            _ => Err(AdminApiError::NotFound(format!(
+                "No route for {} {}",
+                request.method(),
+                request.uri().path()
+            ))),
+        }
+    }
+
+    /// Returns true if the request presents the token of the API
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        let Some(token) = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        // compared in constant time so that the token cannot be guessed from the response times
+        constant_time_eq(token.as_bytes(), self.token.as_bytes())
+    }
+
+    fn list_canisters(&self) -> Response<Body> {
+        let canisters: Vec<CanisterView> = self
+            .gateway_state
+            .get_poller_states()
+            .into_iter()
+            .map(|(canister_id, poller_state)| get_canister_view(canister_id, &poller_state, false))
+            .collect();
+        json_response(StatusCode::OK, &canisters)
+    }
+
+    fn get_canister(&self, canister_id: &str) -> Result<Response<Body>, AdminApiError> {
+        let (canister_id, poller_state) = self.get_poller_state(canister_id)?;
+        Ok(json_response(
+            StatusCode::OK,
+            &get_canister_view(canister_id, &poller_state, true),
+        ))
+    }
+
+    fn kick_canister_clients(&self, canister_id: &str) -> Result<Response<Body>, AdminApiError> {
+        let canister_id = parse_canister_id(canister_id)?;
+        let kicked_clients_count = self
+            .gateway_state
+            .close_canister_clients(canister_id, ClientCloseReason::Kicked)
+            .ok_or_else(|| canister_not_found(canister_id))?;
+        info!(
+            "Kicked {} clients of canister {}",
+            kicked_clients_count, canister_id
+        );
+        counter!("kicked_clients").increment(kicked_clients_count as u64);
+        Ok(json_response(
+            StatusCode::OK,
+            &serde_json::json!({ "kicked_clients": kicked_clients_count }),
+        ))
+    }
+
+    fn kick_client(
+        &self,
+        canister_id: &str,
+        client_key: &str,
+    ) -> Result<Response<Body>, AdminApiError> {
+        let canister_id = parse_canister_id(canister_id)?;
+        let client_key = parse_client_key(client_key)?;
+        if !self
+            .gateway_state
+            .close_client(canister_id, &client_key, ClientCloseReason::Kicked)
+        {
+            return Err(AdminApiError::NotFound(format!(
+                "Client {} is not connected to canister {}",
+                client_key, canister_id
+            )));
+        }
+        info!("Kicked client {} of canister {}", client_key, canister_id);
+        counter!("kicked_clients").increment(1);
+        Ok(json_response(
+            StatusCode::OK,
+            &serde_json::json!({ "kicked_clients": 1 }),
+        ))
+    }
+
+    fn set_poller_paused(
+        &self,
+        canister_id: &str,
+        is_paused: bool,
+    ) -> Result<Response<Body>, AdminApiError> {
+        let (canister_id, poller_state) = self.get_poller_state(canister_id)?;
+        if is_paused && poller_state.pause() {
+            info!("Paused poller for canister {}", canister_id);
+        } else if !is_paused && poller_state.resume() {
+            info!("Resumed poller for canister {}", canister_id);
+        }
+        Ok(json_response(
+            StatusCode::OK,
+            &serde_json::json!({ "is_paused": is_paused }),
+        ))
+    }
+
+    fn get_poller_state(
+        &self,
+        canister_id: &str,
+    ) -> Result<(CanisterPrincipal, PollerState), AdminApiError> {
+        let canister_id = parse_canister_id(canister_id)?;
+        let poller_state = self
+            .gateway_state
+            .get_poller_state(canister_id)
+            .ok_or_else(|| canister_not_found(canister_id))?;
+        Ok((canister_id, poller_state))
+    }
+}
+
+fn get_canister_view(
+    canister_id: CanisterPrincipal,
+    poller_state: &PollerState,
+    with_clients: bool,
+) -> CanisterView {
+    let status = poller_state.get_status();
+    let clients = with_clients.then(|| {
+        let mut clients: Vec<ClientView> = poller_state
+            .clients
+            .iter()
+            .map(|client| ClientView {
+                client_key: client.key().to_string(),
+                client_principal: client.key().client_principal.to_string(),
+                client_nonce: client.key().client_nonce,
+                session_token: client.session_token,
+                client_ip: client.client_ip.map(|client_ip| client_ip.to_string()),
+                connected_at_ms: get_unix_time_ms(client.connected_at),
+            })
+            .collect();
+        clients.sort_by(|a, b| a.client_key.cmp(&b.client_key));
+        clients
+    });
+    CanisterView {
+        canister_id: canister_id.to_string(),
+        clients_count: poller_state.clients.len(),
+        poller: PollerView {
+            next_message_nonce: status.next_message_nonce,
+            last_poll_time_ms: status.last_poll_time.map(get_unix_time_ms),
+            error_streak: status.error_streak,
+            last_error: status.last_error,
+            is_paused: poller_state.is_paused(),
+        },
+        clients,
+    }
+}
+
+fn parse_canister_id(canister_id: &str) -> Result<CanisterPrincipal, AdminApiError> {
+    Principal::from_text(canister_id).map_err(|e| {
+        AdminApiError::BadRequest(format!("Invalid canister principal {}: {}", canister_id, e))
+    })
+}
+
+/// Parses a client key formatted as '<client principal>_<client nonce>'
+fn parse_client_key(client_key: &str) -> Result<ClientKey, AdminApiError> {
+    let invalid_client_key =
+        || AdminApiError::BadRequest(format!("Invalid client key {}", client_key));
+    let (client_principal, client_nonce) =
+        client_key.rsplit_once('_').ok_or_else(invalid_client_key)?;
+    let client_principal =
+        Principal::from_text(client_principal).map_err(|_| invalid_client_key())?;
+    let client_nonce = client_nonce.parse().map_err(|_| invalid_client_key())?;
+    Ok(ClientKey::new(client_principal, client_nonce))
+}
+
+fn canister_not_found(canister_id: CanisterPrincipal) -> AdminApiError {
+    AdminApiError::NotFound(format!("Canister {} is not being polled", canister_id))
+}
+
+fn get_unix_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn json_response<T: Serialize>(status_code: StatusCode, body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("admin API responses should always be serializable");
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status_code;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Loads the token of the admin API from the file, ignoring the surrounding whitespaces
+pub fn load_admin_api_token(path: &str) -> Result<String, String> {
+    let token = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read admin API token file {}: {}", path, e))?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(format!("Admin API token file {} is empty", path));
+    }
+    Ok(token.to_string())
+}
+
+/// Binds the admin API to the address and serves its requests in a separate task
+/// The gateway fails immediately if the address is not available
+pub fn start_admin_api(admin_api: Arc<AdminApi>, address: SocketAddr) -> Result<(), String> {
+    let server = Server::try_bind(&address)
+        .map_err(|e| format!("Could not bind admin API to {}: {}", address, e))?;
+    let make_service = make_service_fn(move |_| {
+        let admin_api = Arc::clone(&admin_api);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let admin_api = Arc::clone(&admin_api);
+                async move { Ok::<_, Infallible>(admin_api.handle_request(&request)) }
+            }))
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = server.serve(make_service).await {
+            error!("Admin API stopped: {}", e);
+        }
+    });
+    info!("Admin API listening on {}", address);
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use candid::Principal;
+use gateway_state::CanisterPrincipal;
+use std::{collections::HashSet, fs, sync::RwLock};
+use tracing::info;
+
+/// Lists of the canisters which the clients are allowed or denied to connect to
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CanisterAccessList {
+    /// If set, only the canisters in the allowlist are served
+    allowlist: Option<HashSet<CanisterPrincipal>>,
+    /// The canisters in the denylist are never served, even if they are in the allowlist
+    denylist: HashSet<CanisterPrincipal>,
+}
+
+impl CanisterAccessList {
+    pub fn new(
+        allowlist: Option<HashSet<CanisterPrincipal>>,
+        denylist: HashSet<CanisterPrincipal>,
+    ) -> Self {
+        Self {
+            allowlist,
+            denylist,
+        }
+    }
+
+    /// Returns true if the clients are allowed to connect to the canister
+    pub fn is_allowed(&self, canister_id: &CanisterPrincipal) -> bool {
+        if self.denylist.contains(canister_id) {
+            return false;
+        }
+        match &self.allowlist {
+            Some(allowlist) => allowlist.contains(canister_id),
+            None => true,
+        }
+    }
+}
+
+/// Access control of the canisters, whose lists can be reloaded from their files while the gateway is running
+#[derive(Debug)]
+pub struct CanisterAccessControl {
+    /// Path of the file containing the allowlist
+    allowlist_path: Option<String>,
+    /// Path of the file containing the denylist
+    denylist_path: Option<String>,
+    /// Lists currently enforced
+    // the lock is never held across an '.await', therefore a sync lock can be used
+    access_list: RwLock<CanisterAccessList>,
+}
+
+impl CanisterAccessControl {
+    /// Loads the lists from their files
+    /// If no path is specified for a list, the list is not enforced
+    pub fn load(
+        allowlist_path: Option<String>,
+        denylist_path: Option<String>,
+    ) -> Result<Self, String> {
+        let access_list = load_access_list(allowlist_path.as_deref(), denylist_path.as_deref())?;
+        Ok(Self {
+            allowlist_path,
+            denylist_path,
+            access_list: RwLock::new(access_list),
+        })
+    }
+
+    /// Reloads the lists from their files
+    /// If any of the files cannot be loaded, the current lists are kept
+    pub fn reload(&self) -> Result<(), String> {
+        let access_list = load_access_list(
+            self.allowlist_path.as_deref(),
+            self.denylist_path.as_deref(),
+        )?;
+        *self
+            .access_list
+            .write()
+            .expect("lock should not be poisoned") = access_list;
+        info!("Reloaded canister allowlist and denylist");
+        Ok(())
+    }
+
+    /// Returns true if the clients are allowed to connect to the canister
+    pub fn is_allowed(&self, canister_id: &CanisterPrincipal) -> bool {
+        self.access_list
+            .read()
+            .expect("lock should not be poisoned")
+            .is_allowed(canister_id)
+    }
+}
+
+fn load_access_list(
+    allowlist_path: Option<&str>,
+    denylist_path: Option<&str>,
+) -> Result<CanisterAccessList, String> {
+    let allowlist = allowlist_path.map(load_canister_list).transpose()?;
+    let denylist = denylist_path
+        .map(load_canister_list)
+        .transpose()?
+        .unwrap_or_default();
+    Ok(CanisterAccessList::new(allowlist, denylist))
+}
+
+fn load_canister_list(path: &str) -> Result<HashSet<CanisterPrincipal>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read canister list {}: {}", path, e))?;
+    parse_canister_list(&content)
+}
+
+/// Parses a list containing one canister principal per line
+/// Empty lines and lines starting with '#' are ignored
+pub(crate) fn parse_canister_list(content: &str) -> Result<HashSet<CanisterPrincipal>, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            Principal::from_text(line)
+                .map_err(|e| format!("Invalid canister principal {}: {}", line, e))
+        })
+        .collect()
+}
@@ -0,0 +1,157 @@
+use candid::Principal;
+use gateway_state::CanisterPrincipal;
+use serde::Deserialize;
+use std::{collections::HashMap, fs};
+
+/// Policy applied to the poller and to the clients of a canister
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanisterPolicy {
+    /// Minimum polling interval in milliseconds
+    pub polling_interval_ms: u64,
+    /// Time in milliseconds after which a poll of the canister is considered timed out
+    pub polling_timeout_ms: u64,
+    /// Maximum number of clients connected to the canister, unlimited if None
+    pub max_clients: Option<usize>,
+    /// Maximum size in bytes of a message sent by a client, unlimited if None
+    pub max_client_message_size: Option<usize>,
+    /// Maximum number of canister messages per client waiting to be sent to the client
+    pub client_queue_capacity: usize,
+}
+
+/// Fields of a policy specified in the policies file, the missing ones are inherited from the default policy
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CanisterPolicyOverride {
+    polling_interval_ms: Option<u64>,
+    polling_timeout_ms: Option<u64>,
+    max_clients: Option<usize>,
+    max_client_message_size: Option<usize>,
+    client_queue_capacity: Option<usize>,
+}
+
+/// Content of the policies file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CanisterPoliciesFile {
+    /// Overrides the default policy specified via the command line arguments
+    #[serde(default)]
+    default: CanisterPolicyOverride,
+    /// Overrides the default policy for specific canisters, keyed by canister principal
+    #[serde(default)]
+    canisters: HashMap<String, CanisterPolicyOverride>,
+}
+
+impl CanisterPolicy {
+    fn check(&self, policy_name: &str) -> Result<(), String> {
+        // the queue of the clients could not hold any message
+        if self.client_queue_capacity == 0 {
+            return Err(format!(
+                "The client queue capacity of {} must be greater than 0",
+                policy_name
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_adaptive_polling(
+        &self,
+        max_polling_interval_ms: u64,
+        policy_name: &str,
+    ) -> Result<(), String> {
+        // a polling interval of 0 would never grow, whatever the growth factor
+        if self.polling_interval_ms == 0 {
+            return Err(format!(
+                "The polling interval of {} must be greater than 0 with adaptive polling",
+                policy_name
+            ));
+        }
+        if self.polling_interval_ms > max_polling_interval_ms {
+            return Err(format!(
+                "The polling interval of {} must not exceed the maximum polling interval",
+                policy_name
+            ));
+        }
+        Ok(())
+    }
+
+    fn with_override(&self, policy_override: &CanisterPolicyOverride) -> Self {
+        Self {
+            polling_interval_ms: policy_override
+                .polling_interval_ms
+                .unwrap_or(self.polling_interval_ms),
+            polling_timeout_ms: policy_override
+                .polling_timeout_ms
+                .unwrap_or(self.polling_timeout_ms),
+            max_clients: policy_override.max_clients.or(self.max_clients),
+            max_client_message_size: policy_override
+                .max_client_message_size
+                .or(self.max_client_message_size),
+            client_queue_capacity: policy_override
+                .client_queue_capacity
+                .unwrap_or(self.client_queue_capacity),
+        }
+    }
+}
+
+/// Table of the policies applied to the canisters, consisting of a default policy
+/// and of the policies of the canisters which override it
+#[derive(Debug, Clone)]
+pub struct CanisterPolicies {
+    default: CanisterPolicy,
+    overrides: HashMap<CanisterPrincipal, CanisterPolicy>,
+}
+
+impl CanisterPolicies {
+    /// Creates a table in which all the canisters have the default policy
+    pub fn new(default: CanisterPolicy) -> Self {
+        Self {
+            default,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Loads the policies from a JSON file of the form:
+    /// { "default": { <fields> }, "canisters": { "<canister principal>": { <fields> } } }
+    /// The fields specified in "default" override the ones of 'default',
+    /// the fields specified for a canister override the ones of the resulting default policy
+    pub fn load(path: &str, default: CanisterPolicy) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read canister policies file {}: {}", path, e))?;
+        Self::parse(&content, default)
+    }
+
+    pub(crate) fn parse(content: &str, default: CanisterPolicy) -> Result<Self, String> {
+        let policies_file: CanisterPoliciesFile = serde_json::from_str(content)
+            .map_err(|e| format!("Could not parse canister policies: {}", e))?;
+
+        let default = default.with_override(&policies_file.default);
+        default.check("the default policy")?;
+        let mut overrides = HashMap::new();
+        for (canister_id, policy_override) in policies_file.canisters {
+            let canister_id = Principal::from_text(&canister_id)
+                .map_err(|e| format!("Invalid canister principal {}: {}", canister_id, e))?;
+            let policy = default.with_override(&policy_override);
+            policy.check(&format!("the policy of canister {}", canister_id))?;
+            overrides.insert(canister_id, policy);
+        }
+        Ok(Self { default, overrides })
+    }
+
+    /// Checks that the polling interval of every canister can grow up to the maximum polling interval
+    pub fn check_adaptive_polling(&self, max_polling_interval_ms: u64) -> Result<(), String> {
+        self.default
+            .check_adaptive_polling(max_polling_interval_ms, "the default policy")?;
+        for (canister_id, policy) in &self.overrides {
+            policy.check_adaptive_polling(
+                max_polling_interval_ms,
+                &format!("the policy of canister {}", canister_id),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the policy of the canister, which is the default one if the canister does not override it
+    pub fn get(&self, canister_id: &CanisterPrincipal) -> &CanisterPolicy {
+        self.overrides.get(canister_id).unwrap_or(&self.default)
+    }
+}
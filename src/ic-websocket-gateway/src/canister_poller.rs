@@ -1,17 +1,33 @@
-use candid::Principal;
+use crate::{
+    canister_policies::{CanisterPolicies, CanisterPolicy},
+    certification::verify_canister_output,
+    close_outbox::CloseOutbox,
+    poller_nonces::PollerNonces,
+};
+use candid::{decode_one, Principal};
 use canister_utils::{
     ws_get_messages, CanisterOutputCertifiedMessages, CanisterToClientMessage,
-    CanisterWsGetMessagesArguments, IcError, IcWsCanisterMessage,
+    CanisterWsGetMessagesArguments, ClientKey, IcError, IcWsCanisterMessage, WebsocketMessage,
 };
 use gateway_state::{
-    CanisterPrincipal, CanisterRemovalResult, ClientSender, GatewayState, PollerState,
+    CanisterPrincipal, CanisterRemovalResult, ClientCloseReason, ClientRemovalResult, ClientSender,
+    GatewayState, PollerState, SessionToken,
 };
 use ic_agent::{agent::RejectCode, Agent, AgentError};
-use std::{sync::Arc, time::Duration};
-use tokio::{sync::mpsc::Sender, time::timeout};
-use tracing::{error, span, trace, warn, Instrument, Level, Span};
-
-pub(crate) const POLLING_TIMEOUT_MS: u64 = 5_000;
+use metrics::counter;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    select,
+    sync::mpsc::{error::TrySendError, Sender},
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, span, trace, warn, Instrument, Level, Span};
 
 type PollingTimeout = Duration;
 
@@ -24,6 +40,85 @@ pub(crate) enum PollingStatus {
     MessagesPolled(CanisterOutputCertifiedMessages),
     /// Request timed out
     TimedOut,
+    /// Messages polled but discarded as their certification is not valid
+    InvalidCertification,
+}
+
+/// Policy applied by the poller to the clients which do not read the canister messages as fast as they are relayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowClientPolicy {
+    /// The client session is requested to close the connection with the client
+    Close,
+    /// The client is immediately removed from the gateway state and the canister is notified via ws_close
+    Drop,
+}
+
+impl SlowClientPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SlowClientPolicy::Close => "close",
+            SlowClientPolicy::Drop => "drop",
+        }
+    }
+}
+
+impl FromStr for SlowClientPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "close" => Ok(SlowClientPolicy::Close),
+            "drop" => Ok(SlowClientPolicy::Drop),
+            _ => Err(format!("Invalid slow client policy: {}", s)),
+        }
+    }
+}
+
+/// Configuration of the canister pollers
+#[derive(Debug, Clone)]
+pub struct PollerConfig {
+    /// Maximum polling interval in milliseconds, reached while the canister stays idle
+    /// The minimum polling interval, used while the canister is active, is specified by the policy of each canister
+    /// If None, the polling interval is not adapted to the activity of the canister
+    pub max_polling_interval_ms: Option<u64>,
+    /// Factor by which the polling interval grows after each polling iteration which returns no messages
+    pub polling_interval_growth_factor: f64,
+    /// Policy applied to the clients whose queue is full
+    pub slow_client_policy: SlowClientPolicy,
+    /// Maximum number of consecutive restarts of a failed poller before its clients are disconnected
+    pub max_restarts: u32,
+    /// Time to wait in milliseconds before the first restart of a failed poller, doubled at each consecutive restart
+    pub restart_backoff_ms: u64,
+    /// Maximum time to wait in milliseconds before restarting a failed poller
+    pub max_restart_backoff_ms: u64,
+    /// Whether the certificates of the polled messages are verified before relaying the messages to the clients
+    pub verify_certificates: bool,
+    /// Nonces from which the pollers resume polling their canisters after the gateway restarts, not persisted if None
+    pub poller_nonces: Option<Arc<PollerNonces>>,
+    /// Outbox of the ws_close calls for the clients dropped by the pollers
+    pub close_outbox: Arc<CloseOutbox>,
+}
+
+/// Result of relaying a canister message to a client session
+#[derive(Debug, PartialEq, Eq)]
+enum RelayResult {
+    /// The message has been queued in the client's channel
+    Relayed,
+    /// The client's queue is full
+    QueueFull,
+    /// The client session has already terminated
+    SessionTerminated,
+}
+
+/// Result of checking the sequence number of a canister message against the one expected by the client session
+#[derive(Debug, PartialEq, Eq)]
+enum SequenceNumCheck {
+    /// The message follows the last message relayed to the client
+    Consecutive,
+    /// The message has already been relayed to the client
+    Duplicate,
+    /// Some of the messages preceding the message have not been relayed to the client
+    Gap,
 }
 
 /// Poller which periodically queries a canister for new messages and relays them to the client
@@ -39,11 +134,21 @@ pub struct CanisterPoller {
     /// Nonce specified by the gateway during the query call to ws_get_messages,
     /// used by the CDK to determine which messages to respond with
     next_message_nonce: u64,
+    /// Session token and sequence number of the next message expected by each client which received messages from the poller
+    /// The canister numbers the messages of each client consecutively, therefore the clients which missed some messages
+    /// can be detected even though the nonces of the canister are not consecutive
+    next_sequence_nums: HashMap<ClientKey, (SessionToken, u64)>,
     /// The number of polling iterations since the poller started
     /// reference of the PollerEvents
     polling_iteration: u64,
-    /// Polling interval in milliseconds
+    /// Current polling interval in milliseconds, adapted to the activity of the canister
     polling_interval_ms: u64,
+    /// Configuration of the poller
+    config: PollerConfig,
+    /// Policy of the canister
+    policy: CanisterPolicy,
+    /// Token cancelled when the gateway is shutting down
+    shutdown_token: CancellationToken,
 }
 
 impl CanisterPoller {
@@ -52,44 +157,156 @@ impl CanisterPoller {
         canister_id: Principal,
         poller_state: PollerState,
         gateway_state: GatewayState,
-        polling_interval_ms: u64,
+        config: PollerConfig,
+        canister_policies: &CanisterPolicies,
+        shutdown_token: CancellationToken,
     ) -> Self {
+        let policy = canister_policies.get(&canister_id).clone();
+        // the poller resumes from the nonce stored before the gateway restarted, if any
+        let next_message_nonce = config
+            .poller_nonces
+            .as_ref()
+            .and_then(|poller_nonces| poller_nonces.get(&canister_id))
+            .unwrap_or(0);
+        if next_message_nonce != 0 {
+            info!(
+                "Resuming polling canister {} from nonce {}",
+                canister_id, next_message_nonce
+            );
+        }
+        poller_state.update_status(|status| status.next_message_nonce = next_message_nonce);
         Self {
             agent,
             canister_id,
             poller_state,
             gateway_state,
-            next_message_nonce: 0,
+            next_message_nonce,
+            next_sequence_nums: HashMap::new(),
             polling_iteration: 0,
-            polling_interval_ms,
+            polling_interval_ms: policy.polling_interval_ms,
+            config,
+            policy,
+            shutdown_token,
+        }
+    }
+
+    /// Supervises the poller by restarting it with an exponential backoff whenever it fails
+    /// As the poller state is kept, the clients stay connected and the restarted poller resumes from the last polled nonce
+    /// Only once 'max_restarts' consecutive restarts fail, the poller state is removed and the clients are disconnected
+    pub async fn run_supervised_polling(&mut self) -> Result<(), String> {
+        let mut consecutive_failures: u32 = 0;
+        loop {
+            let polling_iteration_before_start = self.polling_iteration;
+            let polling_error = match self.run_polling().await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            // the restart is considered successful if the poller completed at least one polling iteration
+            // in such a case, the budget of restarts is replenished
+            if self.polling_iteration > polling_iteration_before_start {
+                consecutive_failures = 0;
+            }
+            consecutive_failures += 1;
+
+            if consecutive_failures > self.config.max_restarts {
+                error!(
+                    "Poller failed {} consecutive times. Disconnecting its clients",
+                    consecutive_failures
+                );
+                // upon exhausting the restarts, remove the poller state from the gateway state and immediately terminate the poller
+                // client sessions will detect that the poller side of the channel has been dropped and therefore will also terminate
+                // as the poller state contains all the state of the clients sessions opened to the failed poller, removing the poller state
+                // will also remove all the corresponding clients' states
+                // therefore, there is no need to wait for the clients to remove their state before terminating the poller
+                self.gateway_state.remove_failed_canister(self.canister_id);
+                self.remove_stored_nonce();
+                // TODO: notify the canister that it cannot be polled anymore
+                return Err(polling_error);
+            }
+
+            if self.poller_should_terminate() {
+                // all the clients disconnected while the poller was failing, there is no need to restart it
+                return Ok(());
+            }
+
+            let restart_backoff = self.compute_restart_backoff(consecutive_failures);
+            warn!(
+                "Restarting poller for canister {} in {:?} ({}/{})",
+                self.canister_id, restart_backoff, consecutive_failures, self.config.max_restarts
+            );
+            counter!("poller_restarts").increment(1);
+            select! {
+                _ = tokio::time::sleep(restart_backoff) => (),
+                _ = self.shutdown_token.cancelled() => {
+                    info!("Gateway shutting down. Stopped restarting poller for canister: {}", self.canister_id);
+                    self.remove_stored_nonce();
+                    return Ok(());
+                }
+            }
         }
     }
 
-    /// Periodically polls the canister for updates to be relayed to clients
+    /// Computes the time to wait before restarting the poller after 'consecutive_failures' failures
+    fn compute_restart_backoff(&self, consecutive_failures: u32) -> Duration {
+        let restart_backoff_ms = self
+            .config
+            .restart_backoff_ms
+            .saturating_mul(2u64.saturating_pow(consecutive_failures - 1))
+            .min(self.config.max_restart_backoff_ms);
+        Duration::from_millis(restart_backoff_ms)
+    }
+
+    /// Periodically polls the canister for updates to be relayed to clients, until the gateway is shutting down
     pub async fn run_polling(&mut self) -> Result<(), String> {
         // keeps track of the previous polling iteration span in order to create a follow from relationship
         // initially set to None as the first iteration will not have a previous span
         let mut previous_polling_iteration_span: Option<Span> = None;
+        let shutdown_token = self.shutdown_token.clone();
+        let poller_state = Arc::clone(&self.poller_state);
         loop {
+            // created before checking whether the poller is paused so that a resumption in the meantime is not missed
+            let resumed = poller_state.resumed.notified();
+            if poller_state.is_paused() {
+                // the clients stay connected while the poller is paused
+                // once resumed, the canister is polled from the nonce at which the poller was paused
+                trace!("Poller is paused");
+                select! {
+                    _ = resumed => info!("Resumed polling canister: {}", self.canister_id),
+                    // the poller keeps checking whether all the clients disconnected in the meantime
+                    _ = tokio::time::sleep(Duration::from_millis(self.polling_interval_ms)) => (),
+                    _ = shutdown_token.cancelled() => {
+                        info!("Gateway shutting down. Stopped polling canister: {}", self.canister_id);
+                        self.remove_stored_nonce();
+                        return Ok(());
+                    }
+                }
+                if self.poller_should_terminate() {
+                    return Ok(());
+                }
+                continue;
+            }
+
             let polling_iteration_span = span!(Level::TRACE, "Polling Iteration", canister_id = %self.canister_id, polling_iteration = self.polling_iteration, cargo_version = env!("CARGO_PKG_VERSION"));
             if let Some(previous_polling_iteration_span) = previous_polling_iteration_span {
                 // create a follow from relationship between the current and previous polling iteration
                 // this enables to crawl polling iterations in reverse chronological order
                 polling_iteration_span.follows_from(previous_polling_iteration_span.id());
             }
-            if let Err(e) = self
-                .poll_and_relay()
-                .instrument(polling_iteration_span.clone())
-                .await
-            {
+            let polling_result = select! {
+                polling_result = self.poll_and_relay().instrument(polling_iteration_span.clone()) => polling_result,
+                _ = shutdown_token.cancelled() => {
+                    // the client sessions take care of closing the connections with their clients
+                    // there is no need to relay messages to clients which are about to be disconnected
+                    info!("Gateway shutting down. Stopped polling canister: {}", self.canister_id);
+                    self.remove_stored_nonce();
+                    return Ok(());
+                }
+            };
+            if let Err(e) = polling_result {
                 error!("Error polling canister: {:?}", e);
-                // upon poller error, remove the poller state from the gateway state and immediately terminate the poller
-                // client sessions will detect that the poller side of the channel has been dropped and therefore will also terminate
-                // as the poller state contains all the state of the clients sessions opened to the failed poller, removing the poller state
-                // will also remove all the corresponding clients' states
-                // therefore, there is no need to wait for the clients to remove their state before terminating the poller
-                self.gateway_state.remove_failed_canister(self.canister_id);
-                // TODO: notify the canister that it cannot be polled anymore
+                self.record_polling_result(Some(e.clone()));
+                // the poller state is left untouched so that the supervisor can restart the poller
+                // without disconnecting the clients
                 return Err(e);
             }
 
@@ -109,8 +326,10 @@ impl CanisterPoller {
     pub async fn poll_and_relay(&mut self) -> Result<(), String> {
         let start_polling_instant = tokio::time::Instant::now();
 
-        match self.poll_canister().await? {
-            PollingStatus::MessagesPolled(certified_canister_output) => {
+        let is_canister_idle = match self.poll_canister().await? {
+            PollingStatus::MessagesPolled(mut certified_canister_output) => {
+                // the canister is active, poll it as frequently as possible
+                self.reset_polling_interval();
                 let relay_messages_span =
                     span!(parent: &Span::current(), Level::TRACE, "Relay Canister Messages");
                 let end_of_queue_reached = {
@@ -122,46 +341,100 @@ impl CanisterPoller {
                         None => true,
                     }
                 };
-                self.update_nonce(&certified_canister_output)?;
+                let relayable_messages_count = self.update_nonce(&certified_canister_output)?;
+                let nonce_went_backward =
+                    relayable_messages_count < certified_canister_output.messages.len();
+                certified_canister_output
+                    .messages
+                    .truncate(relayable_messages_count);
                 // relaying of messages cannot be done in a separate task for each polling iteration
                 // as they might interleave and break the correct ordering of messages
                 // TODO: create a separate task dedicated to relaying messages which receives the messages from the poller via a queue
                 //       and relays them in FIFO order
-                self.relay_messages(certified_canister_output)
-                    .instrument(relay_messages_span)
-                    .await;
+                // relaying never awaits a client's channel, so that a client which does not read its messages
+                // does not delay the messages of all the other clients of the canister
+                relay_messages_span.in_scope(|| self.relay_messages(certified_canister_output));
+                if let Some(poller_nonces) = &self.config.poller_nonces {
+                    // the nonce is written to disk in batches with the nonces of the other pollers
+                    poller_nonces.set(self.canister_id, self.next_message_nonce);
+                }
+                self.poller_state
+                    .update_status(|status| status.next_message_nonce = self.next_message_nonce);
+                if nonce_went_backward {
+                    // the messages following the backward jump are polled again from the resynchronized nonce
+                    warn!("Canister nonces went backward. Polling immediately");
+                    return Ok(());
+                }
                 if !end_of_queue_reached {
                     // if the queue is not fully drained, return immediately so that the next polling iteration can be started
                     warn!("Canister queue is not fully drained. Polling immediately");
                     return Ok(());
                 }
+                false
             },
             PollingStatus::TimedOut => {
                 // if the poller timed out, it already waited way too long... return immediately so that the next polling iteration can be started
                 warn!("Poller timed out. Polling immediately");
                 return Ok(());
             },
-            PollingStatus::NoMessagesPolled => (),
-        }
+            PollingStatus::NoMessagesPolled => true,
+            // the same messages are polled again after the polling interval, possibly from another replica
+            PollingStatus::InvalidCertification => false,
+        };
 
         // compute the amout of time to sleep for before polling again
         let effective_polling_interval =
             self.compute_effective_polling_interval(start_polling_instant);
+        if is_canister_idle {
+            // the canister is idle, poll it less frequently starting from the next polling iteration
+            self.increase_polling_interval();
+        }
         // if no messages are returned or if the queue is fully drained, sleep for 'effective_polling_interval' before polling again
-        tokio::time::sleep(effective_polling_interval).await;
+        // unless a client sends a message to the canister in the meantime
+        let client_activity = Arc::clone(&self.poller_state.client_activity);
+        select! {
+            _ = tokio::time::sleep(effective_polling_interval) => (),
+            _ = client_activity.notified() => {
+                // the response to the client message is likely to be polled soon
+                trace!("Client sent a message. Resetting polling interval");
+                self.reset_polling_interval();
+                // do not poll before the minimum polling interval elapsed so that very active clients do not make the poller spin
+                tokio::time::sleep_until(
+                    start_polling_instant + Duration::from_millis(self.policy.polling_interval_ms),
+                )
+                .await;
+            }
+        }
         Ok(())
     }
 
+    /// Increases the polling interval by the growth factor, up to the maximum polling interval
+    fn increase_polling_interval(&mut self) {
+        if let Some(max_polling_interval_ms) = self.config.max_polling_interval_ms {
+            let increased_polling_interval_ms = (self.polling_interval_ms as f64
+                * self.config.polling_interval_growth_factor)
+                as u64;
+            self.polling_interval_ms = increased_polling_interval_ms
+                .min(max_polling_interval_ms)
+                .max(self.policy.polling_interval_ms);
+        }
+    }
+
+    /// Resets the polling interval to the minimum polling interval
+    fn reset_polling_interval(&mut self) {
+        self.polling_interval_ms = self.policy.polling_interval_ms;
+    }
+
     /// Polls the canister for messages
     pub(crate) async fn poll_canister(&mut self) -> Result<PollingStatus, String> {
         trace!("Started polling iteration");
 
         // get messages to be relayed to clients from canister (starting from 'message_nonce')
         // the response timeout of the IC CDK is 2 minutes which implies that the poller would be stuck for that long waiting for a response
-        // to prevent this, we set a timeout of 5 seconds, if the poller does not receive a response in time, it polls immediately
+        // to prevent this, we set a timeout (5 seconds by default), if the poller does not receive a response in time, it polls immediately
         // in case of a timeout, the message nonce is not updated so that no messages are lost by polling immediately again
         match timeout(
-            PollingTimeout::from_millis(POLLING_TIMEOUT_MS),
+            PollingTimeout::from_millis(self.policy.polling_timeout_ms),
             ws_get_messages(
                 &self.agent,
                 &self.canister_id,
@@ -176,12 +449,35 @@ impl CanisterPoller {
                 let number_of_polled_messages = certified_canister_output.messages.len();
                 if number_of_polled_messages == 0 {
                     trace!("No messages polled from canister");
+                    self.record_polling_result(None);
                     Ok(PollingStatus::NoMessagesPolled)
                 } else {
                     trace!(
                         "Polled {} messages from canister",
                         number_of_polled_messages
                     );
+                    if self.config.verify_certificates {
+                        // a misbehaving replica or boundary node might return forged messages
+                        // the nonce is not updated so that the messages are polled again
+                        if let Err(e) = verify_canister_output(
+                            &self.agent,
+                            &self.canister_id,
+                            &certified_canister_output,
+                        ) {
+                            warn!(
+                                "Discarding polled messages as their certification is not valid: {}",
+                                e
+                            );
+                            counter!("canister_certification_failures", "reason" => e.as_str())
+                                .increment(1);
+                            self.record_polling_result(Some(format!(
+                                "Invalid certification: {}",
+                                e
+                            )));
+                            return Ok(PollingStatus::InvalidCertification);
+                        }
+                    }
+                    self.record_polling_result(None);
                     Ok(PollingStatus::MessagesPolled(certified_canister_output))
                 }
             },
@@ -193,6 +489,7 @@ impl CanisterPoller {
                     // provide the canister updates
                     // TODO: add counter as after several retries the poller should be stopped
                     warn!("Ignoring replica error: {:?}", e);
+                    self.record_polling_result(Some(format!("Replica error: {:?}", e)));
                     Ok(PollingStatus::NoMessagesPolled)
                 } else {
                     Err(format!("Unrecoverable agent error: {:?}", e))
@@ -202,15 +499,22 @@ impl CanisterPoller {
             Ok(Err(IcError::Cdk(e))) => Err(format!("Unrecoverable CDK error: {:?}", e)),
             Err(e) => {
                 warn!("Poller took too long to retrieve messages: {:?}", e);
+                self.record_polling_result(Some(String::from("Polling timed out")));
                 Ok(PollingStatus::TimedOut)
             },
         }
     }
 
-    async fn relay_messages(&self, msgs: CanisterOutputCertifiedMessages) {
+    fn relay_messages(&mut self, msgs: CanisterOutputCertifiedMessages) {
         trace!("Started relaying messages");
         let mut relayed_messages_count = 0;
+        // the token of the session of the slow clients is kept so that a session which replaced them in the meantime is not dropped
+        let mut slow_clients: HashMap<ClientKey, SessionToken> = HashMap::new();
         for canister_output_message in msgs.messages {
+            // the content of the messages is not checked by the poller, if it cannot be decoded the client is left to reject it
+            let sequence_num = decode_one::<WebsocketMessage>(&canister_output_message.content)
+                .ok()
+                .map(|websocket_message| websocket_message.sequence_num);
             let canister_to_client_message = CanisterToClientMessage {
                 key: canister_output_message.key,
                 content: canister_output_message.content,
@@ -219,36 +523,130 @@ impl CanisterPoller {
             };
 
             // TODO: figure out if keeping references to a value in the poller state can cause deadlocks
-            if let Some(ClientSender {
-                sender: client_channel_tx,
-                span: client_session_span,
-            }) = self
+            if let Some(
+                client_sender @ ClientSender {
+                    sender: client_channel_tx,
+                    span: client_session_span,
+                    close_sender: client_close_tx,
+                    session_token,
+                    ..
+                },
+            ) = self
                 .poller_state
+                .clients
                 .get(&canister_output_message.client_key)
                 .as_deref()
             {
+                // clients which missed some messages do not get any of the following messages, as they would be out of order
+                // this holds also for the following polls, as the session of the client might not have handled the close request yet
+                if client_sender.has_lost_messages() {
+                    continue;
+                }
+                if let Some(sequence_num) = sequence_num {
+                    match check_sequence_num(
+                        &mut self.next_sequence_nums,
+                        &canister_output_message.client_key,
+                        *session_token,
+                        sequence_num,
+                    ) {
+                        SequenceNumCheck::Consecutive => (),
+                        SequenceNumCheck::Duplicate => {
+                            // the message has been polled again after the nonces of the canister went backward
+                            trace!("Skipping message already relayed to the client");
+                            continue;
+                        },
+                        SequenceNumCheck::Gap => {
+                            warn!(
+                                "Client {} missed some of the canister messages",
+                                canister_output_message.client_key
+                            );
+                            // only the clients which missed some messages are closed, the others keep receiving their messages
+                            // if the session is already closing, the request is redundant and can be ignored
+                            if client_close_tx
+                                .try_send(ClientCloseReason::MessagesLost)
+                                .is_ok()
+                            {
+                                counter!("out_of_sync_clients").increment(1);
+                            }
+                            client_sender.set_lost_messages();
+                            continue;
+                        },
+                    }
+                }
                 let canister_message_span = span!(parent: client_session_span, Level::TRACE, "Canister Message", message_key = canister_to_client_message.key, %self.canister_id);
                 canister_message_span.follows_from(Span::current().id());
                 let canister_message = canister_message_span.in_scope(|| {
                     trace!("Start relaying message",);
                     (canister_to_client_message, Span::current())
                 });
-                relay_message(canister_message, &client_channel_tx)
-                    .instrument(canister_message_span)
-                    .await;
-                relayed_messages_count += 1;
+                match canister_message_span
+                    .in_scope(|| relay_message(canister_message, client_channel_tx))
+                {
+                    RelayResult::Relayed => relayed_messages_count += 1,
+                    RelayResult::QueueFull => {
+                        if self.config.slow_client_policy == SlowClientPolicy::Close {
+                            // the session closes the connection with the client and then removes the client from the gateway state
+                            // if the session is already closing, the request is redundant and can be ignored
+                            if client_close_tx
+                                .try_send(ClientCloseReason::SlowClient)
+                                .is_ok()
+                            {
+                                counter!("slow_clients", "policy" => self.config.slow_client_policy.as_str()).increment(1);
+                            }
+                        }
+                        client_sender.set_lost_messages();
+                        slow_clients.insert(canister_output_message.client_key, *session_token);
+                    },
+                    RelayResult::SessionTerminated => (),
+                }
             }
             // SAFETY:
             // messages received from a client key that is not in the poller state are ignored
             // this is safe to do because we the client session handler relayes the messages to the IC
             // only after updating the poller state
         }
+        // the sequence numbers of the clients which left the canister are not needed anymore
+        let clients = &self.poller_state.clients;
+        self.next_sequence_nums
+            .retain(|client_key, (session_token, _)| {
+                clients
+                    .get(client_key)
+                    .is_some_and(|client_sender| client_sender.session_token == *session_token)
+            });
+        if self.config.slow_client_policy == SlowClientPolicy::Drop {
+            // the clients are removed only after relaying all the messages
+            // as the reference to the poller state entry must be dropped before removing it
+            for (client_key, session_token) in slow_clients {
+                self.drop_slow_client(client_key, session_token);
+            }
+        }
         trace!(
             "Relayed {} messages to connection handlers. The others were ignored",
             relayed_messages_count
         );
     }
 
+    /// Removes the slow client from the gateway state and notifies the canister via ws_close
+    /// Once its state is removed, the client session detects that the poller side of the channel has been dropped and terminates
+    fn drop_slow_client(&self, client_key: ClientKey, session_token: SessionToken) {
+        // the client might have been removed by its session in the meantime
+        // if so, the session takes care of calling ws_close
+        if let ClientRemovalResult::Removed(client_key) = self
+            .gateway_state
+            .remove_client_if_exists(self.canister_id, client_key, session_token)
+        {
+            warn!("Dropped slow client: {}", client_key);
+            counter!("slow_clients", "policy" => self.config.slow_client_policy.as_str())
+                .increment(1);
+
+            // ws_close is called by the retry task of the outbox so that the poller does not wait for the canister
+            // the call is queued before returning so that it is not lost if the gateway shuts down in the meantime
+            self.config
+                .close_outbox
+                .schedule_close(self.canister_id, client_key);
+        }
+    }
+
     /// Computes the effective polling interval based on the time it took to poll the canister
     fn compute_effective_polling_interval(
         &self,
@@ -278,29 +676,50 @@ impl CanisterPoller {
         }
     }
 
-    /// Updates the message nonce according to the last polled message
+    /// Updates the message nonce according to the polled messages and returns the number of messages to relay
     /// This is necessary to do before starting the next polling iteration
+    /// If the polled nonces are not consecutive, e.g. because the canister deleted messages before they were polled
+    /// or because its queue was reset by an upgrade, the poller resynchronizes with the canister instead of failing
+    /// After a gap, all the messages are relayed and the nonce follows the last polled message
+    /// After a backward jump, the following messages are not relayed and the nonce is reset to 0,
+    /// so that the canister is polled again from the first message in its queue
+    /// In both cases, the clients which missed some messages are detected by their sequence numbers while relaying
     /// Returns an error if a nonce could not be parsed from a message
-    /// or if the polled nonces are not consecutive
+    /// In case of error, the nonce is not updated so that a restarted poller polls the same messages again
     fn update_nonce(
         &mut self,
         certified_canister_output: &CanisterOutputCertifiedMessages,
-    ) -> Result<(), String> {
-        for canister_to_client_message in &certified_canister_output.messages {
+    ) -> Result<usize, String> {
+        let mut next_message_nonce = self.next_message_nonce;
+        for (i, canister_to_client_message) in certified_canister_output.messages.iter().enumerate()
+        {
             let message_nonce = get_nonce_from_message(&canister_to_client_message.key)?;
             // the first time the poller is started 'self.next_message_nonce' is 0
             // however, the canister might have already deleted messages that were relayed by previous pollers
             // therefore, if 'self.next_message_nonce' is 0 we can ignore the possible mismatch with the nonce of the first message received from the canister
             // all the other messages, instead shall arrive in the expected order (monotonically increasing and each adjacent to the next)
-            if self.next_message_nonce != 0 && message_nonce != self.next_message_nonce {
-                return Err(format!(
-                    "Non consecutive nonce: expected {}, got {}",
-                    self.next_message_nonce, message_nonce
-                ));
+            if next_message_nonce != 0 && message_nonce > next_message_nonce {
+                warn!(
+                    "Non consecutive nonce: expected {}, got {}. Messages {} to {} were not polled",
+                    next_message_nonce,
+                    message_nonce,
+                    next_message_nonce,
+                    message_nonce - 1
+                );
+                counter!("nonce_discontinuities", "kind" => "gap").increment(1);
+            } else if next_message_nonce != 0 && message_nonce < next_message_nonce {
+                warn!(
+                    "Non consecutive nonce: expected {}, got {}. Polling again from nonce 0",
+                    next_message_nonce, message_nonce
+                );
+                counter!("nonce_discontinuities", "kind" => "backward").increment(1);
+                self.next_message_nonce = 0;
+                return Ok(i);
             }
-            self.next_message_nonce = message_nonce + 1;
+            next_message_nonce = message_nonce + 1;
         }
-        Ok(())
+        self.next_message_nonce = next_message_nonce;
+        Ok(certified_canister_output.messages.len())
     }
 
     fn poller_should_terminate(&mut self) -> bool {
@@ -319,25 +738,92 @@ impl CanisterPoller {
             .gateway_state
             .remove_canister_if_empty(self.canister_id)
         {
-            CanisterRemovalResult::Empty => true,
+            CanisterRemovalResult::Empty => {
+                // for the same reason, the next poller of the canister must not resume from the current nonce
+                self.remove_stored_nonce();
+                true
+            },
             CanisterRemovalResult::NotEmpty => false,
         }
     }
+
+    /// Records the outcome of the polling iteration in the status of the poller
+    /// The unrecoverable errors are recorded by the poller before it is restarted
+    fn record_polling_result(&self, polling_error: Option<String>) {
+        self.poller_state.update_status(|status| {
+            status.last_poll_time = Some(SystemTime::now());
+            match polling_error {
+                Some(polling_error) => {
+                    status.error_streak += 1;
+                    status.last_error = Some(polling_error);
+                },
+                None => {
+                    status.error_streak = 0;
+                    status.last_error = None;
+                },
+            }
+        });
+    }
+
+    /// Removes the nonce stored for the canister, so that a poller started after the gateway restarts polls from nonce 0
+    /// called whenever the clients of the canister are closed, as the canister resets its queue once its last client is removed:
+    /// this includes the graceful shutdown of the gateway, during which the client sessions call ws_close for each client
+    fn remove_stored_nonce(&self) {
+        if let Some(poller_nonces) = &self.config.poller_nonces {
+            poller_nonces.remove(&self.canister_id);
+        }
+    }
 }
 
-async fn relay_message(
+fn relay_message(
     canister_message: IcWsCanisterMessage,
     client_channel_tx: &Sender<IcWsCanisterMessage>,
-) {
-    if let Err(e) = client_channel_tx.send(canister_message).await {
-        // SAFETY:
-        // no need to panic here as the client session handler might have terminated
-        // after the poller got the client_chanel_tx
-        // the client session handler also updated the poller state so the poller can simply ignore this message
-        warn!("Client's session terminated: {}", e);
-    } else {
-        trace!("Message relayed to connection handler");
+) -> RelayResult {
+    match client_channel_tx.try_send(canister_message) {
+        Ok(()) => {
+            trace!("Message relayed to connection handler");
+            RelayResult::Relayed
+        },
+        Err(TrySendError::Full(_)) => {
+            // the client does not read the canister messages as fast as they are relayed
+            // the poller does not wait for the client as this would delay all the other clients of the canister
+            warn!("Client's queue is full");
+            RelayResult::QueueFull
+        },
+        Err(TrySendError::Closed(_)) => {
+            // SAFETY:
+            // no need to panic here as the client session handler might have terminated
+            // after the poller got the client_chanel_tx
+            // the client session handler also updated the poller state so the poller can simply ignore this message
+            warn!("Client's session terminated");
+            RelayResult::SessionTerminated
+        },
+    }
+}
+
+/// Checks the sequence number of a canister message against the one expected by the client session
+/// and, if the message is to be relayed, updates the sequence number expected next
+/// The first message relayed to a session is not checked, as the poller does not know from which sequence number
+/// the canister started numbering the messages of the session
+fn check_sequence_num(
+    next_sequence_nums: &mut HashMap<ClientKey, (SessionToken, u64)>,
+    client_key: &ClientKey,
+    session_token: SessionToken,
+    sequence_num: u64,
+) -> SequenceNumCheck {
+    if let Some((expected_session_token, next_sequence_num)) = next_sequence_nums.get(client_key) {
+        // the canister numbers the messages of a new session with the same client key from scratch
+        if *expected_session_token == session_token {
+            if sequence_num < *next_sequence_num {
+                return SequenceNumCheck::Duplicate;
+            }
+            if sequence_num > *next_sequence_num {
+                return SequenceNumCheck::Gap;
+            }
+        }
     }
+    next_sequence_nums.insert(client_key.clone(), (session_token, sequence_num + 1));
+    SequenceNumCheck::Consecutive
 }
 
 pub fn get_nonce_from_message(key: &String) -> Result<u64, String> {
@@ -353,7 +839,7 @@ pub fn get_nonce_from_message(key: &String) -> Result<u64, String> {
 }
 
 /// Returns true if the error is caused by a replica which is either actively malicious or simply unavailable
-fn is_recoverable_error(e: &AgentError) -> bool {
+pub(crate) fn is_recoverable_error(e: &AgentError) -> bool {
     match e {
         // TODO: make sure that we include all the "recoverable" errors
         AgentError::InvalidReplicaUrl(_)
@@ -0,0 +1,96 @@
+use canister_utils::{CanisterOutputCertifiedMessages, CanisterOutputMessage};
+use gateway_state::CanisterPrincipal;
+use ic_agent::Agent;
+use ic_certification::{Certificate, HashTree, LookupResult};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Label of the subtree in which the CDK certifies the hashes of the messages for the clients
+const WEBSOCKET_LABEL: &[u8] = b"websocket";
+
+/// Reason for which data certified by a canister cannot be trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificationError {
+    /// The certificate cannot be decoded
+    MalformedCertificate,
+    /// The tree cannot be decoded
+    MalformedTree,
+    /// The certificate is not signed by the IC or not issued by the subnet of the canister
+    InvalidCertificate,
+    /// The root hash of the tree is not the certified data of the canister
+    CertifiedDataMismatch,
+    /// The hash of a message is not certified in the tree under the key of the message
+    MessageNotCertified,
+}
+
+impl CertificationError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificationError::MalformedCertificate => "malformed_certificate",
+            CertificationError::MalformedTree => "malformed_tree",
+            CertificationError::InvalidCertificate => "invalid_certificate",
+            CertificationError::CertifiedDataMismatch => "certified_data_mismatch",
+            CertificationError::MessageNotCertified => "message_not_certified",
+        }
+    }
+}
+
+impl fmt::Display for CertificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Returns an error if the messages polled from the canister are not certified as the clients expect them to be
+pub fn verify_canister_output(
+    agent: &Agent,
+    canister_id: &CanisterPrincipal,
+    canister_output: &CanisterOutputCertifiedMessages,
+) -> Result<(), CertificationError> {
+    let tree: HashTree = serde_cbor::from_slice(&canister_output.tree)
+        .map_err(|_| CertificationError::MalformedTree)?;
+    verify_certified_tree(agent, canister_id, &canister_output.cert, &tree)?;
+    verify_certified_messages(&tree, &canister_output.messages)
+}
+
+/// Returns an error if the certificate is not signed by the IC on behalf of the canister
+/// or if it does not certify the root hash of the tree as the certified data of the canister
+/// The certificate is verified against the root key of the network the agent is connected to
+pub fn verify_certified_tree(
+    agent: &Agent,
+    canister_id: &CanisterPrincipal,
+    certificate: &[u8],
+    tree: &HashTree,
+) -> Result<(), CertificationError> {
+    let certificate: Certificate = serde_cbor::from_slice(certificate)
+        .map_err(|_| CertificationError::MalformedCertificate)?;
+    agent
+        .verify(&certificate, *canister_id)
+        .map_err(|_| CertificationError::InvalidCertificate)?;
+    let LookupResult::Found(certified_data) = certificate.tree.lookup_path([
+        b"canister".as_slice(),
+        canister_id.as_slice(),
+        b"certified_data".as_slice(),
+    ]) else {
+        return Err(CertificationError::CertifiedDataMismatch);
+    };
+    if certified_data != tree.digest().as_slice() {
+        return Err(CertificationError::CertifiedDataMismatch);
+    }
+    Ok(())
+}
+
+/// Returns an error if the hash of any of the messages is not certified in the tree under the key of the message
+pub fn verify_certified_messages(
+    tree: &HashTree,
+    messages: &[CanisterOutputMessage],
+) -> Result<(), CertificationError> {
+    for message in messages {
+        let content_hash = Sha256::digest(&message.content);
+        match tree.lookup_path([WEBSOCKET_LABEL, message.key.as_bytes()]) {
+            LookupResult::Found(certified_hash) if certified_hash == content_hash.as_slice() => (),
+            _ => return Err(CertificationError::MessageNotCertified),
+        }
+    }
+    Ok(())
+}
@@ -1,28 +1,42 @@
+use crate::{
+    envelope_validation::{
+        check_session_envelope, InvalidEnvelope, WS_MESSAGE_METHOD_NAME, WS_OPEN_METHOD_NAME,
+    },
+    envelope_verification::{current_time, verify_envelope},
+    rate_limiter::{RateLimitAction, SessionRateLimiter},
+};
 use candid::{decode_args, Principal};
 use canister_utils::{
-    CanisterToClientMessage, CanisterWsOpenArguments, ClientKey, IcWsCanisterMessage,
+    CanisterToClientMessage, CanisterWsMessageArguments, CanisterWsOpenArguments, ClientKey,
+    IcWsCanisterMessage,
 };
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
-use gateway_state::CanisterPrincipal;
+use gateway_state::{CanisterPrincipal, ClientActivityNotifier, ClientCloseReason, SessionToken};
 use ic_agent::{
     agent::{Envelope, EnvelopeContent},
     Agent, AgentError,
 };
+use metrics::counter;
 use serde::{Deserialize, Serialize};
 use serde_cbor::{from_slice, to_vec};
-use std::sync::Arc;
+use std::{future::pending, sync::Arc, time::Duration};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     select,
-    sync::mpsc::Receiver,
+    sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+    time::{sleep_until, Instant},
 };
 use tokio_tungstenite::{
-    tungstenite::{Error, Message},
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Error, Message,
+    },
     WebSocketStream,
 };
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::{error, span, trace, warn, Instrument, Level, Span};
 
 /// Message sent by the WS Gateway upon open the (traditional) WebSocket connection
@@ -43,10 +57,130 @@ struct ClientRequest<'a> {
 pub enum IcWsSessionState {
     Init,
     Setup(Message),
+    /// The first message of the client asks to resume a session whose connection was lost
+    Resuming(Message),
     Open,
     Closed,
 }
 
+/// Signed envelope of a client message waiting to be relayed to the IC, together with its span
+struct ClientMessage {
+    serialized_envelope: Vec<u8>,
+    canister_id: CanisterPrincipal,
+    span: ClientMessageSpan,
+}
+
+type ClientMessageSpan = Span;
+
+/// Bounded pipeline used by the client session to relay the client messages to the IC without waiting for them to be submitted
+/// so that a slow response from the IC does not prevent the session from reading the next client message
+/// or from relaying the canister messages to the client
+pub(crate) struct ClientMessagesPipeline {
+    /// Channel used to queue the client messages to be relayed by the relayer task
+    client_messages_tx: Sender<ClientMessage>,
+    /// Channel used by the relayer task to report that a client message could not be relayed to the IC
+    relay_errors_rx: Receiver<IcWsError>,
+}
+
+impl ClientMessagesPipeline {
+    /// Spawns the relayer task on the tracker, so that the gateway waits for the queued messages to be relayed before shutting down
+    pub(crate) fn new(agent: Arc<Agent>, capacity: usize, task_tracker: &TaskTracker) -> Self {
+        // [client session task]                 [relayer task]
+        // client_messages_tx         -----> client_messages_rx
+        // relay_errors_rx           <-----  relay_errors_tx
+        let (client_messages_tx, client_messages_rx): (
+            Sender<ClientMessage>,
+            Receiver<ClientMessage>,
+        ) = mpsc::channel(capacity);
+        let (relay_errors_tx, relay_errors_rx): (Sender<IcWsError>, Receiver<IcWsError>) =
+            mpsc::channel(1);
+        task_tracker.spawn(
+            relay_client_messages(agent, client_messages_rx, relay_errors_tx)
+                .instrument(Span::current()),
+        );
+        Self {
+            client_messages_tx,
+            relay_errors_rx,
+        }
+    }
+
+    /// Queues the serialized envelope of a client message, without waiting for it to be relayed to the IC
+    pub(crate) fn enqueue(
+        &self,
+        serialized_envelope: Vec<u8>,
+        canister_id: CanisterPrincipal,
+        span: ClientMessageSpan,
+    ) -> Result<(), IcWsError> {
+        let client_message = ClientMessage {
+            serialized_envelope,
+            canister_id,
+            span,
+        };
+        match self.client_messages_tx.try_send(client_message) {
+            Ok(()) => Ok(()),
+            // the client keeps sending messages faster than they can be relayed to the IC
+            Err(TrySendError::Full(_)) => Err(IcWsError::IcWsProtocol(String::from(
+                "Too many client messages waiting to be relayed to the IC",
+            ))),
+            // the relayer task terminates only after reporting a relay error, which terminates the session
+            Err(TrySendError::Closed(_)) => Err(IcWsError::IcWsProtocol(String::from(
+                "Relayer task terminated",
+            ))),
+        }
+    }
+
+    /// Receives the error reported by the relayer task if a client message could not be relayed to the IC
+    pub(crate) async fn recv_relay_error(&mut self) -> Option<IcWsError> {
+        self.relay_errors_rx.recv().await
+    }
+}
+
+/// Configuration of the pings sent to the client to detect whether it is still connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    /// Time without receiving frames from the client after which a ping is sent
+    pub ping_interval: Duration,
+    /// Time given to the client to respond to a ping before the session is closed
+    pub pong_timeout: Duration,
+}
+
+/// Configuration of a client session
+pub struct ClientSessionConfig {
+    /// Maximum number of client messages waiting to be relayed to the IC
+    pub client_messages_pipeline_capacity: usize,
+    /// Rate limiter of the messages sent by the client
+    pub rate_limiter: SessionRateLimiter,
+    /// Configuration of the pings sent to the client, no ping is sent if None
+    pub keepalive_config: Option<KeepaliveConfig>,
+}
+
+/// Liveness of the client, tracked to send pings and to detect an unresponsive client
+struct Keepalive {
+    config: KeepaliveConfig,
+    /// Last time a frame has been received from the client
+    last_client_frame: Instant,
+    /// Time at which the ping the client has not responded to yet has been sent
+    ping_sent_at: Option<Instant>,
+}
+
+impl Keepalive {
+    fn new(config: KeepaliveConfig) -> Self {
+        Self {
+            config,
+            last_client_frame: Instant::now(),
+            ping_sent_at: None,
+        }
+    }
+
+    /// Returns the time at which either a ping has to be sent or the client is considered unresponsive
+    fn deadline(&self) -> Instant {
+        match self.ping_sent_at {
+            Some(ping_sent_at) => ping_sent_at + self.config.pong_timeout,
+            None => self.last_client_frame + self.config.ping_interval,
+        }
+    }
+}
+
 /// Possible errors that can occur during an IC WebSocket session
 #[derive(Debug, Clone)]
 pub enum IcWsError {
@@ -56,6 +190,8 @@ pub enum IcWsError {
     WebSocket(String),
     /// Poller error
     Poller(String),
+    /// Error due to the client sending an envelope which does not belong to its session
+    InvalidEnvelope(InvalidEnvelope),
 }
 
 /// Actor for an IC WebSocket session
@@ -66,36 +202,88 @@ pub struct ClientSession<S: AsyncRead + AsyncWrite + Unpin> {
     pub client_key: Option<ClientKey>,
     /// Principal of the canister the client is connected to
     pub canister_id: Option<CanisterPrincipal>,
+    /// Token identifying the entry of the session in the gateway state
+    /// Set once the client has been added to the gateway state
+    pub session_token: Option<SessionToken>,
     /// Channel used to receive canister updates by the poller
-    client_channel_rx: Receiver<IcWsCanisterMessage>,
-    /// Sending side of the WS connection with the client
-    ws_write: SplitSink<WebSocketStream<S>, Message>,
+    /// Set once the client has been added to the gateway state, as its capacity depends on the policy of the canister
+    client_channel_rx: Option<Receiver<IcWsCanisterMessage>>,
+    /// Channel used by the poller to request the session to be closed
+    /// Set once the client has been added to the gateway state
+    client_close_rx: Option<Receiver<ClientCloseReason>>,
+    /// Channel used to send messages to the writer task, which owns the sending side of the WS connection with the client
+    ws_write_tx: Sender<Message>,
     /// Receiving side of the WS connection with the client
     ws_read: SplitStream<WebSocketStream<S>>,
+    /// Pipeline relaying the client messages to the IC
+    client_messages_pipeline: ClientMessagesPipeline,
     /// Current state of the IC WS session
     session_state: IcWsSessionState,
     /// Agent used to communicate with the IC
     agent: Arc<Agent>,
+    /// Token cancelled when the gateway is shutting down
+    shutdown_token: CancellationToken,
+    /// Notifier used to wake up the poller of the canister whenever the client sends a message
+    /// Set once the client has been added to the gateway state
+    client_activity: Option<ClientActivityNotifier>,
+    /// Maximum size in bytes of the messages sent by the client, specified by the policy of the canister
+    max_client_message_size: Option<usize>,
+    /// Rate limiter of the messages sent by the client
+    rate_limiter: SessionRateLimiter,
+    /// Liveness of the client, None if no pings are sent
+    keepalive: Option<Keepalive>,
+    /// Whether the session was closed because the connection with the client was lost while Open
+    connection_lost: bool,
+    /// Whether the signatures and expiries of the envelopes are verified before relaying them to the IC
+    verify_envelopes: bool,
 }
 
-impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> ClientSession<S> {
     pub async fn init(
         _client_id: u64,
-        gateway_principal: Principal,
-        client_channel_rx: Receiver<IcWsCanisterMessage>,
-        ws_write: SplitSink<WebSocketStream<S>, Message>,
-        ws_read: SplitStream<WebSocketStream<S>>,
+        ws_stream: WebSocketStream<S>,
         agent: Arc<Agent>,
+        shutdown_token: CancellationToken,
+        task_tracker: &TaskTracker,
+        config: ClientSessionConfig,
     ) -> Result<Self, IcWsError> {
+        let gateway_principal = agent.get_principal().expect("Principal should be set");
+        let (ws_write, ws_read) = ws_stream.split();
+
+        // [client session task]          [writer task]
+        // ws_write_tx           -----> ws_write_rx
+
+        // channel used by the client session to send messages to the client without waiting for the WebSocket to be written
+        // so that writing to a slow client does not prevent the session from reading the next client message
+        let (ws_write_tx, ws_write_rx): (Sender<Message>, Receiver<Message>) = mpsc::channel(100);
+        // the writer task is tracked so that the gateway waits for the close frames to be flushed before shutting down
+        task_tracker.spawn(write_ws_messages(ws_write, ws_write_rx).instrument(Span::current()));
+
+        let client_messages_pipeline = ClientMessagesPipeline::new(
+            Arc::clone(&agent),
+            config.client_messages_pipeline_capacity,
+            task_tracker,
+        );
+
         let mut client_session = Self {
             _client_id,
             client_key: None,
             canister_id: None,
-            client_channel_rx,
-            ws_write,
+            session_token: None,
+            client_channel_rx: None,
+            client_close_rx: None,
+            ws_write_tx,
             ws_read,
+            client_messages_pipeline,
             session_state: IcWsSessionState::Init,
             agent,
+            shutdown_token,
+            client_activity: None,
+            max_client_message_size: None,
+            rate_limiter: config.rate_limiter,
+            keepalive: config.keepalive_config.map(Keepalive::new),
+            connection_lost: false,
+            verify_envelopes: true,
         };
 
         // as soon as the WS connection with the client is established, send the gateway principal
@@ -119,7 +307,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
     }
 }
 
-impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> ClientSession<S> {
     pub async fn try_update_state(&mut self) -> Result<Option<IcWsSessionState>, IcWsError> {
         // keep track of the session state before handling the update
         let previous_session_state = self.session_state.clone();
@@ -132,7 +320,16 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
             Some(client_update) = self.ws_read.next() => self.handle_client_update(client_update).await?,
             // in case of a poller error, the poller will terminate immediately, without waiting for the client session handler to cleanup its state and terminate
             // in such a case, the sending side of the channel is dropped and therefore the client session shall return an error
-            canister_update = self.client_channel_rx.recv() => self.handle_canister_update(canister_update).await?,
+            // until the client is added to the gateway state, no canister update can be received
+            canister_update = recv_from_poller(&mut self.client_channel_rx) => self.handle_canister_update(canister_update).await?,
+            // the poller holds the sending side of the channel as long as the client is in the poller state, therefore None can be ignored
+            Some(close_reason) = recv_from_poller(&mut self.client_close_rx) => self.close(close_reason).await,
+            // the relayer task holds the sending side of the channel until the session is dropped, therefore 'recv' never returns None
+            Some(relay_error) = self.client_messages_pipeline.recv_relay_error() => self.handle_relay_error(relay_error)?,
+            // once the gateway starts shutting down, the session is closed so that the session handler can clean up the client's state
+            _ = self.shutdown_token.cancelled() => self.handle_shutdown().await,
+            // the client might be gone without closing the TCP connection (e.g. behind a NAT), in which case reading never fails
+            _ = wait_for_keepalive_deadline(&self.keepalive) => self.handle_keepalive_deadline()?,
         }
         // if the update resulted in a new session state, return it so that the session handler can act accordingly,
         if self.session_state != previous_session_state {
@@ -145,6 +342,15 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
         &mut self,
         client_update: Result<Message, Error>,
     ) -> Result<(), IcWsError> {
+        // any frame received from the client proves that it is still connected
+        if let Some(keepalive) = &mut self.keepalive {
+            keepalive.last_client_frame = Instant::now();
+            keepalive.ping_sent_at = None;
+        }
+        // control frames are not part of the IC WS protocol, the pings of the client are answered by the WebSocket library
+        if let Ok(Message::Ping(_) | Message::Pong(_)) = client_update {
+            return Ok(());
+        }
         match self.session_state {
             IcWsSessionState::Init => {
                 let ws_message = self.handle_ws_errors(client_update)?;
@@ -162,7 +368,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
                     Ok(())
                 }
             },
-            IcWsSessionState::Setup(_) => {
+            IcWsSessionState::Setup(_) | IcWsSessionState::Resuming(_) => {
                 // upon receiving an update while the session is Setup, check if it is due to
                 // the client disconnecting without a closing handshake
                 let _ = self.handle_ws_errors(client_update)?;
@@ -178,11 +384,25 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
             IcWsSessionState::Open => {
                 let ws_message = self.handle_ws_errors(client_update)?;
                 if !ws_message.is_close() {
-                    // upon receiving a message while the session is Open, immediately relay the client messages to the IC
+                    self.check_client_message_size(&ws_message)?;
+                    if !self.check_client_rate_limit().await {
+                        return Ok(());
+                    }
+                    // upon receiving a message while the session is Open, queue the client message in the pipeline relaying it to the IC
+                    // without waiting for it to be submitted, so that the session can keep reading client messages and relaying canister messages
                     // this does not result in a state transition, which shall remain in Open state
-                    self.relay_client_message(ws_message)
-                        .instrument(Span::current())
-                        .await?;
+                    if let Err(e) = self.enqueue_client_message(ws_message) {
+                        // the envelope might call another canister or method, on behalf of the client or of someone else
+                        // the gateway shall not relay it, as it only relays the messages of the IC WS session
+                        if let IcWsError::InvalidEnvelope(invalid_envelope) = e {
+                            warn!(
+                                "Closing the session as the client sent an invalid envelope: {}",
+                                invalid_envelope
+                            );
+                            self.close(ClientCloseReason::InvalidEnvelope).await;
+                        }
+                        return Err(e);
+                    }
                     Ok(())
                 } else {
                     trace!("Client disconnected while in Open state");
@@ -209,11 +429,14 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
         match canister_update {
             Some((canister_message, canister_message_span)) => {
                 match self.session_state {
-                    IcWsSessionState::Init => {
+                    IcWsSessionState::Init | IcWsSessionState::Resuming(_) => {
                         // no need to update the session state to Closed as getting to this state shall not be possible
                         // if here, there is a bug in the WS Gateway as the poller is not suppose to relay messages
                         // for uninitialized client sessions
-                        unreachable!("Canister shall not send messages while in Init state")
+                        // the channels of a resumed session are handed over only once the session is Open
+                        unreachable!(
+                            "Canister shall not send messages while in Init or Resuming state"
+                        )
                     },
                     IcWsSessionState::Setup(_) => {
                         let open_state = self
@@ -243,15 +466,138 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
                 }
             },
             None => {
+                // the gateway drops the poller side of the channel after requesting the session to close when it is replaced by a new session
+                if let Some(Ok(close_reason)) = self
+                    .client_close_rx
+                    .as_mut()
+                    .map(|client_close_rx| client_close_rx.try_recv())
+                {
+                    self.close(close_reason).await;
+                    return Ok(());
+                }
+                // the poller drops its side of the channel when it fails or when it drops the client for being too slow
                 warn!("Poller side of the channel has been dropped. Terminating the session.");
                 self.close_ws_session().await?;
                 Err(IcWsError::Poller(String::from(
-                    "Client session terminated due to poller failure",
+                    "Client session terminated due to poller failure or to the client being dropped by the poller",
                 )))
             },
         }
     }
 
+    fn handle_relay_error(&mut self, relay_error: IcWsError) -> Result<(), IcWsError> {
+        // the session cannot continue as the messages following the one which could not be relayed
+        // would be rejected by the canister anyway, due to the sequence number being out of order
+        self.session_state = IcWsSessionState::Closed;
+        Err(relay_error)
+    }
+
+    /// Closes the session by sending the close frame corresponding to the reason to the client
+    pub async fn close(&mut self, close_reason: ClientCloseReason) {
+        trace!("Closing the session: {:?}", close_reason);
+        if close_reason == ClientCloseReason::Replaced {
+            counter!("replaced_sessions").increment(1);
+        }
+        self.session_state = IcWsSessionState::Closed;
+        let close_frame = get_close_frame(close_reason);
+        if let Err(e) = self
+            .send_ws_message_to_client(Message::Close(Some(close_frame)))
+            .await
+        {
+            warn!("Could not send close frame to client: {:?}", e);
+        }
+    }
+
+    /// Returns false if the client message exceeds the rate limit and must not be relayed to the IC
+    async fn check_client_rate_limit(&mut self) -> bool {
+        let client_principal = self
+            .client_key
+            .as_ref()
+            .expect("must be set in Open state")
+            .client_principal;
+        let Err(rate_limit_scope) = self.rate_limiter.check(&client_principal) else {
+            return true;
+        };
+        let rate_limit_action = self.rate_limiter.action();
+        counter!(
+            "rate_limited_client_messages",
+            "scope" => rate_limit_scope.as_str(),
+            "action" => rate_limit_action.as_str()
+        )
+        .increment(1);
+        match rate_limit_action {
+            // the canister rejects all the messages following the dropped one, as their sequence number is out of order,
+            // and disconnects the client, while the WebSocket stays open
+            RateLimitAction::Drop => {
+                warn!(
+                    "Dropped client message exceeding the {} rate limit",
+                    rate_limit_scope
+                );
+            },
+            RateLimitAction::Close => {
+                warn!(
+                    "Closing the session as the client exceeded the {} rate limit",
+                    rate_limit_scope
+                );
+                self.close(ClientCloseReason::RateLimited).await;
+            },
+        }
+        false
+    }
+
+    /// Sends a ping to the client or, if the client has not responded to the previous one in time,
+    /// closes the session so that the session handler removes the client from the gateway state
+    fn handle_keepalive_deadline(&mut self) -> Result<(), IcWsError> {
+        let keepalive = self
+            .keepalive
+            .as_mut()
+            .expect("deadline is never reached without keepalive");
+        if keepalive.ping_sent_at.is_some() {
+            counter!("unresponsive_clients").increment(1);
+            warn!(
+                "Closing the session as the client did not respond to a ping within {:?}",
+                keepalive.config.pong_timeout
+            );
+            // the close frame is sent without waiting for the writer task, as the client is not expected to read it
+            let _ = self.ws_write_tx.try_send(Message::Close(Some(get_close_frame(
+                ClientCloseReason::Unresponsive,
+            ))));
+            self.connection_lost = self.session_state == IcWsSessionState::Open;
+            self.session_state = IcWsSessionState::Closed;
+            return Ok(());
+        }
+        keepalive.ping_sent_at = Some(Instant::now());
+        trace!("Sending ping to client");
+        match self.ws_write_tx.try_send(Message::Ping(Vec::new())) {
+            // if the writer task cannot keep up, the client is not reading its frames
+            // and it will be considered unresponsive unless it sends a frame before the pong deadline
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Closed(_)) => {
+                self.session_state = IcWsSessionState::Closed;
+                Err(IcWsError::WebSocket(String::from(
+                    "Could not write to the WebSocket",
+                )))
+            },
+        }
+    }
+
+    async fn handle_shutdown(&mut self) {
+        trace!("Closing the session as the gateway is shutting down");
+        // the session is Closed even if the close frame cannot be delivered
+        // as the client is not going to be served anymore by this gateway
+        self.session_state = IcWsSessionState::Closed;
+        let close_frame = CloseFrame {
+            code: CloseCode::Away,
+            reason: "Gateway is shutting down".into(),
+        };
+        if let Err(e) = self
+            .send_ws_message_to_client(Message::Close(Some(close_frame)))
+            .await
+        {
+            warn!("Could not send close frame to client: {:?}", e);
+        }
+    }
+
     fn handle_ws_errors(
         &mut self,
         canister_update: Result<Message, Error>,
@@ -262,6 +608,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
                 // no need to update the session state to Closed as 'update_state' will return an error
                 // and the session handler will not call it again
                 // set the session state to Closed anyway just for clarity
+                self.connection_lost = self.session_state == IcWsSessionState::Open;
                 self.session_state = IcWsSessionState::Closed;
                 Err(IcWsError::WebSocket(format!(
                     "Error receiving message from client: {:?}",
@@ -280,7 +627,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
             .await
         {
             // if the IC WS connection is setup, create a new client session and send it to the main task
-            Ok((client_key, canister_id)) => {
+            Ok((client_key, canister_id, is_resumption)) => {
                 // replace the field with the canister_id received in the first envelope
                 // this shall not be updated anymore
                 // if canister_id is already set in the struct, we return an error as inspect_ic_ws_open_message shall only be called once
@@ -295,6 +642,10 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
                         "canister_id or client_key field was set twice",
                     )));
                 }
+                if is_resumption {
+                    trace!("Validated session resumption message");
+                    return Ok(IcWsSessionState::Resuming(ws_open_message));
+                }
                 trace!("Validated WS open message");
 
                 // client session is now Setup
@@ -302,7 +653,11 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
             },
             // in case of other errors, we report them and terminate the connection handler task
             Err(e) => {
-                self.close_ws_session().await?;
+                if let IcWsError::InvalidEnvelope(_) = e {
+                    self.close(ClientCloseReason::InvalidEnvelope).await;
+                } else {
+                    self.close_ws_session().await?;
+                }
                 return Err(IcWsError::IcWsProtocol(format!(
                     "IC WS setup failed. Error: {:?}",
                     e
@@ -311,16 +666,43 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
         }
     }
 
+    /// Returns the key of the client, the canister it connects to and whether the client asks to resume a session
     async fn inspect_ic_ws_open_message(
         &mut self,
         ws_message: Message,
-    ) -> Result<(ClientKey, Principal), IcWsError> {
+    ) -> Result<(ClientKey, Principal, bool), IcWsError> {
         let client_request = get_client_request(ws_message)?;
         // the first envelope shall have content of variant Call, which contains canister_id
         if let EnvelopeContent::Call {
-            canister_id, arg, ..
+            canister_id,
+            method_name,
+            arg,
+            ..
         } = &*client_request.envelope.content
         {
+            let client_principal = client_request.envelope.content.sender().to_owned();
+
+            // a client whose connection was lost resumes its session by sending its next message instead of the WS open message
+            if method_name == WS_MESSAGE_METHOD_NAME {
+                let (ws_message_arguments,): (CanisterWsMessageArguments,) = decode_args(arg)
+                    .map_err(|e| {
+                        IcWsError::IcWsProtocol(format!(
+                            "arg field of envelope's content has the wrong type: {:?}",
+                            e.to_string()
+                        ))
+                    })?;
+                let client_key = ws_message_arguments.msg.client_key;
+                if client_key.client_principal != client_principal {
+                    return Err(IcWsError::IcWsProtocol(String::from(
+                        "client_key of the message does not belong to the sender of the envelope",
+                    )));
+                }
+                return Ok((client_key, canister_id.to_owned(), true));
+            }
+            if method_name != WS_OPEN_METHOD_NAME {
+                return Err(reject_envelope(InvalidEnvelope::MethodNotAllowed));
+            }
+
             let (ws_open_arguments,): (CanisterWsOpenArguments,) =
                 decode_args(arg).map_err(|e| {
                     IcWsError::IcWsProtocol(format!(
@@ -329,63 +711,187 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
                     ))
                 })?;
 
-            let client_principal = client_request.envelope.content.sender().to_owned();
             let client_key = ClientKey::new(client_principal, ws_open_arguments.client_nonce);
 
-            return Ok((client_key, canister_id.to_owned()));
+            return Ok((client_key, canister_id.to_owned(), false));
         }
-        Err(IcWsError::IcWsProtocol(String::from(
-            "first message from client should contain canister_id and arg in envelope's content and should be of Call variant",
-        )))
+        // the first message from client should contain canister_id and arg in envelope's content and should be of Call variant
+        Err(reject_envelope(InvalidEnvelope::NotCall))
     }
 
-    pub async fn relay_client_message(&self, message: Message) -> Result<(), IcWsError> {
+    /// Sets the receiving sides of the channels used by the poller to communicate with the session
+    /// and the token of the entry of the session in the gateway state
+    pub fn set_poller_channels(
+        &mut self,
+        client_channel_rx: Receiver<IcWsCanisterMessage>,
+        client_close_rx: Receiver<ClientCloseReason>,
+        session_token: SessionToken,
+    ) {
+        self.client_channel_rx = Some(client_channel_rx);
+        self.client_close_rx = Some(client_close_rx);
+        self.session_token = Some(session_token);
+    }
+
+    /// Takes the receiving sides of the channels used by the poller to communicate with the session,
+    /// so that they can be handed over to the session resuming it
+    pub fn take_poller_channels(
+        &mut self,
+    ) -> Option<(Receiver<IcWsCanisterMessage>, Receiver<ClientCloseReason>)> {
+        self.client_channel_rx
+            .take()
+            .zip(self.client_close_rx.take())
+    }
+
+    /// Opens the session with the channels and the entry in the gateway state of the session it resumes
+    /// The canister messages buffered in the meantime are relayed to the client as soon as the session is updated
+    pub fn resume(
+        &mut self,
+        client_channel_rx: Receiver<IcWsCanisterMessage>,
+        client_close_rx: Receiver<ClientCloseReason>,
+        session_token: SessionToken,
+    ) {
+        self.set_poller_channels(client_channel_rx, client_close_rx, session_token);
+        self.session_state = IcWsSessionState::Open;
+    }
+
+    /// Returns true if the session was closed because the connection with the client was lost while Open
+    pub fn is_connection_lost(&self) -> bool {
+        self.connection_lost
+    }
+
+    /// Sets the maximum size of the messages sent by the client
+    pub fn set_max_client_message_size(&mut self, max_client_message_size: Option<usize>) {
+        self.max_client_message_size = max_client_message_size;
+    }
+
+    /// Returns an error if the client message exceeds the maximum size
+    pub fn check_client_message_size(&self, message: &Message) -> Result<(), IcWsError> {
+        match self.max_client_message_size {
+            Some(max_client_message_size) if message.len() > max_client_message_size => {
+                Err(IcWsError::IcWsProtocol(format!(
+                    "Client message of {} bytes exceeds the maximum size of {} bytes",
+                    message.len(),
+                    max_client_message_size
+                )))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets whether the signatures and expiries of the envelopes are verified before relaying them to the IC
+    pub fn set_verify_envelopes(&mut self, verify_envelopes: bool) {
+        self.verify_envelopes = verify_envelopes;
+    }
+
+    /// Sets the notifier used to wake up the poller of the canister whenever the client sends a message
+    pub fn set_client_activity_notifier(&mut self, client_activity: ClientActivityNotifier) {
+        self.client_activity = Some(client_activity);
+    }
+
+    fn notify_client_activity(&self) {
+        // the poller might be waiting for the next polling iteration
+        // if so, it polls as soon as possible so that the response of the canister is relayed to the client promptly
+        if let Some(client_activity) = &self.client_activity {
+            client_activity.notify_one();
+        }
+    }
+
+    /// Relays the client's message to the IC and waits for it to be submitted
+    /// Used to relay the WS open message, which must be submitted before the session can be Open
+    /// Closes the session if the envelope of the message is rejected by the gateway
+    pub async fn relay_client_message(&mut self, message: Message) -> Result<(), IcWsError> {
         let client_message_span = span!(
             parent: &Span::current(),
             Level::TRACE,
             "Client Message",
         );
 
-        self.relay_ws_message_to_ic(message)
+        let result = self
+            .relay_ws_message_to_ic(message)
             .instrument(client_message_span)
-            .await
+            .await;
+        if let Err(IcWsError::InvalidEnvelope(invalid_envelope)) = result {
+            warn!(
+                "Closing the session as the client sent an invalid envelope: {}",
+                invalid_envelope
+            );
+            self.close(ClientCloseReason::InvalidEnvelope).await;
+        }
+        result
     }
 
-    /// relays the client's request to the IC only if the content of the envelope is of the Call variant
     async fn relay_ws_message_to_ic(&self, message: Message) -> Result<(), IcWsError> {
         trace!("Received client message");
-        let client_request = get_client_request(message)?;
-        if let EnvelopeContent::Call { .. } = *client_request.envelope.content {
-            let serialized_envelope = serialize(client_request.envelope)?;
+        let serialized_envelope = self.get_serialized_session_envelope(message)?;
 
-            let canister_id = self.canister_id.expect("must be set");
+        let canister_id = self.canister_id.expect("must be set");
 
-            // relay the envelope to the IC
-            self.relay_envelope_to_canister(serialized_envelope, canister_id.clone())
-                .await
-                .map_err(|e| IcWsError::IcWsProtocol(e.to_string()))?;
+        // relay the envelope to the IC
+        relay_envelope_to_canister(&self.agent, serialized_envelope, canister_id)
+            .await
+            .map_err(|e| IcWsError::IcWsProtocol(e.to_string()))?;
+        self.notify_client_activity();
 
-            // there is no need to relay the response back to the client as the response to a request to the /call enpoint is not certified by the canister
-            // and therefore could be manufactured by the gateway
+        // there is no need to relay the response back to the client as the response to a request to the /call enpoint is not certified by the canister
+        // and therefore could be manufactured by the gateway
 
-            trace!("Relayed client message to canister");
-            Ok(())
-        } else {
-            Err(IcWsError::IcWsProtocol(String::from(
-                "Gateway can only relay envelopes with content of Call variant",
-            )))
-        }
+        trace!("Relayed client message to canister");
+        Ok(())
     }
 
-    async fn relay_envelope_to_canister(
-        &self,
-        serialized_envelope: Vec<u8>,
-        canister_id: Principal,
-    ) -> Result<(), AgentError> {
-        self.agent
-            .update_signed(canister_id, serialized_envelope)
-            .await?;
-        return Ok(());
+    /// Queues the client's message in the pipeline relaying it to the IC, without waiting for it to be submitted
+    fn enqueue_client_message(&self, message: Message) -> Result<(), IcWsError> {
+        let client_message_span = span!(
+            parent: &Span::current(),
+            Level::TRACE,
+            "Client Message",
+        );
+
+        let serialized_envelope = client_message_span.in_scope(|| {
+            trace!("Received client message");
+            self.get_serialized_session_envelope(message)
+        })?;
+
+        self.client_messages_pipeline.enqueue(
+            serialized_envelope,
+            self.canister_id.expect("must be set"),
+            client_message_span,
+        )?;
+        self.notify_client_activity();
+        Ok(())
+    }
+
+    /// Returns the serialized envelope of the client message only if it is a call to the method expected in the current state of the session,
+    /// addressed to the canister of the session and signed by the client of the session
+    /// Unless disabled, the envelope is also verified as the IC would, so that the envelopes refused by the IC are not relayed
+    fn get_serialized_session_envelope(&self, message: Message) -> Result<Vec<u8>, IcWsError> {
+        let client_request = get_client_request(message)?;
+        let canister_id = self.canister_id.expect("must be set");
+        let expected_method_name = match self.session_state {
+            IcWsSessionState::Setup(_) => WS_OPEN_METHOD_NAME,
+            _ => WS_MESSAGE_METHOD_NAME,
+        };
+        check_session_envelope(
+            &client_request.envelope.content,
+            &canister_id,
+            &self
+                .client_key
+                .as_ref()
+                .expect("must be set")
+                .client_principal,
+            expected_method_name,
+        )
+        .map_err(reject_envelope)?;
+        if self.verify_envelopes {
+            verify_envelope(
+                &self.agent,
+                &client_request.envelope,
+                &canister_id,
+                current_time(),
+            )
+            .map_err(reject_envelope)?;
+        }
+        serialize(client_request.envelope)
     }
 
     async fn handle_open_transition(
@@ -417,18 +923,168 @@ impl<S: AsyncRead + AsyncWrite + Unpin> ClientSession<S> {
     }
 
     async fn send_ws_message_to_client(&mut self, message: Message) -> Result<(), IcWsError> {
-        if let Err(e) = self.ws_write.send(message).await {
-            return Err(IcWsError::WebSocket(e.to_string()));
+        // the writer task drops its side of the channel only after failing to write to the WebSocket
+        if let Err(e) = self.ws_write_tx.send(message).await {
+            return Err(IcWsError::WebSocket(format!(
+                "Could not write to the WebSocket: {:?}",
+                e.to_string()
+            )));
         }
         Ok(())
     }
 
     async fn close_ws_session(&mut self) -> Result<(), IcWsError> {
-        if let Err(e) = self.ws_write.close().await {
-            return Err(IcWsError::WebSocket(e.to_string()));
+        self.send_ws_message_to_client(Message::Close(None)).await
+    }
+}
+
+/// Writes the messages received from the client session to the WebSocket, in the same order in which they are received
+/// Terminates after sending a close frame, after failing to write to the WebSocket or once the client session is dropped
+async fn write_ws_messages<S: AsyncRead + AsyncWrite + Unpin>(
+    mut ws_write: SplitSink<WebSocketStream<S>, Message>,
+    mut ws_write_rx: Receiver<Message>,
+) {
+    while let Some(message) = ws_write_rx.recv().await {
+        let is_close = message.is_close();
+        if let Err(e) = ws_write.send(message).await {
+            warn!("Could not write to the WebSocket: {:?}", e);
+            return;
+        }
+        if is_close {
+            break;
         }
-        Ok(())
     }
+    // the WebSocket might have already been closed by sending a close frame
+    // therefore, there is no need to report an error
+    let _ = ws_write.close().await;
+    trace!("Terminated writer task");
+}
+
+/// Relays the client messages to the IC, in the same order in which they are queued by the client session
+/// Terminates after failing to relay a message or once the client session is dropped
+async fn relay_client_messages(
+    agent: Arc<Agent>,
+    mut client_messages_rx: Receiver<ClientMessage>,
+    relay_errors_tx: Sender<IcWsError>,
+) {
+    while let Some(ClientMessage {
+        serialized_envelope,
+        canister_id,
+        span: client_message_span,
+    }) = client_messages_rx.recv().await
+    {
+        // messages are relayed one at the time as the canister expects them to be received in order
+        if let Err(e) = relay_envelope_to_canister(&agent, serialized_envelope, canister_id)
+            .instrument(client_message_span.clone())
+            .await
+        {
+            // the session will be terminated, therefore there is no need to relay the following messages
+            // if the session has already been dropped, there is nobody to report the error to
+            let _ = relay_errors_tx
+                .send(IcWsError::IcWsProtocol(e.to_string()))
+                .await;
+            return;
+        }
+        client_message_span.in_scope(|| {
+            // there is no need to relay the response back to the client as the response to a request to the /call enpoint is not certified by the canister
+            // and therefore could be manufactured by the gateway
+            trace!("Relayed client message to canister");
+        });
+    }
+    trace!("Terminated relayer task");
+}
+
+/// Waits until either a ping has to be sent to the client or the client is considered unresponsive
+/// Waits forever if no pings are sent to the client
+async fn wait_for_keepalive_deadline(keepalive: &Option<Keepalive>) {
+    match keepalive {
+        Some(keepalive) => sleep_until(keepalive.deadline()).await,
+        None => pending().await,
+    }
+}
+
+/// Receives the next message from the poller
+/// Waits forever if the channel has not been set yet, as the client has not been added to the gateway state
+async fn recv_from_poller<T>(poller_rx: &mut Option<Receiver<T>>) -> Option<T> {
+    match poller_rx {
+        Some(poller_rx) => poller_rx.recv().await,
+        None => pending().await,
+    }
+}
+
+/// Returns the close frame sent to the client when the gateway closes the session
+fn get_close_frame(close_reason: ClientCloseReason) -> CloseFrame<'static> {
+    match close_reason {
+        // the client can reconnect, but it has missed some of the canister messages
+        ClientCloseReason::SlowClient => CloseFrame {
+            code: CloseCode::Library(4000),
+            reason: "Client did not read the canister messages fast enough".into(),
+        },
+        // the client can try to reconnect later
+        ClientCloseReason::TooManyClients => CloseFrame {
+            code: CloseCode::Again,
+            reason: "Canister reached the maximum number of clients".into(),
+        },
+        ClientCloseReason::CanisterNotAllowed => CloseFrame {
+            code: CloseCode::Policy,
+            reason: "Canister is not served by this gateway".into(),
+        },
+        // the client can reconnect, but it has to send messages at a lower rate
+        ClientCloseReason::RateLimited => CloseFrame {
+            code: CloseCode::Library(4001),
+            reason: "Client exceeded the message rate limit".into(),
+        },
+        // the client has to open a new session
+        ClientCloseReason::SessionNotResumable => CloseFrame {
+            code: CloseCode::Library(4002),
+            reason: "Session cannot be resumed".into(),
+        },
+        // the client has to open a new session with a different nonce
+        ClientCloseReason::DuplicateClientKey => CloseFrame {
+            code: CloseCode::Library(4003),
+            reason: "Client key is already used by another session".into(),
+        },
+        // the client opened a new session with the same client key, which is served instead
+        ClientCloseReason::Replaced => CloseFrame {
+            code: CloseCode::Library(4004),
+            reason: "Session replaced by a new session with the same client key".into(),
+        },
+        ClientCloseReason::InvalidEnvelope => CloseFrame {
+            code: CloseCode::Protocol,
+            reason: "Envelope does not belong to the session".into(),
+        },
+        // the client can reconnect, but it has missed some of the canister messages
+        ClientCloseReason::MessagesLost => CloseFrame {
+            code: CloseCode::Library(4005),
+            reason: "Client missed some of the canister messages".into(),
+        },
+        ClientCloseReason::Kicked => CloseFrame {
+            code: CloseCode::Library(4006),
+            reason: "Client kicked by the gateway operator".into(),
+        },
+        // the client can reconnect once its connection is restored
+        ClientCloseReason::Unresponsive => CloseFrame {
+            code: CloseCode::Library(4007),
+            reason: "Client did not respond to a ping in time".into(),
+        },
+    }
+}
+
+async fn relay_envelope_to_canister(
+    agent: &Agent,
+    serialized_envelope: Vec<u8>,
+    canister_id: Principal,
+) -> Result<(), AgentError> {
+    agent
+        .update_signed(canister_id, serialized_envelope)
+        .await?;
+    Ok(())
+}
+
+/// Counts the envelope rejected for not belonging to the session of the client and returns the corresponding error
+fn reject_envelope(invalid_envelope: InvalidEnvelope) -> IcWsError {
+    counter!("invalid_envelopes", "reason" => invalid_envelope.as_str()).increment(1);
+    IcWsError::InvalidEnvelope(invalid_envelope)
 }
 
 fn serialize<S: Serialize>(message: S) -> Result<Vec<u8>, IcWsError> {
@@ -1,23 +1,72 @@
-use std::collections::HashMap;
 use crate::{
-    canister_poller::CanisterPoller,
-    client_session::{ClientSession, IcWsError, IcWsSessionState},
-    ws_listener::ClientId,
+    canister_access::CanisterAccessControl,
+    canister_policies::CanisterPolicies,
+    canister_poller::{CanisterPoller, PollerConfig},
+    client_session::{
+        ClientSession, ClientSessionConfig, IcWsError, IcWsSessionState, KeepaliveConfig,
+    },
+    close_outbox::CloseOutbox,
+    connection_limiter::HandshakePermit,
+    rate_limiter::ClientRateLimiter,
+    session_resumption::{DetachedSession, SessionResumption},
+    upgrade_request::UpgradeRequestPolicy,
+    ws_listener::{ClientAddress, ClientId},
+};
+use canister_utils::{ClientKey, IcWsCanisterMessage};
+use gateway_state::{
+    CanisterPrincipal, ClientCloseReason, ClientInsertionError, ClientRemovalResult, ClientSender,
+    DuplicateClientKeyPolicy, GatewayState, PollerState,
 };
-use canister_utils::{ws_close, CanisterWsCloseArguments, ClientKey, IcWsCanisterMessage};
-use futures_util::StreamExt;
-use gateway_state::{CanisterPrincipal, ClientRemovalResult, GatewayState, PollerState};
 use ic_agent::Agent;
-use std::sync::Arc;
-use std::time::Instant;
-use metrics::{gauge, histogram};
+use metrics::{counter, gauge, histogram};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
+    select,
     sync::mpsc::{self, Receiver, Sender},
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::handshake::server::{Request, Response},
 };
-use tokio_tungstenite::accept_async;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::{debug, field, info, span, warn, Instrument, Level, Span};
 
+/// Configuration of the client session handlers and of the pollers they start
+#[derive(Debug, Clone)]
+pub struct ClientSessionHandlerConfig {
+    /// Maximum number of client messages per session waiting to be relayed to the IC
+    pub client_messages_pipeline_capacity: usize,
+    /// Policies applied to the clients and to the pollers of each canister
+    pub canister_policies: Arc<CanisterPolicies>,
+    /// Access control of the canisters which the clients can connect to
+    pub canister_access: Arc<CanisterAccessControl>,
+    /// Configuration of the pollers
+    pub poller_config: PollerConfig,
+    /// Rate limiter of the messages sent by the clients
+    pub client_rate_limiter: Arc<ClientRateLimiter>,
+    /// Time in milliseconds given to the clients to send the WebSocket upgrade request
+    pub upgrade_timeout_ms: u64,
+    /// Policy applied to the WebSocket upgrade requests
+    pub upgrade_request_policy: Arc<UpgradeRequestPolicy>,
+    /// Configuration of the pings sent to the clients, no pings are sent if None
+    pub keepalive_config: Option<KeepaliveConfig>,
+    /// Sessions waiting to be resumed by their clients, the sessions are never resumed if None
+    pub session_resumption: Option<Arc<SessionResumption>>,
+    /// Policy applied to the sessions opened with the same client key as a connected session
+    pub duplicate_client_key_policy: DuplicateClientKeyPolicy,
+    /// Whether the signatures and expiries of the client envelopes are verified before relaying them to the IC
+    pub verify_envelopes: bool,
+    /// Outbox of the ws_close calls for the clients which disconnected
+    pub close_outbox: Arc<CloseOutbox>,
+}
+
 /// Handler of a client IC WS session
 pub struct ClientSessionHandler {
     /// Identifier of the client connection
@@ -26,8 +75,12 @@ pub struct ClientSessionHandler {
     agent: Arc<Agent>,
     /// State of the gateway
     gateway_state: GatewayState,
-    /// Polling interval in milliseconds
-    polling_interval_ms: u64,
+    /// Configuration of the client session and of the poller
+    config: ClientSessionHandlerConfig,
+    /// Token cancelled when the gateway is shutting down
+    shutdown_token: CancellationToken,
+    /// Tracker of the client session handlers, their writer and relayer tasks and the pollers tasks
+    task_tracker: TaskTracker,
 }
 
 impl ClientSessionHandler {
@@ -35,62 +88,117 @@ impl ClientSessionHandler {
         id: ClientId,
         agent: Arc<Agent>,
         gateway_state: GatewayState,
-        polling_interval_ms: u64,
+        config: ClientSessionHandlerConfig,
+        shutdown_token: CancellationToken,
+        task_tracker: TaskTracker,
     ) -> Self {
         Self {
             id,
             agent,
             gateway_state,
-            polling_interval_ms,
+            config,
+            shutdown_token,
+            task_tracker,
         }
     }
 
     /// Upgrades to a WebSocket connection and handles the client session
-    pub async fn start_session<S: AsyncRead + AsyncWrite + Unpin>(
+    pub async fn start_session<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         &mut self,
         stream: S,
+        client_address: ClientAddress,
+        handshake_permit: HandshakePermit,
     ) -> Result<(), String> {
-        match accept_async(stream).await {
+        let upgrade_request_policy = &self.config.upgrade_request_policy;
+        // held until the session terminates if the address of the client is known only from the upgrade request
+        let mut _forwarded_ip_connection_permit = None;
+        // set once the upgrade request is received if the address of the client is known only from the upgrade request
+        let mut client_ip = match &client_address {
+            ClientAddress::Known(client_addr) => Some(client_addr.ip()),
+            ClientAddress::Forwarded { .. } => None,
+        };
+        // the upgrade request is refused before any IC WS session starts if it does not comply with the policy
+        let check_upgrade_request = |request: &Request, response: Response| {
+            if let Err(rejection) = upgrade_request_policy.validate(request) {
+                counter!("connections_rejected", "reason" => rejection.as_str()).increment(1);
+                return Err(rejection.to_error_response());
+            }
+            if let ClientAddress::Forwarded {
+                proxy_addr,
+                is_proxy_ip_limited,
+                trusted_proxies,
+                connection_limiter,
+            } = &client_address
+            {
+                // the request was sent by the proxy itself if it does not have the X-Forwarded-For header
+                let (forwarded_client_ip, is_ip_limited) =
+                    match trusted_proxies.get_forwarded_client_ip(request) {
+                        Some(forwarded_client_ip) => (forwarded_client_ip, true),
+                        None => (proxy_addr.ip(), *is_proxy_ip_limited),
+                    };
+                Span::current().record("client_addr", field::debug(forwarded_client_ip));
+                if !is_ip_limited {
+                    client_ip = Some(forwarded_client_ip);
+                    return Ok(response);
+                }
+                match connection_limiter.try_accept_from_ip(forwarded_client_ip) {
+                    Ok(ip_connection_permit) => {
+                        _forwarded_ip_connection_permit = Some(ip_connection_permit);
+                        client_ip = Some(forwarded_client_ip);
+                    },
+                    Err(rejection) => {
+                        counter!("connections_rejected", "reason" => rejection.as_str())
+                            .increment(1);
+                        return Err(rejection.to_error_response());
+                    },
+                }
+            }
+            Ok(response)
+        };
+        // a client which never sends the upgrade request must not hold the connection forever
+        let ws_stream = timeout(
+            Duration::from_millis(self.config.upgrade_timeout_ms),
+            accept_hdr_async(stream, check_upgrade_request),
+        )
+        .await;
+        // release the permit as soon as the handshake completes, so that another connection can perform it
+        drop(handshake_permit);
+        let Ok(ws_stream) = ws_stream else {
+            counter!("connections_rejected", "reason" => "upgrade_timeout").increment(1);
+            return Err(String::from("WebSocket upgrade timed out"));
+        };
+        match ws_stream {
             Ok(ws_stream) => {
                 debug!("Accepted WebSocket connection");
 
-                let (ws_write, ws_read) = ws_stream.split();
-
-                // [client connection handler task]        [poller task]
-                // client_channel_rx                <----- client_channel_tx
-
-                // channel used by the poller task to send canister updates from the poller to the client session handler task
-                // which will then forward it to the client via the WebSocket connection
-                let (client_channel_tx, client_channel_rx): (
-                    Sender<IcWsCanisterMessage>,
-                    Receiver<IcWsCanisterMessage>,
-                ) = mpsc::channel(100);
-
                 let client_session_span = span!(parent: &Span::current(), Level::TRACE, "Client Session", canister_id = field::Empty);
 
-                let client_session = ClientSession::init(
+                let mut client_session = ClientSession::init(
                     self.id,
-                    self.agent.get_principal().expect("Principal should be set"),
-                    client_channel_rx,
-                    ws_write,
-                    ws_read,
+                    ws_stream,
                     Arc::clone(&self.agent),
+                    self.shutdown_token.clone(),
+                    &self.task_tracker,
+                    ClientSessionConfig {
+                        client_messages_pipeline_capacity: self
+                            .config
+                            .client_messages_pipeline_capacity,
+                        rate_limiter: self.config.client_rate_limiter.session_rate_limiter(),
+                        keepalive_config: self.config.keepalive_config,
+                    },
                 )
                 .instrument(client_session_span.clone())
                 .await
                 .map_err(|e| format!("Client session error: {:?}", e))?;
+                client_session.set_verify_envelopes(self.config.verify_envelopes);
 
                 client_session_span.in_scope(|| {
                     debug!("Client session initialized");
                 });
 
-                self.handle_client_session(
-                    client_session,
-                    Some(client_channel_tx),
-                    client_session_span,
-                )
-                .instrument(Span::current())
-                .await?;
+                self.handle_client_session(client_session, client_session_span, client_ip)
+                    .instrument(Span::current())
+                    .await?;
                 Ok(())
             },
             Err(e) => {
@@ -101,15 +209,13 @@ impl ClientSessionHandler {
     }
 
     /// Handles the client session by reacting to the changes in the session state
-    async fn handle_client_session<S: AsyncRead + AsyncWrite + Unpin>(
+    async fn handle_client_session<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         &mut self,
         mut client_session: ClientSession<S>,
-        // passed in an option so that it can be taken once after session Setup without cloning it
-        // it is important not to clone it as otherwise the client session will not receive None in case of a poller error
-        mut client_channel_tx: Option<Sender<IcWsCanisterMessage>>,
         client_session_span: Span,
+        client_ip: Option<IpAddr>,
     ) -> Result<(), String> {
-        let mut clients_session_time: HashMap<ClientKey, Instant>  = HashMap::new();
+        let mut clients_session_time: HashMap<ClientKey, Instant> = HashMap::new();
 
         // keeps trying to update the client session state
         // if a new state is returned, execute the corresponding logic
@@ -136,16 +242,102 @@ impl ClientSessionHandler {
 
                     let canister_id = self.get_canister_id(&client_session);
                     let client_key = self.get_client_key(&client_session);
-                    let new_poller_state = self
+
+                    // the WS open message of a client connecting to a canister which is not allowed
+                    // is neither added to the gateway state nor relayed to the IC
+                    if !self.config.canister_access.is_allowed(&canister_id) {
+                        client_session
+                            .close(ClientCloseReason::CanisterNotAllowed)
+                            .instrument(client_session_span.clone())
+                            .await;
+                        return Err(format!(
+                            "Canister {} is not allowed by the gateway",
+                            canister_id
+                        ));
+                    }
+
+                    let canister_policy = self.config.canister_policies.get(&canister_id);
+
+                    client_session
+                        .set_max_client_message_size(canister_policy.max_client_message_size);
+                    if let Err(e) = client_session.check_client_message_size(&ws_open_message) {
+                        return Err(format!("Invalid WS open message: {:?}", e));
+                    }
+
+                    // [client connection handler task]        [poller task]
+                    // client_channel_rx                <----- client_channel_tx
+                    // client_close_rx                  <----- client_close_tx
+
+                    // channel used by the poller task to send canister updates from the poller to the client session handler task
+                    // which will then forward it to the client via the WebSocket connection
+                    // the poller never waits for the channel to have capacity, the client is considered slow if it is full
+                    let (client_channel_tx, client_channel_rx): (
+                        Sender<IcWsCanisterMessage>,
+                        Receiver<IcWsCanisterMessage>,
+                    ) = mpsc::channel(canister_policy.client_queue_capacity);
+
+                    // channel used by the poller task to request the client session to be closed
+                    let (client_close_tx, client_close_rx): (
+                        Sender<ClientCloseReason>,
+                        Receiver<ClientCloseReason>,
+                    ) = mpsc::channel(1);
+
+                    // the entry of the session in the gateway state is identified by the connection which opened it
+                    client_session.set_poller_channels(client_channel_rx, client_close_rx, self.id);
+
+                    let new_poller_state = match self
                         .gateway_state
                         .insert_client_channel_and_get_new_poller_state(
                             canister_id,
                             client_key.clone(),
-                            // important not to clone 'client_channel_tx' as otherwise the client session will not receive None in case of a poller error
-                            client_channel_tx.take().expect("must be set only once"),
-                            client_session_span.clone(),
-                        );
+                            // important not to clone the sending sides of the channels as otherwise the client session will not receive None in case of a poller error
+                            ClientSender::new(
+                                client_channel_tx,
+                                client_session_span.clone(),
+                                client_close_tx,
+                                self.id,
+                                client_ip,
+                            ),
+                            canister_policy.max_clients,
+                            self.config.duplicate_client_key_policy,
+                        ) {
+                        Ok(new_poller_state) => new_poller_state,
+                        Err(ClientInsertionError::DuplicateClientKey) => {
+                            // the client has not been added to the gateway state, therefore there is nothing to clean up
+                            counter!("duplicate_client_keys", "policy" => self.config.duplicate_client_key_policy.as_str()).increment(1);
+                            client_session
+                                .close(ClientCloseReason::DuplicateClientKey)
+                                .instrument(client_session_span.clone())
+                                .await;
+                            return Err(format!(
+                                "Client key {} is already used by another session",
+                                client_key
+                            ));
+                        },
+                        Err(ClientInsertionError::MaxClientsReached) => {
+                            // the client has not been added to the gateway state, therefore there is nothing to clean up
+                            client_session
+                                .close(ClientCloseReason::TooManyClients)
+                                .instrument(client_session_span.clone())
+                                .await;
+                            return Err(format!(
+                                "Canister {} reached the maximum number of clients",
+                                canister_id
+                            ));
+                        },
+                    };
                     debug!("Client added to gateway state");
+                    // a ws_close call left pending by a previous session of the client, possibly before the gateway restarted,
+                    // must not be retried once the client registers again
+                    self.config.close_outbox.cancel(canister_id, &client_key);
+
+                    // the poller might have already failed and been removed from the gateway state
+                    // if so, the session is going to be terminated as the client's channel has been dropped
+                    if let Some(client_activity) =
+                        self.gateway_state.get_client_activity_notifier(canister_id)
+                    {
+                        client_session.set_client_activity_notifier(client_activity);
+                    }
 
                     client_session_span.record("canister_id", canister_id.to_string());
 
@@ -160,7 +352,7 @@ impl ClientSessionHandler {
                         // if the message could not be relayed to the IC, remove the client from the gateway state
                         // before returning the error and terminating the session handler
                         self.gateway_state
-                            .remove_client(canister_id, client_key.clone());
+                            .remove_client(canister_id, client_key.clone(), self.id);
                         debug!("Client removed from gateway state");
 
                         return Err(format!("Could not relay WS open message to IC: {:?}", e))?;
@@ -182,6 +374,103 @@ impl ClientSessionHandler {
                     }
                     // do not return anything as the session is still alive
                 },
+                Ok(Some(IcWsSessionState::Resuming(ws_message))) => {
+                    let canister_id = self.get_canister_id(&client_session);
+                    let client_key = self.get_client_key(&client_session);
+                    client_session_span.record("canister_id", canister_id.to_string());
+
+                    let session_resumption =
+                        self.config
+                            .session_resumption
+                            .clone()
+                            .filter(|session_resumption| {
+                                session_resumption.is_detached(canister_id, &client_key)
+                            });
+                    let Some(session_resumption) = session_resumption else {
+                        client_session
+                            .close(ClientCloseReason::SessionNotResumable)
+                            .instrument(client_session_span.clone())
+                            .await;
+                        return Err(format!("Client {} has no session to resume", client_key));
+                    };
+
+                    if !self.config.canister_access.is_allowed(&canister_id) {
+                        client_session
+                            .close(ClientCloseReason::CanisterNotAllowed)
+                            .instrument(client_session_span.clone())
+                            .await;
+                        return Err(format!(
+                            "Canister {} is not allowed by the gateway",
+                            canister_id
+                        ));
+                    }
+
+                    client_session.set_max_client_message_size(
+                        self.config
+                            .canister_policies
+                            .get(&canister_id)
+                            .max_client_message_size,
+                    );
+                    if let Err(e) = client_session.check_client_message_size(&ws_message) {
+                        return Err(format!("Invalid session resumption message: {:?}", e));
+                    }
+
+                    // the IC accepts the envelope only if it is signed by the principal of the client and it has not expired,
+                    // which proves that the client owns the key of the detached session
+                    // the detached session is kept for its owner if the envelope is refused
+                    if let Err(e) = client_session
+                        .relay_client_message(ws_message)
+                        .instrument(client_session_span.clone())
+                        .await
+                    {
+                        // the session has already been closed if the gateway rejected the envelope
+                        if !matches!(e, IcWsError::InvalidEnvelope(_)) {
+                            client_session
+                                .close(ClientCloseReason::SessionNotResumable)
+                                .instrument(client_session_span.clone())
+                                .await;
+                        }
+                        return Err(format!(
+                            "Could not relay session resumption message to IC: {:?}",
+                            e
+                        ));
+                    }
+
+                    // the session might have expired or been resumed by another connection in the meantime
+                    let Some(DetachedSession {
+                        client_channel_rx,
+                        client_close_rx,
+                        session_token,
+                        ..
+                    }) = session_resumption.resume(canister_id, &client_key)
+                    else {
+                        client_session
+                            .close(ClientCloseReason::SessionNotResumable)
+                            .instrument(client_session_span.clone())
+                            .await;
+                        return Err(format!(
+                            "Session of client {} is not detached anymore",
+                            client_key
+                        ));
+                    };
+                    client_session.resume(client_channel_rx, client_close_rx, session_token);
+
+                    if let Some(client_activity) =
+                        self.gateway_state.get_client_activity_notifier(canister_id)
+                    {
+                        client_session.set_client_activity_notifier(client_activity);
+                    }
+
+                    client_session_span.in_scope(|| {
+                        debug!("Client session resumed");
+
+                        counter!("resumed_sessions").increment(1);
+                        gauge!("clients_connected").increment(1.0);
+
+                        clients_session_time.insert(client_key, Instant::now());
+                    });
+                    // do not return anything as the session is still alive
+                },
                 Ok(Some(IcWsSessionState::Open)) => {
                     client_session_span.in_scope(|| {
                         debug!("Client session opened");
@@ -195,28 +484,43 @@ impl ClientSessionHandler {
                     // do not return anything as the session is still alive
                 },
                 Ok(Some(IcWsSessionState::Closed)) => {
+                    // the session might be closed before being Setup (e.g. the client disconnects or the gateway shuts down while in Init state)
+                    // if so, the client has not been added to the gateway state and there is nothing to clean up
+                    let (Some(canister_id), Some(client_key), Some(session_token)) = (
+                        client_session.canister_id,
+                        client_session.client_key.clone(),
+                        client_session.session_token,
+                    ) else {
+                        debug!("Client session closed before Setup");
+                        return Ok(());
+                    };
+
                     client_session_span.in_scope(|| {
                         debug!("Client session closed");
 
-                        let client_key = self.get_client_key(&client_session);
-
-                        gauge!("clients_connected").decrement(1.0);
-
-                        let value = clients_session_time.get(&client_key.clone());
-
-                        let delta = value.unwrap().elapsed();
-                        histogram!("connection_duration", "client_key" => client_key.to_string()).record(delta);
-
+                        record_session_end(&mut clients_session_time, &client_key);
                     });
 
-                    let canister_id = self.get_canister_id(&client_session);
-                    let client_key = self.get_client_key(&client_session);
-                    // remove client from gateway state
-                    self.gateway_state
-                        .remove_client(canister_id, client_key.clone());
-                    debug!("Client removed from gateway state");
+                    // the client which lost its connection can resume the session within the grace period
+                    if self.detach_session(&mut client_session) {
+                        return Ok(());
+                    }
 
-                    self.call_ws_close(&canister_id, client_key).await;
+                    // remove client from gateway state, if it is present
+                    // the client might have already been dropped by the poller for being too slow
+                    // if so, the poller has already called ws_close
+                    // the session might also have been replaced by a new session with the same client key
+                    // if so, the client is still connected through the new session and the canister must not be notified
+                    if let ClientRemovalResult::Removed(client_key) = self
+                        .gateway_state
+                        .remove_client_if_exists(canister_id, client_key, session_token)
+                    {
+                        debug!("Client removed from gateway state");
+                        self.config
+                            .close_outbox
+                            .close(canister_id, client_key)
+                            .await;
+                    }
 
                     // return Ok as the session was closed correctly
                     return Ok(());
@@ -235,18 +539,36 @@ impl ClientSessionHandler {
                         warn!(err_msg);
                         return Err(err_msg);
                     }
-                    let canister_id = self.get_canister_id(&client_session);
-                    let client_key = self.get_client_key(&client_session);
+                    // the client which lost its connection can resume the session within the grace period
+                    if self.detach_session(&mut client_session) {
+                        client_session_span.in_scope(|| {
+                            record_session_end(
+                                &mut clients_session_time,
+                                &self.get_client_key(&client_session),
+                            );
+                        });
+                        return Err(format!("Client session detached: {:?}", e));
+                    }
                     // if the error is not due to a a failed poller
                     // remove client from poller state, if it is present
                     // error might have happened before the client session was Setup
                     // if so, there is no need to remove the client as it is not yet in the poller state
+                    let (Some(canister_id), Some(client_key), Some(session_token)) = (
+                        client_session.canister_id,
+                        client_session.client_key.clone(),
+                        client_session.session_token,
+                    ) else {
+                        return Err(format!("Client error before session Setup: {:?}", e));
+                    };
                     if let ClientRemovalResult::Removed(client_key) = self
                         .gateway_state
-                        .remove_client_if_exists(canister_id, client_key)
+                        .remove_client_if_exists(canister_id, client_key, session_token)
                     {
                         debug!("Client removed from gateway state");
-                        self.call_ws_close(&canister_id, client_key).await;
+                        self.config
+                            .close_outbox
+                            .close(canister_id, client_key)
+                            .await;
 
                         // return Err as the session had an error and cannot be updated anymore
                         return Err(format!("Client session error: {:?}", e));
@@ -276,21 +598,72 @@ impl ClientSessionHandler {
             .expect("must be set during Setup")
     }
 
-    async fn call_ws_close(&self, canister_id: &CanisterPrincipal, client_key: ClientKey) {
-        // call ws_close so that the client is removed from the canister
-        if let Err(e) = ws_close(
-            &self.agent,
-            &canister_id,
-            CanisterWsCloseArguments { client_key },
-        )
-        .await
-        {
-            // this might happen when the canister has already removed the client from its state
-            // due to an out of order client message, keep alive timeout or due to the dapp logic
-            warn!("Calling ws_close on canister failed: {}", e);
-        } else {
-            debug!("Canister closed connection with client");
+    /// Keeps the client in the gateway state if it lost its connection, so that it can resume its session on a new connection
+    /// The client is removed and ws_close is called on the canister only if the session is not resumed within the grace period
+    /// Returns false if the session cannot be resumed and the client has to be removed immediately
+    fn detach_session<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        &self,
+        client_session: &mut ClientSession<S>,
+    ) -> bool {
+        let Some(session_resumption) = &self.config.session_resumption else {
+            return false;
+        };
+        if !client_session.is_connection_lost() {
+            return false;
         }
+        // the channels are set once the client has been added to the gateway state
+        let Some((client_channel_rx, client_close_rx)) = client_session.take_poller_channels()
+        else {
+            return false;
+        };
+        let canister_id = self.get_canister_id(client_session);
+        let client_key = self.get_client_key(client_session);
+        session_resumption.detach(
+            client_key.clone(),
+            DetachedSession {
+                client_id: self.id,
+                canister_id,
+                client_channel_rx,
+                client_close_rx,
+                session_token: client_session
+                    .session_token
+                    .expect("must be set with the poller channels"),
+            },
+        );
+        counter!("detached_sessions").increment(1);
+        debug!("Client session detached");
+
+        let session_resumption = Arc::clone(session_resumption);
+        let close_outbox = Arc::clone(&self.config.close_outbox);
+        let gateway_state = self.gateway_state.clone();
+        let shutdown_token = self.shutdown_token.clone();
+        let client_id = self.id;
+        self.task_tracker.spawn(
+            async move {
+                select! {
+                    _ = sleep(session_resumption.grace_period()) => {},
+                    // the sessions cannot be resumed once the gateway is shutting down
+                    _ = shutdown_token.cancelled() => {},
+                }
+                // dropping the detached session discards the canister messages buffered for the client
+                let Some(DetachedSession { session_token, .. }) =
+                    session_resumption.expire(&client_key, client_id)
+                else {
+                    return;
+                };
+                counter!("expired_sessions").increment(1);
+                debug!("Detached client session expired");
+                // the client might have been dropped by the poller in the meantime
+                if let ClientRemovalResult::Removed(client_key) =
+                    gateway_state.remove_client_if_exists(canister_id, client_key, session_token)
+                {
+                    debug!("Client removed from gateway state");
+                    close_outbox.close(canister_id, client_key).await;
+                }
+            }
+            .instrument(Span::current()),
+        );
+        true
     }
 
     /// Starts a new canister poller
@@ -300,8 +673,10 @@ impl ClientSessionHandler {
         // spawn new canister poller task
         let agent = Arc::clone(&self.agent);
         let gateway_state = self.gateway_state.clone();
-        let polling_interval_ms = self.polling_interval_ms;
-        tokio::spawn(async move {
+        let poller_config = self.config.poller_config.clone();
+        let canister_policies = Arc::clone(&self.config.canister_policies);
+        let shutdown_token = self.shutdown_token.clone();
+        self.task_tracker.spawn(async move {
             // we pass both the whole gateway state and the poller state for the specific canister
             // the poller can access the poller state to determine which clients are connected
             // without having to lock the whole gateway state (TODO: check if true)
@@ -313,9 +688,11 @@ impl ClientSessionHandler {
                 canister_id,
                 poller_state,
                 gateway_state,
-                polling_interval_ms,
+                poller_config,
+                &canister_policies,
+                shutdown_token,
             );
-            if let Err(e) = poller.run_polling().await {
+            if let Err(e) = poller.run_supervised_polling().await {
                 warn!(
                     "Poller for canister {} terminated with error: {:?}",
                     canister_id, e
@@ -329,3 +706,17 @@ impl ClientSessionHandler {
         });
     }
 }
+
+/// Records the end of the session which was counted as opened
+fn record_session_end(
+    clients_session_time: &mut HashMap<ClientKey, Instant>,
+    client_key: &ClientKey,
+) {
+    // the session might be closed while in Setup state, before the connection was counted as opened
+    if let Some(session_start) = clients_session_time.remove(client_key) {
+        gauge!("clients_connected").decrement(1.0);
+
+        let delta = session_start.elapsed();
+        histogram!("connection_duration", "client_key" => client_key.to_string()).record(delta);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{canister_poller::is_recoverable_error, persistence::write_file_atomically};
+use canister_utils::{ws_close, CanisterWsCloseArguments, ClientKey, IcError};
+use futures_util::future::join_all;
+use gateway_state::CanisterPrincipal;
+use ic_agent::Agent;
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    future::pending,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::Notify,
+    time::{interval, sleep_until, Instant},
+};
+use tracing::{debug, error, warn};
+
+/// Path of the file storing the pending ws_close calls, under the data directory
+pub const CLOSE_OUTBOX_PATH: &str = "./data/close_outbox.json";
+
+/// Configuration of the retries of the ws_close calls
+#[derive(Debug, Clone)]
+pub struct CloseOutboxConfig {
+    /// Time to wait in milliseconds before retrying a failed ws_close call, doubled at each consecutive failure
+    pub retry_backoff_ms: u64,
+    /// Maximum time to wait in milliseconds before retrying a failed ws_close call
+    pub max_retry_backoff_ms: u64,
+    /// Maximum number of attempts of a ws_close call, after which the canister is left to remove the client on its own
+    pub max_attempts: u32,
+}
+
+/// ws_close call which has not succeeded yet, as persisted on disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingClose {
+    pub canister_id: CanisterPrincipal,
+    pub client_key: ClientKey,
+    /// Number of attempts which failed with a transient error
+    pub failed_attempts: u32,
+}
+
+/// Schedule of a pending ws_close call
+#[derive(Debug)]
+struct ScheduledClose {
+    /// Number of attempts which failed with a transient error
+    failed_attempts: u32,
+    /// Time at which the call is retried, None while an attempt is in progress
+    retry_at: Option<Instant>,
+}
+
+/// Outbox of the ws_close calls which notify the canisters that their clients disconnected
+/// The calls failing with a transient error, e.g. because the IC is briefly unreachable, are retried with an exponential backoff
+/// The pending calls are written to disk in batches, so that a restarted gateway retries the calls which did not succeed
+#[derive(Debug)]
+pub struct CloseOutbox {
+    /// Agent used to call ws_close
+    agent: Arc<Agent>,
+    /// Configuration of the retries
+    config: CloseOutboxConfig,
+    /// Path of the file storing the pending calls, not persisted if None
+    path: Option<PathBuf>,
+    /// Calls which have not succeeded yet
+    pending_closes: Mutex<HashMap<(CanisterPrincipal, ClientKey), ScheduledClose>>,
+    /// Whether the pending calls changed since they were last written to disk
+    is_dirty: AtomicBool,
+    /// Notified whenever a call is scheduled to be retried
+    retry_scheduled: Notify,
+}
+
+impl CloseOutbox {
+    /// Creates an outbox whose pending calls are not persisted
+    pub fn new(agent: Arc<Agent>, config: CloseOutboxConfig) -> Self {
+        Self {
+            agent,
+            config,
+            path: None,
+            pending_closes: Mutex::new(HashMap::new()),
+            is_dirty: AtomicBool::new(false),
+            retry_scheduled: Notify::new(),
+        }
+    }
+
+    /// Loads the calls left pending before the gateway restarted, which are retried immediately
+    /// If the file does not exist, there is no call to retry
+    pub fn load(
+        agent: Arc<Agent>,
+        config: CloseOutboxConfig,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut pending_closes = HashMap::new();
+        if path.exists() {
+            let content = fs::read_to_string(path).map_err(|e| {
+                format!("Could not read close outbox file {}: {}", path.display(), e)
+            })?;
+            let stored_closes: Vec<PendingClose> = serde_json::from_str(&content)
+                .map_err(|e| format!("Could not parse close outbox: {}", e))?;
+            let now = Instant::now();
+            for pending_close in stored_closes {
+                pending_closes.insert(
+                    (pending_close.canister_id, pending_close.client_key),
+                    ScheduledClose {
+                        failed_attempts: pending_close.failed_attempts,
+                        retry_at: Some(now),
+                    },
+                );
+            }
+        }
+        gauge!("close_outbox_depth").set(pending_closes.len() as f64);
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            pending_closes: Mutex::new(pending_closes),
+            ..Self::new(agent, config)
+        })
+    }
+
+    /// Calls ws_close on the canister so that the client is removed from its state
+    /// If the call fails with a transient error, it is retried in the background
+    pub async fn close(&self, canister_id: CanisterPrincipal, client_key: ClientKey) {
+        self.lock_pending_closes().insert(
+            (canister_id, client_key.clone()),
+            ScheduledClose {
+                failed_attempts: 0,
+                retry_at: None,
+            },
+        );
+        self.on_pending_closes_changed();
+        let result = call_ws_close(&self.agent, &canister_id, client_key.clone()).await;
+        self.handle_close_result(canister_id, client_key, result);
+    }
+
+    /// Schedules the ws_close call to be performed by the retry task, so that the caller does not wait for the canister
+    /// The call is pending, and therefore written to disk at the next flush, as soon as this returns
+    pub fn schedule_close(&self, canister_id: CanisterPrincipal, client_key: ClientKey) {
+        // a call which is already pending for the client is not rescheduled
+        self.lock_pending_closes()
+            .entry((canister_id, client_key))
+            .or_insert(ScheduledClose {
+                failed_attempts: 0,
+                retry_at: Some(Instant::now()),
+            });
+        self.on_pending_closes_changed();
+        self.retry_scheduled.notify_one();
+    }
+
+    /// Cancels the pending call for the client, which registered again with the canister
+    /// Otherwise, a later retry would remove the new registration of the client from the state of the canister
+    pub fn cancel(&self, canister_id: CanisterPrincipal, client_key: &ClientKey) {
+        let is_cancelled = self
+            .lock_pending_closes()
+            .remove(&(canister_id, client_key.clone()))
+            .is_some();
+        if is_cancelled {
+            debug!("Cancelled pending ws_close for client {}", client_key);
+            self.on_pending_closes_changed();
+        }
+    }
+
+    /// Returns the pending calls, sorted by canister and client
+    pub fn get_pending_closes(&self) -> Vec<PendingClose> {
+        let mut pending_closes: Vec<PendingClose> = self
+            .lock_pending_closes()
+            .iter()
+            .map(
+                |((canister_id, client_key), scheduled_close)| PendingClose {
+                    canister_id: *canister_id,
+                    client_key: client_key.clone(),
+                    failed_attempts: scheduled_close.failed_attempts,
+                },
+            )
+            .collect();
+        pending_closes.sort_by_key(|pending_close| {
+            (
+                pending_close.canister_id,
+                pending_close.client_key.client_principal,
+                pending_close.client_key.client_nonce,
+            )
+        });
+        pending_closes
+    }
+
+    /// Writes the pending calls to disk if they changed since they were last written
+    pub fn flush(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.is_dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // the calls are copied so that the sessions are not blocked while the file is written
+        let pending_closes = self.get_pending_closes();
+        let content = serde_json::to_vec_pretty(&pending_closes)
+            .expect("pending closes should always be serializable");
+        write_file_atomically(path, &content).map_err(|e| {
+            // the calls are written again at the next flush
+            self.is_dirty.store(true, Ordering::Release);
+            format!(
+                "Could not write close outbox file {}: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+
+    /// Retries the failed calls whose backoff elapsed, concurrently
+    async fn retry_due_closes(&self) {
+        let now = Instant::now();
+        let due_closes: Vec<(CanisterPrincipal, ClientKey)> = self
+            .lock_pending_closes()
+            .iter_mut()
+            .filter(|(_, scheduled_close)| {
+                scheduled_close
+                    .retry_at
+                    .is_some_and(|retry_at| retry_at <= now)
+            })
+            .map(|(key, scheduled_close)| {
+                // the call is not retried again while this attempt is in progress
+                scheduled_close.retry_at = None;
+                key.clone()
+            })
+            .collect();
+        join_all(
+            due_closes
+                .into_iter()
+                .map(|(canister_id, client_key)| async move {
+                    debug!("Retrying ws_close for client {}", client_key);
+                    let result = call_ws_close(&self.agent, &canister_id, client_key.clone()).await;
+                    self.handle_close_result(canister_id, client_key, result);
+                }),
+        )
+        .await;
+    }
+
+    /// Returns the earliest time at which a failed call is retried, if any
+    fn get_next_retry_at(&self) -> Option<Instant> {
+        self.lock_pending_closes()
+            .values()
+            .filter_map(|scheduled_close| scheduled_close.retry_at)
+            .min()
+    }
+
+    /// Removes the call if it succeeded or cannot succeed, otherwise schedules it to be retried
+    fn handle_close_result(
+        &self,
+        canister_id: CanisterPrincipal,
+        client_key: ClientKey,
+        result: Result<(), IcError>,
+    ) {
+        let key = (canister_id, client_key);
+        let mut pending_closes = self.lock_pending_closes();
+        match result {
+            Ok(()) => {
+                debug!("Canister closed connection with client");
+                pending_closes.remove(&key);
+            },
+            Err(e) if is_transient_close_error(&e) => {
+                counter!("ws_close_failures", "reason" => "transient").increment(1);
+                // the call might have been removed in the meantime by another attempt for the same client
+                let Some(scheduled_close) = pending_closes.get_mut(&key) else {
+                    return;
+                };
+                scheduled_close.failed_attempts += 1;
+                if scheduled_close.failed_attempts >= self.config.max_attempts {
+                    // the canister removes the client on its own once the keep alive timeout expires
+                    warn!(
+                        "Giving up calling ws_close on canister {} for client {} after {} attempts: {:?}",
+                        key.0, key.1, scheduled_close.failed_attempts, e
+                    );
+                    counter!("abandoned_ws_closes").increment(1);
+                    pending_closes.remove(&key);
+                } else {
+                    let retry_backoff = self.compute_retry_backoff(scheduled_close.failed_attempts);
+                    warn!(
+                        "Calling ws_close on canister failed, retrying in {:?}: {:?}",
+                        retry_backoff, e
+                    );
+                    scheduled_close.retry_at = Some(Instant::now() + retry_backoff);
+                    self.retry_scheduled.notify_one();
+                }
+            },
+            Err(e) => {
+                // this might happen when the canister has already removed the client from its state
+                // due to an out of order client message, keep alive timeout or due to the dapp logic
+                warn!("Calling ws_close on canister failed: {:?}", e);
+                counter!("ws_close_failures", "reason" => "rejected").increment(1);
+                pending_closes.remove(&key);
+            },
+        }
+        drop(pending_closes);
+        self.on_pending_closes_changed();
+    }
+
+    /// Computes the time to wait before retrying a call after 'failed_attempts' failures
+    fn compute_retry_backoff(&self, failed_attempts: u32) -> Duration {
+        let retry_backoff_ms = self
+            .config
+            .retry_backoff_ms
+            .saturating_mul(2u64.saturating_pow(failed_attempts - 1))
+            .min(self.config.max_retry_backoff_ms);
+        Duration::from_millis(retry_backoff_ms)
+    }
+
+    fn on_pending_closes_changed(&self) {
+        self.is_dirty.store(true, Ordering::Release);
+        gauge!("close_outbox_depth").set(self.lock_pending_closes().len() as f64);
+    }
+
+    fn lock_pending_closes(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<(CanisterPrincipal, ClientKey), ScheduledClose>> {
+        self.pending_closes
+            .lock()
+            .expect("close outbox lock should not be poisoned")
+    }
+}
+
+async fn call_ws_close(
+    agent: &Agent,
+    canister_id: &CanisterPrincipal,
+    client_key: ClientKey,
+) -> Result<(), IcError> {
+    ws_close(agent, canister_id, CanisterWsCloseArguments { client_key }).await
+}
+
+/// Returns true if the ws_close call might succeed if retried
+/// The canister rejecting the call, e.g. because the client is not registered anymore, is not transient
+fn is_transient_close_error(e: &IcError) -> bool {
+    match e {
+        IcError::Agent(e) => is_recoverable_error(e),
+        IcError::Candid(_) | IcError::Cdk(_) => false,
+    }
+}
+
+/// Retries the failed ws_close calls once their backoff elapsed
+pub async fn retry_failed_closes(close_outbox: Arc<CloseOutbox>) {
+    loop {
+        let next_retry_at = close_outbox.get_next_retry_at();
+        select! {
+            _ = sleep_until_retry(next_retry_at) => close_outbox.retry_due_closes().await,
+            // a call scheduled in the meantime might have to be retried earlier
+            _ = close_outbox.retry_scheduled.notified() => (),
+        }
+    }
+}
+
+async fn sleep_until_retry(retry_at: Option<Instant>) {
+    match retry_at {
+        Some(retry_at) => sleep_until(retry_at).await,
+        None => pending().await,
+    }
+}
+
+/// Periodically writes the pending ws_close calls which changed to disk
+pub async fn flush_close_outbox_periodically(
+    close_outbox: Arc<CloseOutbox>,
+    flush_interval: Duration,
+) {
+    let mut flush_interval = interval(flush_interval);
+    loop {
+        flush_interval.tick().await;
+        if let Err(e) = close_outbox.flush() {
+            error!("{}", e);
+        }
+    }
+}
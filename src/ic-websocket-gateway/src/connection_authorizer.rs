@@ -0,0 +1,50 @@
+use std::{collections::HashSet, net::SocketAddr};
+
+/// Identity of a client authenticated by the certificate it presented in the TLS handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Distinguished name of the subject of the certificate, e.g. "CN=internal-service, O=Example"
+    pub subject: String,
+}
+
+/// Hook deciding whether a connection which completed the TLS handshake can be upgraded to WebSocket
+pub trait ConnectionAuthorizer: Send + Sync {
+    /// 'client_certificate' is None if the client did not present a certificate or if TLS is disabled
+    fn is_authorized(
+        &self,
+        client_addr: &SocketAddr,
+        client_certificate: Option<&ClientCertificate>,
+    ) -> bool;
+}
+
+/// Authorizes the clients whose certificate subject is allowed
+#[derive(Debug, Default)]
+pub struct ClientCertificateSubjectAuthorizer {
+    /// If set, only the clients presenting a certificate with one of these subjects are authorized
+    allowed_subjects: Option<HashSet<String>>,
+}
+
+impl ClientCertificateSubjectAuthorizer {
+    /// Creates the authorizer from the allowed subjects, all the clients are authorized if the list is empty
+    pub fn new(allowed_subjects: Vec<String>) -> Self {
+        Self {
+            allowed_subjects: (!allowed_subjects.is_empty())
+                .then(|| allowed_subjects.into_iter().collect()),
+        }
+    }
+}
+
+impl ConnectionAuthorizer for ClientCertificateSubjectAuthorizer {
+    fn is_authorized(
+        &self,
+        _client_addr: &SocketAddr,
+        client_certificate: Option<&ClientCertificate>,
+    ) -> bool {
+        match &self.allowed_subjects {
+            Some(allowed_subjects) => client_certificate.is_some_and(|client_certificate| {
+                allowed_subjects.contains(&client_certificate.subject)
+            }),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::rate_limiter::{RateLimit, TokenBucket, IDLE_BUCKETS_REMOVAL_INTERVAL_MS};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::interval,
+};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::ErrorResponse,
+    http::{Response, StatusCode},
+};
+use tracing::trace;
+
+/// Permit to perform the TLS and WebSocket handshakes of a connection, released when dropped
+pub type HandshakePermit = OwnedSemaphorePermit;
+
+/// Limits on the incoming connections
+#[derive(Debug, Clone)]
+pub struct ConnectionLimiterConfig {
+    /// Maximum number of connections performing the TLS and WebSocket handshakes at the same time
+    pub max_concurrent_handshakes: usize,
+    /// Maximum number of open connections from the same IP, unlimited if None
+    pub max_connections_per_ip: Option<usize>,
+    /// Rate at which the same IP can open new connections, unlimited if None
+    pub new_connections_per_ip_rate_limit: Option<RateLimit>,
+}
+
+/// Reason why an incoming connection is rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRejection {
+    /// Too many connections are performing the handshakes
+    TooManyHandshakes,
+    /// The IP has too many open connections
+    TooManyConnectionsFromIp,
+    /// The IP is opening new connections too fast
+    ConnectionRateExceeded,
+}
+
+impl ConnectionRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionRejection::TooManyHandshakes => "too_many_handshakes",
+            ConnectionRejection::TooManyConnectionsFromIp => "too_many_connections_from_ip",
+            ConnectionRejection::ConnectionRateExceeded => "connection_rate_exceeded",
+        }
+    }
+
+    /// Returns the HTTP response refusing the upgrade request of a client whose address is known only from it
+    pub fn to_error_response(self) -> ErrorResponse {
+        let mut response = Response::new(Some(String::from(self.as_str())));
+        *response.status_mut() = match self {
+            ConnectionRejection::TooManyHandshakes => StatusCode::SERVICE_UNAVAILABLE,
+            ConnectionRejection::TooManyConnectionsFromIp
+            | ConnectionRejection::ConnectionRateExceeded => StatusCode::TOO_MANY_REQUESTS,
+        };
+        response
+    }
+}
+
+/// Number of open connections of each IP
+type ConnectionsPerIp = Arc<DashMap<IpAddr, usize>>;
+
+/// Slot taken by a connection among the ones of its IP, released when dropped
+pub struct IpConnectionPermit {
+    ip: IpAddr,
+    connections_per_ip: ConnectionsPerIp,
+}
+
+impl Drop for IpConnectionPermit {
+    fn drop(&mut self) {
+        // the entry is removed together with the last connection of the IP
+        // so that the map does not grow with the number of IPs ever seen
+        if let Entry::Occupied(mut entry) = self.connections_per_ip.entry(self.ip) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// Limiter of the incoming connections, checked before starting the handshakes
+pub struct ConnectionLimiter {
+    config: ConnectionLimiterConfig,
+    /// Permits of the connections performing the handshakes
+    handshake_semaphore: Arc<Semaphore>,
+    // the guards returned when locking the dashmaps are 'Send', therefore it is critical
+    // that they are not held accross .await points
+    connections_per_ip: ConnectionsPerIp,
+    new_connection_buckets: DashMap<IpAddr, TokenBucket>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: ConnectionLimiterConfig) -> Self {
+        Self {
+            handshake_semaphore: Arc::new(Semaphore::new(config.max_concurrent_handshakes)),
+            config,
+            connections_per_ip: Arc::new(DashMap::new()),
+            new_connection_buckets: DashMap::new(),
+        }
+    }
+
+    /// Checks whether a new connection from the IP can be accepted
+    /// The returned permits must be held until the handshakes complete and until the connection is closed, respectively
+    pub fn try_accept(
+        &self,
+        ip: IpAddr,
+    ) -> Result<(HandshakePermit, IpConnectionPermit), ConnectionRejection> {
+        // the handshakes are checked first so that a connection rejected because they are saturated
+        // does not use a token of the rate limit of its IP
+        let handshake_permit = self.try_acquire_handshake_permit()?;
+        // if the IP connection permit cannot be acquired, the handshake permit is dropped and therefore released
+        let ip_connection_permit = self.try_accept_from_ip(ip)?;
+        Ok((handshake_permit, ip_connection_permit))
+    }
+
+    /// Checks whether too many connections are performing the handshakes, regardless of their IP
+    /// Used for the connections relayed by a trusted proxy, whose IP is not known yet
+    pub fn try_acquire_handshake_permit(&self) -> Result<HandshakePermit, ConnectionRejection> {
+        Arc::clone(&self.handshake_semaphore)
+            .try_acquire_owned()
+            .map_err(|_| ConnectionRejection::TooManyHandshakes)
+    }
+
+    /// Checks the limits of the IP of a new connection
+    pub fn try_accept_from_ip(
+        &self,
+        ip: IpAddr,
+    ) -> Result<IpConnectionPermit, ConnectionRejection> {
+        let now = Instant::now();
+        if let Some(rate_limit) = self.config.new_connections_per_ip_rate_limit {
+            let is_allowed = self
+                .new_connection_buckets
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(rate_limit, now))
+                .try_acquire(now);
+            if !is_allowed {
+                return Err(ConnectionRejection::ConnectionRateExceeded);
+            }
+        }
+        self.try_acquire_ip_connection_permit(ip)
+    }
+
+    fn try_acquire_ip_connection_permit(
+        &self,
+        ip: IpAddr,
+    ) -> Result<IpConnectionPermit, ConnectionRejection> {
+        let is_limit_reached = |connections: usize| {
+            self.config
+                .max_connections_per_ip
+                .is_some_and(|max_connections_per_ip| connections >= max_connections_per_ip)
+        };
+        // the connection is counted while holding the lock on the entry
+        // so that concurrent connections from the same IP cannot exceed the limit
+        match self.connections_per_ip.entry(ip) {
+            Entry::Occupied(mut entry) => {
+                if is_limit_reached(*entry.get()) {
+                    return Err(ConnectionRejection::TooManyConnectionsFromIp);
+                }
+                *entry.get_mut() += 1;
+            },
+            Entry::Vacant(entry) => {
+                if is_limit_reached(0) {
+                    return Err(ConnectionRejection::TooManyConnectionsFromIp);
+                }
+                entry.insert(1);
+            },
+        }
+        Ok(IpConnectionPermit {
+            ip,
+            connections_per_ip: Arc::clone(&self.connections_per_ip),
+        })
+    }
+
+    /// Removes the buckets of the IPs which have not opened connections recently
+    pub fn remove_idle_buckets(&self) {
+        let now = Instant::now();
+        self.new_connection_buckets
+            .retain(|_, bucket| !bucket.is_full(now));
+        trace!(
+            "Connection limiter is tracking {} IPs with open connections and {} IPs opening connections",
+            self.connections_per_ip.len(),
+            self.new_connection_buckets.len()
+        );
+    }
+}
+
+/// Removes the buckets of the IPs which have not opened connections recently until the gateway terminates
+pub async fn remove_idle_connection_buckets_periodically(
+    connection_limiter: Arc<ConnectionLimiter>,
+) {
+    let mut removal_interval = interval(Duration::from_millis(IDLE_BUCKETS_REMOVAL_INTERVAL_MS));
+    loop {
+        removal_interval.tick().await;
+        connection_limiter.remove_idle_buckets();
+    }
+}
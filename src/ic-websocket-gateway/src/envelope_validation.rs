@@ -0,0 +1,96 @@
+use canister_utils::ClientPrincipal;
+use gateway_state::CanisterPrincipal;
+use ic_agent::agent::EnvelopeContent;
+use std::fmt;
+
+/// Canister method called by the WS open message
+pub const WS_OPEN_METHOD_NAME: &str = "ws_open";
+/// Canister method called by every other client message
+/// The clients do not call 'ws_close' as the gateway calls it on their behalf once their session is closed
+pub const WS_MESSAGE_METHOD_NAME: &str = "ws_message";
+
+/// Reason for which an envelope sent by a client is rejected by the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidEnvelope {
+    /// The content of the envelope is not of the Call variant
+    NotCall,
+    /// The envelope calls a canister other than the one of the session
+    CanisterMismatch,
+    /// The envelope is signed by a principal other than the one of the client of the session
+    SenderMismatch,
+    /// The envelope calls a method which the client is not expected to call at this point of the session
+    MethodNotAllowed,
+    /// The ingress expiry of the envelope is in the past
+    Expired,
+    /// The ingress expiry of the envelope is further in the future than allowed by the IC
+    ExpiryTooFar,
+    /// The envelope is not signed although its sender is not the anonymous principal
+    MissingSignature,
+    /// The sender of the envelope is not the self-authenticating principal of its public key
+    PrincipalMismatch,
+    /// The envelope or one of its delegations is signed with a key of an unsupported type
+    UnsupportedPublicKey,
+    /// The signature of the envelope or of one of its delegations is not valid
+    InvalidSignature,
+    /// The envelope has more delegations than allowed by the IC
+    TooManyDelegations,
+    /// One of the delegations of the envelope is expired
+    DelegationExpired,
+    /// One of the delegations of the envelope does not allow calling the canister of the session
+    DelegationTargetMismatch,
+}
+
+impl InvalidEnvelope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvalidEnvelope::NotCall => "not_call",
+            InvalidEnvelope::CanisterMismatch => "canister_mismatch",
+            InvalidEnvelope::SenderMismatch => "sender_mismatch",
+            InvalidEnvelope::MethodNotAllowed => "method_not_allowed",
+            InvalidEnvelope::Expired => "expired",
+            InvalidEnvelope::ExpiryTooFar => "expiry_too_far",
+            InvalidEnvelope::MissingSignature => "missing_signature",
+            InvalidEnvelope::PrincipalMismatch => "principal_mismatch",
+            InvalidEnvelope::UnsupportedPublicKey => "unsupported_public_key",
+            InvalidEnvelope::InvalidSignature => "invalid_signature",
+            InvalidEnvelope::TooManyDelegations => "too_many_delegations",
+            InvalidEnvelope::DelegationExpired => "delegation_expired",
+            InvalidEnvelope::DelegationTargetMismatch => "delegation_target_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for InvalidEnvelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Returns an error if the envelope is not a call to the expected method of the canister of the session,
+/// on behalf of the client of the session
+pub fn check_session_envelope(
+    content: &EnvelopeContent,
+    canister_id: &CanisterPrincipal,
+    client_principal: &ClientPrincipal,
+    expected_method_name: &str,
+) -> Result<(), InvalidEnvelope> {
+    let EnvelopeContent::Call {
+        canister_id: envelope_canister_id,
+        sender,
+        method_name,
+        ..
+    } = content
+    else {
+        return Err(InvalidEnvelope::NotCall);
+    };
+    if envelope_canister_id != canister_id {
+        return Err(InvalidEnvelope::CanisterMismatch);
+    }
+    if sender != client_principal {
+        return Err(InvalidEnvelope::SenderMismatch);
+    }
+    if method_name != expected_method_name {
+        return Err(InvalidEnvelope::MethodNotAllowed);
+    }
+    Ok(())
+}
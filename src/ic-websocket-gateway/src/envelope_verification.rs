@@ -0,0 +1,320 @@
+use crate::{certification::verify_certified_tree, envelope_validation::InvalidEnvelope};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use candid::Principal;
+use gateway_state::CanisterPrincipal;
+use ic_agent::{agent::Envelope, Agent};
+use ic_certification::{HashTree, LookupResult};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use x509_parser::{der_parser::oid::Oid, prelude::FromDer, x509::SubjectPublicKeyInfo};
+
+/// Maximum time between the creation of an envelope and its expiry accepted by the IC
+const MAX_INGRESS_TTL: Duration = Duration::from_secs(5 * 60);
+/// Maximum difference tolerated between the clock of the gateway and the clocks of the clients and of the IC
+const PERMITTED_DRIFT: Duration = Duration::from_secs(60);
+/// Maximum number of delegations accepted by the IC in the chain of an envelope
+const MAX_DELEGATIONS: usize = 20;
+/// Maximum number of target canisters accepted by the IC in a delegation
+const MAX_DELEGATION_TARGETS: usize = 1000;
+
+/// Algorithms of the public keys accepted by the IC, identified by their OID
+const ED25519_OID: &str = "1.3.101.112";
+const EC_PUBLIC_KEY_OID: &str = "1.2.840.10045.2.1";
+const P256_CURVE_OID: &str = "1.2.840.10045.3.1.7";
+const SECP256K1_CURVE_OID: &str = "1.3.132.0.10";
+const CANISTER_SIGNATURE_OID: &str = "1.3.6.1.4.1.56387.1.2";
+const WEBAUTHN_OID: &str = "1.3.6.1.4.1.56387.1.1";
+
+/// Labels and values of the fields of the COSE keys of the WebAuthn authenticators (RFC 8152)
+const COSE_KEY_TYPE_LABEL: i128 = 1;
+const COSE_ALGORITHM_LABEL: i128 = 3;
+const COSE_CURVE_LABEL: i128 = -1;
+const COSE_X_LABEL: i128 = -2;
+const COSE_Y_LABEL: i128 = -3;
+const COSE_EC2_KEY_TYPE: i128 = 2;
+const COSE_ES256_ALGORITHM: i128 = -7;
+const COSE_P256_CURVE: i128 = 1;
+/// Length of the authenticator data without any extension: hash of the relying party ID, flags and signature counter
+const MIN_AUTHENTICATOR_DATA_LEN: usize = 37;
+
+/// Signature produced by a canister, e.g. Internet Identity signing the delegations of its users
+#[derive(Deserialize)]
+struct CanisterSignature {
+    /// Certificate of the certified data of the canister
+    #[serde(with = "serde_bytes")]
+    certificate: Vec<u8>,
+    /// Tree of the signatures of the canister, whose root hash is the certified data of the canister
+    tree: HashTree,
+}
+
+/// Signature produced by a WebAuthn authenticator, e.g. the passkey of an Internet Identity user
+#[derive(Deserialize)]
+struct WebAuthnSignature {
+    /// Data of the authenticator, signed together with the hash of the client data
+    #[serde(with = "serde_bytes")]
+    authenticator_data: Vec<u8>,
+    /// Client data in JSON, whose challenge is the signed message
+    client_data_json: String,
+    /// DER encoded ECDSA signature
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+/// Client data of a WebAuthn signature
+#[derive(Deserialize)]
+struct WebAuthnClientData {
+    /// Message signed by the authenticator, encoded in base64url
+    challenge: String,
+}
+
+/// Returns the current time, as the time elapsed since the UNIX epoch
+pub fn current_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time should be after the UNIX epoch")
+}
+
+/// Returns an error if the IC would refuse the envelope calling the canister due to its expiry,
+/// its sender or its signature, including the signatures, expiries and targets of its delegations
+/// The agent is used to verify the certificates of the canister signatures
+pub fn verify_envelope(
+    agent: &Agent,
+    envelope: &Envelope,
+    canister_id: &CanisterPrincipal,
+    now: Duration,
+) -> Result<(), InvalidEnvelope> {
+    let content = &*envelope.content;
+
+    let ingress_expiry = Duration::from_nanos(content.ingress_expiry());
+    if ingress_expiry + PERMITTED_DRIFT < now {
+        return Err(InvalidEnvelope::Expired);
+    }
+    if ingress_expiry > now + MAX_INGRESS_TTL + PERMITTED_DRIFT {
+        return Err(InvalidEnvelope::ExpiryTooFar);
+    }
+
+    let sender = content.sender();
+    let (Some(sender_pubkey), Some(sender_sig)) = (&envelope.sender_pubkey, &envelope.sender_sig)
+    else {
+        // only the envelopes of the anonymous principal are not signed
+        if *sender == Principal::anonymous()
+            && envelope.sender_pubkey.is_none()
+            && envelope.sender_sig.is_none()
+            && envelope.sender_delegation.is_none()
+        {
+            return Ok(());
+        }
+        return Err(InvalidEnvelope::MissingSignature);
+    };
+    if *sender != Principal::self_authenticating(sender_pubkey) {
+        return Err(InvalidEnvelope::PrincipalMismatch);
+    }
+
+    // each delegation is signed by the key of the previous one, starting from the key of the sender,
+    // and the envelope is signed by the key of the last delegation
+    let delegations = envelope.sender_delegation.as_deref().unwrap_or_default();
+    if delegations.len() > MAX_DELEGATIONS {
+        return Err(InvalidEnvelope::TooManyDelegations);
+    }
+    let mut signing_pubkey = sender_pubkey.as_slice();
+    for signed_delegation in delegations {
+        let delegation = &signed_delegation.delegation;
+        verify_signature(
+            agent,
+            signing_pubkey,
+            &delegation.signable(),
+            &signed_delegation.signature,
+        )?;
+        if Duration::from_nanos(delegation.expiration) < now {
+            return Err(InvalidEnvelope::DelegationExpired);
+        }
+        if let Some(targets) = &delegation.targets {
+            if targets.len() > MAX_DELEGATION_TARGETS || !targets.contains(canister_id) {
+                return Err(InvalidEnvelope::DelegationTargetMismatch);
+            }
+        }
+        signing_pubkey = &delegation.pubkey;
+    }
+    verify_signature(
+        agent,
+        signing_pubkey,
+        &content.to_request_id().signable(),
+        sender_sig,
+    )
+}
+
+/// Returns an error if the signature of the message is not valid for the DER encoded public key
+fn verify_signature(
+    agent: &Agent,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), InvalidEnvelope> {
+    let Ok((_, public_key_info)) = SubjectPublicKeyInfo::from_der(public_key) else {
+        return Err(InvalidEnvelope::UnsupportedPublicKey);
+    };
+    let key = public_key_info.subject_public_key.data.as_ref();
+    let is_valid = match public_key_info.algorithm.algorithm.to_id_string().as_str() {
+        ED25519_OID => verify_ed25519_signature(key, message, signature),
+        EC_PUBLIC_KEY_OID => {
+            let curve = public_key_info
+                .algorithm
+                .parameters
+                .and_then(|parameters| Oid::try_from(parameters).ok())
+                .map(|curve| curve.to_id_string());
+            match curve.as_deref() {
+                Some(P256_CURVE_OID) => verify_p256_signature(key, message, signature),
+                Some(SECP256K1_CURVE_OID) => verify_secp256k1_signature(key, message, signature),
+                _ => return Err(InvalidEnvelope::UnsupportedPublicKey),
+            }
+        },
+        CANISTER_SIGNATURE_OID => verify_canister_signature(agent, key, message, signature),
+        WEBAUTHN_OID => {
+            // only the authenticators signing with ECDSA P-256 are supported
+            let Some(key) = parse_cose_p256_key(key) else {
+                return Err(InvalidEnvelope::UnsupportedPublicKey);
+            };
+            verify_webauthn_signature(&key, message, signature)
+        },
+        _ => return Err(InvalidEnvelope::UnsupportedPublicKey),
+    };
+    if !is_valid {
+        return Err(InvalidEnvelope::InvalidSignature);
+    }
+    Ok(())
+}
+
+fn verify_ed25519_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (Ok(public_key), Ok(signature)) = (
+        ed25519_dalek::VerifyingKey::try_from(public_key),
+        ed25519_dalek::Signature::from_slice(signature),
+    ) else {
+        return false;
+    };
+    public_key.verify_strict(message, &signature).is_ok()
+}
+
+fn verify_p256_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+    // the message is hashed with SHA-256 before verifying the signature
+    let (Ok(public_key), Ok(signature)) = (
+        VerifyingKey::from_sec1_bytes(public_key),
+        Signature::from_slice(signature),
+    ) else {
+        return false;
+    };
+    public_key.verify(message, &signature).is_ok()
+}
+
+fn verify_secp256k1_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+    // the message is hashed with SHA-256 before verifying the signature
+    let (Ok(public_key), Ok(signature)) = (
+        VerifyingKey::from_sec1_bytes(public_key),
+        Signature::from_slice(signature),
+    ) else {
+        return false;
+    };
+    public_key.verify(message, &signature).is_ok()
+}
+
+/// Verifies that the signing canister certified the signature of the message in its signatures tree
+fn verify_canister_signature(
+    agent: &Agent,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    // the public key is made of the length of the principal of the signing canister, the principal and a seed
+    let Some((&canister_id_len, public_key)) = public_key.split_first() else {
+        return false;
+    };
+    if public_key.len() < canister_id_len as usize {
+        return false;
+    }
+    let (canister_id, seed) = public_key.split_at(canister_id_len as usize);
+    let Ok(canister_id) = Principal::try_from_slice(canister_id) else {
+        return false;
+    };
+    let Ok(signature) = serde_cbor::from_slice::<CanisterSignature>(signature) else {
+        return false;
+    };
+    // the certificate must be signed by the IC and certify the signatures tree as the certified data of the signing canister
+    if verify_certified_tree(agent, &canister_id, &signature.certificate, &signature.tree).is_err()
+    {
+        return false;
+    }
+    let seed_hash = Sha256::digest(seed);
+    let message_hash = Sha256::digest(message);
+    matches!(
+        signature.tree.lookup_path([
+            b"sig".as_slice(),
+            seed_hash.as_slice(),
+            message_hash.as_slice(),
+        ]),
+        LookupResult::Found(_)
+    )
+}
+
+/// Returns the ECDSA P-256 key of a WebAuthn authenticator from its COSE key, if it is one
+fn parse_cose_p256_key(cose_key: &[u8]) -> Option<p256::ecdsa::VerifyingKey> {
+    let Ok(serde_cbor::Value::Map(cose_key)) = serde_cbor::from_slice(cose_key) else {
+        return None;
+    };
+    let get_field = |label| cose_key.get(&serde_cbor::Value::Integer(label));
+    let is_integer = |label, value| get_field(label) == Some(&serde_cbor::Value::Integer(value));
+    if !is_integer(COSE_KEY_TYPE_LABEL, COSE_EC2_KEY_TYPE)
+        || !is_integer(COSE_ALGORITHM_LABEL, COSE_ES256_ALGORITHM)
+        || !is_integer(COSE_CURVE_LABEL, COSE_P256_CURVE)
+    {
+        return None;
+    }
+    let (Some(serde_cbor::Value::Bytes(x)), Some(serde_cbor::Value::Bytes(y))) =
+        (get_field(COSE_X_LABEL), get_field(COSE_Y_LABEL))
+    else {
+        return None;
+    };
+    // the coordinates are converted to the uncompressed SEC1 encoding of the point
+    let sec1_key = [[0x04].as_slice(), x, y].concat();
+    p256::ecdsa::VerifyingKey::from_sec1_bytes(&sec1_key).ok()
+}
+
+/// Verifies that the authenticator signed its data and the client data, whose challenge must be the message
+fn verify_webauthn_signature(
+    public_key: &p256::ecdsa::VerifyingKey,
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    use p256::ecdsa::{signature::Verifier, Signature};
+
+    let Ok(signature) = serde_cbor::from_slice::<WebAuthnSignature>(signature) else {
+        return false;
+    };
+    if signature.authenticator_data.len() < MIN_AUTHENTICATOR_DATA_LEN {
+        return false;
+    }
+    let Ok(client_data) = serde_json::from_str::<WebAuthnClientData>(&signature.client_data_json)
+    else {
+        return false;
+    };
+    // the challenge might be padded even though base64url does not require it
+    let Ok(challenge) = URL_SAFE_NO_PAD.decode(client_data.challenge.trim_end_matches('=')) else {
+        return false;
+    };
+    if challenge != message {
+        return false;
+    }
+    let Ok(ecdsa_signature) = Signature::from_der(&signature.signature) else {
+        return false;
+    };
+    // the message is hashed with SHA-256 before verifying the signature
+    let signed_data = [
+        signature.authenticator_data.as_slice(),
+        Sha256::digest(signature.client_data_json.as_bytes()).as_slice(),
+    ]
+    .concat();
+    public_key.verify(&signed_data, &ecdsa_signature).is_ok()
+}
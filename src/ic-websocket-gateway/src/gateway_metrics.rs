@@ -1,7 +1,7 @@
 use std::error::Error;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
-use metrics::{describe_gauge, describe_histogram, gauge};
+use metrics::{describe_counter, describe_gauge, describe_histogram, gauge};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use metrics_util::{MetricKindMask};
 
@@ -21,6 +21,78 @@ pub fn init_metrics(port: Option<u16>) -> Result<(), Box<dyn Error>> {
         "connection_duration",
         "The duration of the client connection"
     );
+    describe_counter!(
+        "slow_clients",
+        "The number of clients disconnected because they did not read the canister messages fast enough"
+    );
+    describe_counter!(
+        "poller_restarts",
+        "The number of times a failed poller has been restarted"
+    );
+    describe_counter!(
+        "canister_certification_failures",
+        "The number of batches of polled messages discarded because their certification is not valid, by reason"
+    );
+    describe_counter!(
+        "nonce_discontinuities",
+        "The number of times the nonces of the polled messages were not consecutive, by kind (gap or backward)"
+    );
+    describe_counter!(
+        "out_of_sync_clients",
+        "The number of clients disconnected because they missed some of the canister messages"
+    );
+    describe_counter!(
+        "rate_limited_client_messages",
+        "The number of client messages exceeding a rate limit"
+    );
+    describe_counter!(
+        "unresponsive_clients",
+        "The number of client sessions closed because the client did not respond to a ping in time"
+    );
+    describe_counter!(
+        "connections_rejected",
+        "The number of incoming connections rejected before starting a session, by reason"
+    );
+    describe_counter!(
+        "duplicate_client_keys",
+        "The number of sessions rejected because their client key was already used by another session"
+    );
+    describe_counter!(
+        "replaced_sessions",
+        "The number of sessions closed because a new session with the same client key replaced them"
+    );
+    describe_counter!(
+        "invalid_envelopes",
+        "The number of client envelopes rejected by the gateway instead of being relayed to the IC"
+    );
+    describe_counter!(
+        "detached_sessions",
+        "The number of client sessions kept to be resumed after the client lost its connection"
+    );
+    describe_counter!(
+        "resumed_sessions",
+        "The number of detached client sessions resumed by their client on a new connection"
+    );
+    describe_counter!(
+        "expired_sessions",
+        "The number of detached client sessions not resumed within the grace period"
+    );
+    describe_gauge!(
+        "close_outbox_depth",
+        "The number of ws_close calls which have not succeeded yet"
+    );
+    describe_counter!(
+        "ws_close_failures",
+        "The number of failed ws_close calls, by reason (transient or rejected)"
+    );
+    describe_counter!(
+        "abandoned_ws_closes",
+        "The number of ws_close calls given up after failing the maximum number of attempts"
+    );
+    describe_counter!(
+        "kicked_clients",
+        "The number of clients kicked through the admin API"
+    );
 
     gauge!("clients_connected").set(0.0);
 
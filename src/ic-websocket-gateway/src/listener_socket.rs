@@ -0,0 +1,194 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+
+/// Address assigned to the peers of the connections accepted on a Unix domain socket,
+/// which are local processes (e.g. a reverse proxy running as a sidecar)
+pub const UNIX_SOCKET_PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Maximum number of connections waiting to be accepted by a listener
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Address on which a listener accepts the connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerAddress {
+    /// IPv4 or IPv6 address and port, e.g. 0.0.0.0:8080 or [::]:8080
+    Tcp(String),
+    /// Path of a Unix domain socket
+    Unix(PathBuf),
+}
+
+/// Definition of a listener, e.g. "wss://0.0.0.0:443" or "ws+unix:///run/ic-ws-gateway.sock"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerDefinition {
+    pub address: ListenerAddress,
+    /// Whether the clients perform the TLS handshake on the accepted connections
+    pub tls: bool,
+}
+
+impl FromStr for ListenerDefinition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, address) = s
+            .split_once("://")
+            .ok_or_else(|| format!("Invalid listener definition: {}", s))?;
+        if address.is_empty() {
+            return Err(format!("Missing address in listener definition: {}", s));
+        }
+        let (address, tls) = match scheme {
+            "ws" => (ListenerAddress::Tcp(address.to_string()), false),
+            "wss" => (ListenerAddress::Tcp(address.to_string()), true),
+            "ws+unix" => (ListenerAddress::Unix(PathBuf::from(address)), false),
+            "wss+unix" => (ListenerAddress::Unix(PathBuf::from(address)), true),
+            _ => return Err(format!("Invalid listener scheme: {}", scheme)),
+        };
+        Ok(Self { address, tls })
+    }
+}
+
+impl fmt::Display for ListenerDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "wss" } else { "ws" };
+        match &self.address {
+            ListenerAddress::Tcp(address) => write!(f, "{}://{}", scheme, address),
+            ListenerAddress::Unix(path) => write!(f, "{}+unix://{}", scheme, path.display()),
+        }
+    }
+}
+
+/// Socket on which a listener accepts the connections
+pub enum ListenerSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ListenerSocket {
+    /// Binds the socket, must be called from within the Tokio runtime
+    pub fn bind(address: &ListenerAddress) -> io::Result<Self> {
+        match address {
+            ListenerAddress::Tcp(address) => {
+                let socket_addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+                })?;
+                let socket = Socket::new(
+                    Domain::for_address(socket_addr),
+                    Type::STREAM,
+                    Some(Protocol::TCP),
+                )?;
+                // IPv6 listeners do not accept IPv4 connections,
+                // so that an IPv4 listener can be bound to the same port
+                if socket_addr.is_ipv6() {
+                    socket.set_only_v6(true)?;
+                }
+                socket.set_reuse_address(true)?;
+                socket.set_nonblocking(true)?;
+                socket.bind(&socket_addr.into())?;
+                socket.listen(LISTEN_BACKLOG)?;
+                Ok(ListenerSocket::Tcp(TcpListener::from_std(socket.into())?))
+            },
+            ListenerAddress::Unix(path) => {
+                // the socket file left behind by a previous run of the gateway prevents binding
+                if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+                {
+                    fs::remove_file(path)?;
+                }
+                Ok(ListenerSocket::Unix(UnixListener::bind(path)?))
+            },
+        }
+    }
+
+    /// Returns whether the socket is a Unix domain socket, whose peers do not have an IP
+    pub fn is_unix(&self) -> bool {
+        matches!(self, ListenerSocket::Unix(_))
+    }
+
+    /// Accepts a connection, returns its stream and the address of its peer
+    pub async fn accept(&self) -> io::Result<(SocketStream, SocketAddr)> {
+        match self {
+            ListenerSocket::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok((SocketStream::Tcp(stream), peer_addr))
+            },
+            ListenerSocket::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((SocketStream::Unix(stream), UNIX_SOCKET_PEER_ADDR))
+            },
+        }
+    }
+}
+
+/// Stream of a connection accepted by a listener
+pub enum SocketStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for SocketStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SocketStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            SocketStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SocketStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SocketStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            SocketStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SocketStream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            SocketStream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            SocketStream::Tcp(stream) => stream.is_write_vectored(),
+            SocketStream::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SocketStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            SocketStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SocketStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            SocketStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -1,24 +1,98 @@
 use crate::{
+    admin_api::{load_admin_api_token, start_admin_api, AdminApi},
+    canister_access::CanisterAccessControl,
+    canister_policies::{CanisterPolicies, CanisterPolicy},
+    canister_poller::{PollerConfig, SlowClientPolicy},
+    client_session::KeepaliveConfig,
+    client_session_handler::ClientSessionHandlerConfig,
+    close_outbox::{
+        flush_close_outbox_periodically, retry_failed_closes, CloseOutbox, CloseOutboxConfig,
+        CLOSE_OUTBOX_PATH,
+    },
+    connection_authorizer::ClientCertificateSubjectAuthorizer,
+    connection_limiter::{
+        remove_idle_connection_buckets_periodically, ConnectionLimiter, ConnectionLimiterConfig,
+    },
     gateway_metrics::init_metrics,
     gateway_tracing::{init_tracing, InitTracingResult},
+    listener_socket::{ListenerAddress, ListenerDefinition},
     manager::Manager,
-    ws_listener::TlsConfig,
+    poller_nonces::{flush_poller_nonces_periodically, PollerNonces, POLLER_NONCES_PATH},
+    rate_limiter::{
+        remove_idle_buckets_periodically, ClientRateLimiter, RateLimit, RateLimitAction,
+        RateLimiterConfig,
+    },
+    session_resumption::SessionResumption,
+    tls::{
+        create_tls_acceptor, load_sni_certificate_entries, reload_certificates_periodically,
+        ClientAuthConfig, ClientAuthMode, TlsCertificates, TlsConfig,
+    },
+    trusted_proxies::TrustedProxies,
+    upgrade_request::UpgradeRequestPolicy,
+    ws_listener::WsListenerConfig,
 };
+use candid::Principal;
+use gateway_state::DuplicateClientKeyPolicy;
 use ic_identity::{get_identity_from_key_pair, load_key_pair};
-use std::{fs, path::Path};
+use ipnet::IpNet;
+use std::{fs, net::SocketAddr, path::Path, sync::Arc, time::Duration};
 use structopt::StructOpt;
-use tracing::info;
+use tokio::{
+    select,
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+};
+use tracing::{error, info};
 
+mod admin_api;
+mod canister_access;
+mod canister_policies;
 mod canister_poller;
+mod certification;
 mod client_session;
 mod client_session_handler;
+mod close_outbox;
+mod connection_authorizer;
+mod connection_limiter;
+mod envelope_validation;
+mod envelope_verification;
 mod gateway_metrics;
 mod gateway_tracing;
+mod listener_socket;
 mod manager;
+mod persistence;
+mod poller_nonces;
+mod proxy_protocol;
+mod rate_limiter;
+mod session_resumption;
+mod tls;
+mod trusted_proxies;
+mod upgrade_request;
 mod ws_listener;
 
 mod tests {
+    mod admin_api;
+    mod canister_access;
+    mod canister_policies;
     mod canister_poller;
+    mod certification;
+    mod client_session;
+    mod close_outbox;
+    mod connection_authorizer;
+    mod connection_limiter;
+    mod envelope_validation;
+    mod envelope_verification;
+    mod listener_socket;
+    mod manager;
+    mod poller_nonces;
+    mod proxy_protocol;
+    mod rate_limiter;
+    mod session_resumption;
+    mod tls;
+    mod trusted_proxies;
+    mod upgrade_request;
 }
 
 #[derive(Debug, StructOpt)]
@@ -29,22 +103,292 @@ struct DeploymentInfo {
     ic_network_url: String,
 
     #[structopt(long, default_value = "0.0.0.0:8080")]
-    /// Address at which the WebSocket Gateway is reachable.
+    /// Address at which the WebSocket Gateway is reachable, with TLS if a TLS certificate is configured.
+    /// Ignored if `listener` is set.
     gateway_address: String,
 
+    #[structopt(long)]
+    /// Address at which the WebSocket Gateway is reachable, e.g. `wss://0.0.0.0:443`, `ws://[::]:8080`
+    /// or `ws+unix:///run/ic-ws-gateway.sock` (`wss` listeners require a TLS certificate). Can be repeated.
+    listener: Vec<ListenerDefinition>,
+
     #[structopt(long, default_value = "100")]
     /// Time interval (in milliseconds) at which the canisters are polled.
+    /// If `max_polling_interval` is set, it is the interval at which the active canisters are polled.
     polling_interval: u64,
 
+    #[structopt(long, default_value = "5000")]
+    /// Time (in milliseconds) after which a poll of a canister is considered timed out and is immediately retried.
+    polling_timeout: u64,
+
+    #[structopt(long)]
+    /// Enables adaptive polling: the polling interval of an idle canister grows up to this value (in milliseconds).
+    max_polling_interval: Option<u64>,
+
+    #[structopt(long, default_value = "2")]
+    /// Factor by which the polling interval of an idle canister grows after each poll returning no messages.
+    /// Must be greater than 1.
+    polling_interval_growth_factor: f64,
+
+    #[structopt(long, default_value = "32")]
+    /// Maximum number of messages of each client waiting to be relayed to the IC.
+    /// If a client exceeds it, its session is closed.
+    client_messages_pipeline_capacity: usize,
+
+    #[structopt(long, default_value = "100")]
+    /// Maximum number of canister messages of each client waiting to be sent to the client.
+    client_queue_capacity: usize,
+
+    #[structopt(long)]
+    /// Maximum number of clients connected to each canister. Unlimited if not set.
+    max_clients_per_canister: Option<usize>,
+
+    #[structopt(long)]
+    /// Maximum size (in bytes) of the messages sent by the clients. Unlimited if not set.
+    max_client_message_size: Option<usize>,
+
+    #[structopt(long)]
+    /// Path of the JSON file overriding the polling and client limits for specific canisters.
+    canister_policies_path: Option<String>,
+
+    #[structopt(long)]
+    /// Path of the file listing the only canisters the clients can connect to, one principal per line.
+    /// Reloaded upon SIGHUP.
+    canister_allowlist_path: Option<String>,
+
+    #[structopt(long)]
+    /// Path of the file listing the canisters the clients cannot connect to, one principal per line.
+    /// Reloaded upon SIGHUP.
+    canister_denylist_path: Option<String>,
+
+    #[structopt(long, default_value = "10000")]
+    /// Time (in milliseconds) given to the clients to send the WebSocket upgrade request once the connection is accepted.
+    upgrade_timeout: u64,
+
+    #[structopt(long, use_delimiter = true)]
+    /// Comma separated list of the `Origin` values allowed in the WebSocket upgrade requests. Any origin is allowed if not set.
+    allowed_origins: Vec<String>,
+
+    #[structopt(long, use_delimiter = true)]
+    /// Comma separated list of the host names allowed in the `Host` header of the WebSocket upgrade requests.
+    /// Any host is allowed if not set.
+    allowed_hosts: Vec<String>,
+
+    #[structopt(long, default_value = "30000")]
+    /// Time (in milliseconds) without receiving frames from a client after which the gateway sends it a ping.
+    /// Set to 0 to never send pings.
+    ping_interval: u64,
+
+    #[structopt(long, default_value = "10000")]
+    /// Time (in milliseconds) given to a client to respond to a ping before its session is closed.
+    pong_timeout: u64,
+
+    #[structopt(long, default_value = "reject", possible_values = &["reject", "replace"])]
+    /// Policy applied when a client opens a session with the same client key as a connected session:
+    /// `reject` closes the new WebSocket with code 4003, `replace` closes the existing WebSocket with code 4004.
+    duplicate_client_key_policy: DuplicateClientKeyPolicy,
+
+    #[structopt(long)]
+    /// Relays the client envelopes to the IC without verifying their signatures and expiries.
+    skip_envelope_verification: bool,
+
+    #[structopt(long, default_value = "0")]
+    /// Time (in milliseconds) during which the session of a client which lost its connection is kept,
+    /// so that the client can resume it on a new connection. Set to 0 to never resume the sessions.
+    session_resumption_grace_period: u64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Maximum number of connections performing the TLS and WebSocket handshakes at the same time.
+    /// Further connections are closed immediately.
+    max_concurrent_handshakes: usize,
+
+    #[structopt(long)]
+    /// Maximum number of open connections from the same IP. Unlimited if not set.
+    /// Not applied to the connections accepted on a Unix domain socket, unless a trusted proxy sends the address of their clients.
+    max_connections_per_ip: Option<usize>,
+
+    #[structopt(long)]
+    /// Minimum interval (in milliseconds) between the new connections from the same IP, once its burst is exhausted.
+    /// The new connections are not rate limited if not set.
+    /// Not applied to the connections accepted on a Unix domain socket, unless a trusted proxy sends the address of their clients.
+    min_connection_interval_per_ip: Option<u64>,
+
+    #[structopt(long, default_value = "10")]
+    /// Number of connections the same IP can open at once before being rate limited. Must be greater than 0.
+    connection_burst_per_ip: u32,
+
+    #[structopt(long, use_delimiter = true)]
+    /// Comma separated list of the networks (in CIDR notation, e.g. 10.0.0.0/8) of the proxies in front of the gateway
+    /// which are trusted to tell the address of the clients.
+    trusted_proxies: Vec<IpNet>,
+
+    #[structopt(long)]
+    /// Reads the address of the clients from the PROXY protocol (v1 or v2) header
+    /// which the trusted proxies send at the beginning of each connection.
+    proxy_protocol: bool,
+
+    #[structopt(long)]
+    /// Reads the address of the clients from the `X-Forwarded-For` header of the upgrade requests
+    /// sent by the trusted proxies.
+    trust_x_forwarded_for: bool,
+
+    #[structopt(long)]
+    /// Minimum interval (in milliseconds) between the messages of each client session, once its burst is exhausted.
+    /// The messages of the sessions are not rate limited if not set.
+    min_incoming_interval: Option<u64>,
+
+    #[structopt(long, default_value = "10")]
+    /// Number of messages each client session can send at once before being rate limited. Must be greater than 0.
+    incoming_burst: u32,
+
+    #[structopt(long)]
+    /// Minimum interval (in milliseconds) between the messages sent by all the sessions of each client principal,
+    /// once its burst is exhausted. The messages of the principals are not rate limited if not set.
+    principal_min_incoming_interval: Option<u64>,
+
+    #[structopt(long, default_value = "50")]
+    /// Number of messages all the sessions of each client principal can send at once before being rate limited.
+    /// Must be greater than 0.
+    principal_incoming_burst: u32,
+
+    #[structopt(long, default_value = "close", possible_values = &["close", "drop"])]
+    /// Action taken when a client exceeds a rate limit: `close` closes the WebSocket with code 4001,
+    /// `drop` discards the message. Dropping a message desynchronizes the sequence numbers of the client,
+    /// so the canister rejects all its following messages while its WebSocket stays open.
+    rate_limit_action: RateLimitAction,
+
+    #[structopt(long, default_value = "close", possible_values = &["close", "drop"])]
+    /// Policy applied to the clients whose queue is full: `close` closes the WebSocket with code 4000,
+    /// `drop` immediately removes the client and calls `ws_close` on the canister.
+    slow_client_policy: SlowClientPolicy,
+
+    #[structopt(long, default_value = "5")]
+    /// Maximum number of consecutive restarts of a failed poller before the clients of its canister are disconnected.
+    poller_max_restarts: u32,
+
+    #[structopt(long, default_value = "100")]
+    /// Time (in milliseconds) to wait before restarting a failed poller, doubled at each consecutive restart.
+    poller_restart_backoff: u64,
+
+    #[structopt(long, default_value = "10000")]
+    /// Maximum time (in milliseconds) to wait before restarting a failed poller.
+    poller_max_restart_backoff: u64,
+
+    #[structopt(long)]
+    /// Relays the canister messages to the clients without verifying their certificates.
+    skip_certificate_verification: bool,
+
+    #[structopt(long, default_value = "1000")]
+    /// Interval (in milliseconds) at which the nonces of the pollers are written to disk, so that the pollers resume from them after a restart.
+    /// Set to 0 to not persist the nonces.
+    poller_nonces_flush_interval: u64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Time (in milliseconds) to wait before retrying a ws_close call which failed with a transient error, doubled at each consecutive failure.
+    ws_close_retry_backoff: u64,
+
+    #[structopt(long, default_value = "60000")]
+    /// Maximum time (in milliseconds) to wait before retrying a failed ws_close call.
+    ws_close_max_retry_backoff: u64,
+
+    #[structopt(long, default_value = "10")]
+    /// Maximum number of attempts of a ws_close call, after which the canister is left to remove the client on its own.
+    ws_close_max_attempts: u32,
+
+    #[structopt(long, default_value = "1000")]
+    /// Interval (in milliseconds) at which the pending ws_close calls are written to disk, so that they are retried after a restart.
+    /// Set to 0 to not persist them.
+    close_outbox_flush_interval: u64,
+
     #[structopt(long)]
     tls_certificate_pem_path: Option<String>,
 
     #[structopt(long)]
     tls_certificate_key_pem_path: Option<String>,
 
+    #[structopt(long)]
+    /// Path of the JSON file listing the certificates presented to the clients depending on the server name they request (SNI).
+    /// The certificate specified by `tls_certificate_pem_path` is presented to the clients requesting other server names.
+    tls_sni_certificates_path: Option<String>,
+
+    #[structopt(long)]
+    /// Aborts the TLS handshakes with clients requesting a server name without a certificate,
+    /// instead of presenting them the default certificate.
+    tls_reject_unknown_server_names: bool,
+
+    #[structopt(long, default_value = "60000")]
+    /// Interval (in milliseconds) at which the TLS certificate and key files are checked for changes and reloaded.
+    /// Set to 0 to reload them only upon SIGHUP.
+    tls_certificate_reload_interval: u64,
+
+    #[structopt(long, default_value = "off", possible_values = &["off", "optional", "required"])]
+    /// Authentication of the clients with a TLS certificate issued by one of the CAs in `tls_client_ca_path`:
+    /// `off` does not ask for a certificate, `optional` verifies the certificate if the client presents one,
+    /// `required` aborts the TLS handshakes with the clients which do not present a valid certificate.
+    tls_client_auth: ClientAuthMode,
+
+    #[structopt(long)]
+    /// Path of the PEM bundle of the CA certificates the client certificates must be issued by.
+    tls_client_ca_path: Option<String>,
+
+    #[structopt(long)]
+    /// Subject of the client certificates allowed to connect, e.g. "CN=internal-service". Can be repeated.
+    /// Any client is allowed if not set.
+    tls_client_allowed_subject: Vec<String>,
+
     #[structopt(long)]
     /// OpenTelemetry collector endpoint for the telemetry.
     opentelemetry_collector_endpoint: Option<String>,
+
+    #[structopt(long, default_value = "10000")]
+    /// Time (in milliseconds) given to the client sessions to close before the gateway exits upon SIGTERM/SIGINT.
+    graceful_shutdown_timeout: u64,
+
+    #[structopt(long)]
+    /// Address at which the admin API is reachable, e.g. `127.0.0.1:9001`. The admin API is disabled if not set.
+    admin_api_address: Option<SocketAddr>,
+
+    #[structopt(long)]
+    /// Path of the file containing the token the requests to the admin API must present in the `Authorization: Bearer` header.
+    admin_api_token_path: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands run instead of starting the gateway
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Prints the nonces from which the pollers resume polling their canisters after a restart.
+    ShowPollerNonces,
+    /// Removes the stored nonces, so that the pollers start from the first message in the queues of their canisters.
+    /// Must be run while the gateway is stopped.
+    ResetPollerNonces {
+        #[structopt(long)]
+        /// Principal of the canister whose nonce is removed. The nonces of all the canisters are removed if not set.
+        canister_id: Option<Principal>,
+    },
+}
+
+/// Inspects or resets the nonces stored by the pollers
+fn run_poller_nonces_command(command: &Command) -> Result<(), String> {
+    let poller_nonces = PollerNonces::load(POLLER_NONCES_PATH)?;
+    match command {
+        Command::ShowPollerNonces => {
+            let nonces = serde_json::to_string_pretty(&poller_nonces.get_all())
+                .map_err(|e| e.to_string())?;
+            println!("{}", nonces);
+        },
+        Command::ResetPollerNonces {
+            canister_id: Some(canister_id),
+        } => {
+            if !poller_nonces.remove(canister_id) {
+                println!("No nonce stored for canister {}", canister_id);
+            }
+        },
+        Command::ResetPollerNonces { canister_id: None } => poller_nonces.clear(),
+    }
+    poller_nonces.flush()
 }
 
 fn create_data_dir() -> Result<(), String> {
@@ -54,6 +398,33 @@ fn create_data_dir() -> Result<(), String> {
     Ok(())
 }
 
+/// Reloads the canister allowlist and denylist and the TLS certificate whenever the gateway receives SIGHUP
+async fn reload_on_sighup(
+    canister_access: Arc<CanisterAccessControl>,
+    tls_certificates: Option<Arc<TlsCertificates>>,
+) {
+    let mut sighup = signal(SignalKind::hangup()).expect("could not listen for SIGHUP");
+    while sighup.recv().await.is_some() {
+        info!("Received SIGHUP");
+        if let Err(e) = canister_access.reload() {
+            // keep enforcing the previous lists so that a malformed file does not open the gateway to any canister
+            error!("Could not reload canister allowlist and denylist: {}", e);
+        }
+        if let Some(tls_certificates) = &tls_certificates {
+            tls_certificates.reload();
+        }
+    }
+}
+
+/// Waits until the gateway receives either SIGINT or SIGTERM
+async fn wait_for_shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+    select! {
+        _ = ctrl_c() => info!("Received SIGINT"),
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), String> {
     create_data_dir()?;
@@ -61,13 +432,11 @@ async fn main() -> Result<(), String> {
     let identity = get_identity_from_key_pair(key_pair);
 
     let deployment_info = DeploymentInfo::from_args();
+    if let Some(command) = &deployment_info.command {
+        return run_poller_nonces_command(command);
+    }
 
-    let manager = Manager::new(
-        deployment_info.gateway_address.clone(),
-        deployment_info.ic_network_url.clone(),
-        identity,
-    )
-    .await;
+    let manager = Manager::new(deployment_info.ic_network_url.clone(), identity).await;
 
     let gateway_principal = manager.get_agent_principal();
     let InitTracingResult {
@@ -86,7 +455,42 @@ async fn main() -> Result<(), String> {
     info!("Cargo version: {}", env!("CARGO_PKG_VERSION"));
     info!("Gateway Agent principal: {}", gateway_principal);
 
-    let tls_config = if deployment_info.tls_certificate_pem_path.is_some()
+    if deployment_info.client_queue_capacity == 0 {
+        return Err(String::from(
+            "The client queue capacity must be greater than 0",
+        ));
+    }
+    // the policy specified via the command line arguments is applied to the canisters which do not override it
+    let default_canister_policy = CanisterPolicy {
+        polling_interval_ms: deployment_info.polling_interval,
+        polling_timeout_ms: deployment_info.polling_timeout,
+        max_clients: deployment_info.max_clients_per_canister,
+        max_client_message_size: deployment_info.max_client_message_size,
+        client_queue_capacity: deployment_info.client_queue_capacity,
+    };
+    let canister_policies = match &deployment_info.canister_policies_path {
+        Some(canister_policies_path) => {
+            CanisterPolicies::load(canister_policies_path, default_canister_policy)?
+        },
+        None => CanisterPolicies::new(default_canister_policy),
+    };
+    info!("Canister policies: {:?}", canister_policies);
+    if let Some(max_polling_interval) = deployment_info.max_polling_interval {
+        let growth_factor = deployment_info.polling_interval_growth_factor;
+        if growth_factor.is_nan() || growth_factor <= 1.0 {
+            return Err(String::from(
+                "The polling interval growth factor must be greater than 1",
+            ));
+        }
+        canister_policies.check_adaptive_polling(max_polling_interval)?;
+    }
+
+    let canister_access = Arc::new(CanisterAccessControl::load(
+        deployment_info.canister_allowlist_path.clone(),
+        deployment_info.canister_denylist_path.clone(),
+    )?);
+
+    let default_tls_config = if deployment_info.tls_certificate_pem_path.is_some()
         && deployment_info.tls_certificate_key_pem_path.is_some()
     {
         Some(TlsConfig {
@@ -96,10 +500,238 @@ async fn main() -> Result<(), String> {
     } else {
         None
     };
+    let sni_certificate_entries = match &deployment_info.tls_sni_certificates_path {
+        Some(tls_sni_certificates_path) => load_sni_certificate_entries(tls_sni_certificates_path)?,
+        None => Vec::new(),
+    };
+    // TLS is enabled if at least one certificate is configured
+    let tls_certificates = if default_tls_config.is_some() || !sni_certificate_entries.is_empty() {
+        let tls_certificates = Arc::new(TlsCertificates::load(
+            default_tls_config,
+            sni_certificate_entries,
+            deployment_info.tls_reject_unknown_server_names,
+        )?);
+        if deployment_info.tls_certificate_reload_interval > 0 {
+            tokio::spawn(reload_certificates_periodically(
+                Arc::clone(&tls_certificates),
+                Duration::from_millis(deployment_info.tls_certificate_reload_interval),
+            ));
+        }
+        Some(tls_certificates)
+    } else {
+        None
+    };
+
+    tokio::spawn(reload_on_sighup(
+        Arc::clone(&canister_access),
+        tls_certificates.clone(),
+    ));
+
+    // a bucket without any token would reject all the messages of the clients
+    if deployment_info.incoming_burst == 0 {
+        return Err(String::from("The incoming burst must be greater than 0"));
+    }
+    if deployment_info.principal_incoming_burst == 0 {
+        return Err(String::from(
+            "The principal incoming burst must be greater than 0",
+        ));
+    }
+    let client_rate_limiter = Arc::new(ClientRateLimiter::new(RateLimiterConfig {
+        session_rate_limit: deployment_info
+            .min_incoming_interval
+            .map(|min_incoming_interval| RateLimit {
+                burst: deployment_info.incoming_burst,
+                refill_interval: Duration::from_millis(min_incoming_interval),
+            }),
+        principal_rate_limit: deployment_info.principal_min_incoming_interval.map(
+            |principal_min_incoming_interval| RateLimit {
+                burst: deployment_info.principal_incoming_burst,
+                refill_interval: Duration::from_millis(principal_min_incoming_interval),
+            },
+        ),
+        action: deployment_info.rate_limit_action,
+    }));
+    if client_rate_limiter.is_principal_rate_limit_enforced() {
+        tokio::spawn(remove_idle_buckets_periodically(Arc::clone(
+            &client_rate_limiter,
+        )));
+    }
+
+    let poller_nonces = if deployment_info.poller_nonces_flush_interval > 0 {
+        let poller_nonces = Arc::new(PollerNonces::load(POLLER_NONCES_PATH)?);
+        tokio::spawn(flush_poller_nonces_periodically(
+            Arc::clone(&poller_nonces),
+            Duration::from_millis(deployment_info.poller_nonces_flush_interval),
+        ));
+        Some(poller_nonces)
+    } else {
+        None
+    };
+
+    let close_outbox_config = CloseOutboxConfig {
+        retry_backoff_ms: deployment_info.ws_close_retry_backoff,
+        max_retry_backoff_ms: deployment_info.ws_close_max_retry_backoff,
+        max_attempts: deployment_info.ws_close_max_attempts,
+    };
+    let close_outbox = if deployment_info.close_outbox_flush_interval > 0 {
+        let close_outbox = Arc::new(CloseOutbox::load(
+            manager.get_agent(),
+            close_outbox_config,
+            CLOSE_OUTBOX_PATH,
+        )?);
+        tokio::spawn(flush_close_outbox_periodically(
+            Arc::clone(&close_outbox),
+            Duration::from_millis(deployment_info.close_outbox_flush_interval),
+        ));
+        close_outbox
+    } else {
+        Arc::new(CloseOutbox::new(manager.get_agent(), close_outbox_config))
+    };
+    tokio::spawn(retry_failed_closes(Arc::clone(&close_outbox)));
+
+    if deployment_info.client_messages_pipeline_capacity == 0 {
+        return Err(String::from(
+            "The capacity of the client messages pipeline must be greater than 0",
+        ));
+    }
+
+    let client_session_handler_config = ClientSessionHandlerConfig {
+        client_messages_pipeline_capacity: deployment_info.client_messages_pipeline_capacity,
+        canister_policies: Arc::new(canister_policies),
+        canister_access,
+        poller_config: PollerConfig {
+            // if adaptive polling is not enabled, the polling interval never grows
+            max_polling_interval_ms: deployment_info.max_polling_interval,
+            polling_interval_growth_factor: deployment_info.polling_interval_growth_factor,
+            slow_client_policy: deployment_info.slow_client_policy,
+            max_restarts: deployment_info.poller_max_restarts,
+            restart_backoff_ms: deployment_info.poller_restart_backoff,
+            max_restart_backoff_ms: deployment_info.poller_max_restart_backoff,
+            verify_certificates: !deployment_info.skip_certificate_verification,
+            poller_nonces: poller_nonces.clone(),
+            close_outbox: Arc::clone(&close_outbox),
+        },
+        client_rate_limiter,
+        upgrade_timeout_ms: deployment_info.upgrade_timeout,
+        upgrade_request_policy: Arc::new(UpgradeRequestPolicy::new(
+            deployment_info.allowed_origins.clone(),
+            deployment_info.allowed_hosts.clone(),
+        )),
+        keepalive_config: (deployment_info.ping_interval > 0).then(|| KeepaliveConfig {
+            ping_interval: Duration::from_millis(deployment_info.ping_interval),
+            pong_timeout: Duration::from_millis(deployment_info.pong_timeout),
+        }),
+        session_resumption: (deployment_info.session_resumption_grace_period > 0).then(|| {
+            Arc::new(SessionResumption::new(Duration::from_millis(
+                deployment_info.session_resumption_grace_period,
+            )))
+        }),
+        duplicate_client_key_policy: deployment_info.duplicate_client_key_policy,
+        verify_envelopes: !deployment_info.skip_envelope_verification,
+        close_outbox: Arc::clone(&close_outbox),
+    };
+
+    let tls_acceptor = match tls_certificates {
+        Some(tls_certificates) => Some(create_tls_acceptor(
+            tls_certificates,
+            &ClientAuthConfig {
+                mode: deployment_info.tls_client_auth,
+                ca_bundle_pem_path: deployment_info.tls_client_ca_path.clone(),
+            },
+        )?),
+        None => {
+            if deployment_info.tls_client_auth != ClientAuthMode::Off {
+                return Err(String::from(
+                    "TLS client authentication requires a TLS certificate",
+                ));
+            }
+            None
+        },
+    };
+
+    if (deployment_info.proxy_protocol || deployment_info.trust_x_forwarded_for)
+        && deployment_info.trusted_proxies.is_empty()
+    {
+        return Err(String::from(
+            "Reading the address of the clients from the proxies requires trusted proxies",
+        ));
+    }
+
+    // without explicit listeners, the gateway listens on a single address, with TLS if a certificate is configured
+    let listener_definitions = if deployment_info.listener.is_empty() {
+        vec![ListenerDefinition {
+            address: ListenerAddress::Tcp(deployment_info.gateway_address.clone()),
+            tls: tls_acceptor.is_some(),
+        }]
+    } else {
+        deployment_info.listener.clone()
+    };
+    if tls_acceptor.is_none() && listener_definitions.iter().any(|listener| listener.tls) {
+        return Err(String::from("TLS listeners require a TLS certificate"));
+    }
+
+    // a bucket without any token would reject all the connections
+    if deployment_info.connection_burst_per_ip == 0 {
+        return Err(String::from(
+            "The connection burst per IP must be greater than 0",
+        ));
+    }
+    // the limits apply to the connections of all the listeners
+    let connection_limiter = Arc::new(ConnectionLimiter::new(ConnectionLimiterConfig {
+        max_concurrent_handshakes: deployment_info.max_concurrent_handshakes,
+        max_connections_per_ip: deployment_info.max_connections_per_ip,
+        new_connections_per_ip_rate_limit: deployment_info.min_connection_interval_per_ip.map(
+            |min_connection_interval_per_ip| RateLimit {
+                burst: deployment_info.connection_burst_per_ip,
+                refill_interval: Duration::from_millis(min_connection_interval_per_ip),
+            },
+        ),
+    }));
+    tokio::spawn(remove_idle_connection_buckets_periodically(Arc::clone(
+        &connection_limiter,
+    )));
+
+    let ws_listener_config = WsListenerConfig {
+        tls_acceptor,
+        connection_authorizer: Arc::new(ClientCertificateSubjectAuthorizer::new(
+            deployment_info.tls_client_allowed_subject.clone(),
+        )),
+        connection_limiter,
+        trusted_proxies: Arc::new(TrustedProxies::new(
+            deployment_info.trusted_proxies.clone(),
+            deployment_info.proxy_protocol,
+            deployment_info.trust_x_forwarded_for,
+        )),
+    };
+
+    if let Some(admin_api_address) = deployment_info.admin_api_address {
+        let Some(admin_api_token_path) = &deployment_info.admin_api_token_path else {
+            return Err(String::from("The admin API requires a token"));
+        };
+        let admin_api = AdminApi::new(
+            manager.get_state(),
+            load_admin_api_token(admin_api_token_path)?,
+        );
+        start_admin_api(Arc::new(admin_api), admin_api_address)?;
+    }
 
     // keep accept incoming client connections
-    let accept_connections_handle =
-        manager.start_accepting_incoming_connections(tls_config, deployment_info.polling_interval);
+    let accept_connections_handle = manager
+        .start_accepting_incoming_connections(
+            listener_definitions,
+            ws_listener_config,
+            client_session_handler_config,
+        )
+        .await;
+
+    // keep running until the gateway is asked to terminate
+    wait_for_shutdown_signal().await;
+
+    manager
+        .shutdown(Duration::from_millis(
+            deployment_info.graceful_shutdown_timeout,
+        ))
+        .await;
 
     tokio::join!(accept_connections_handle)
         .0
@@ -107,6 +739,17 @@ async fn main() -> Result<(), String> {
 
     info!("Terminated gateway manager");
 
+    if let Some(poller_nonces) = poller_nonces {
+        // the nonces updated since the last periodic flush are written before exiting
+        if let Err(e) = poller_nonces.flush() {
+            error!("{}", e);
+        }
+    }
+    // the ws_close calls which did not succeed before exiting are retried after the restart
+    if let Err(e) = close_outbox.flush() {
+        error!("{}", e);
+    }
+
     if is_telemetry_enabled {
         opentelemetry::global::shutdown_tracer_provider();
     }
@@ -1,27 +1,33 @@
-use crate::ws_listener::{TlsConfig, WsListener};
+use crate::{
+    client_session_handler::ClientSessionHandlerConfig,
+    listener_socket::ListenerDefinition,
+    ws_listener::{WsListener, WsListenerConfig},
+};
 use canister_utils::get_new_agent;
 use gateway_state::GatewayState;
 use ic_agent::{export::Principal, identity::BasicIdentity, Agent};
-use std::sync::Arc;
-use tokio::task::JoinHandle;
-use tracing::info;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    task::{JoinHandle, JoinSet},
+    time::timeout,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
 
 /// Manager of the WS Gateway maintaining its state
 pub struct Manager {
     /// Agent used to interact with the IC
     agent: Arc<Agent>,
-    /// Gateway  address
-    address: String,
     /// State of the WS Gateway
     state: GatewayState,
+    /// Token cancelled when the gateway is shutting down
+    shutdown_token: CancellationToken,
+    /// Tracker of the client session handlers and pollers tasks
+    task_tracker: TaskTracker,
 }
 
 impl Manager {
-    pub async fn new(
-        gateway_address: String,
-        ic_network_url: String,
-        identity: BasicIdentity,
-    ) -> Self {
+    pub async fn new(ic_network_url: String, identity: BasicIdentity) -> Self {
         let agent = get_new_agent(&ic_network_url, identity)
             .await
             .expect("could not get new agent");
@@ -32,8 +38,9 @@ impl Manager {
 
         return Self {
             agent,
-            address: gateway_address,
             state,
+            shutdown_token: CancellationToken::new(),
+            task_tracker: TaskTracker::new(),
         };
     }
 
@@ -41,29 +48,75 @@ impl Manager {
         self.agent.get_principal().expect("Principal should be set")
     }
 
-    /// Keeps accepting incoming connections
-    pub fn start_accepting_incoming_connections(
+    /// Returns the agent shared by the client session handlers and the pollers
+    pub fn get_agent(&self) -> Arc<Agent> {
+        Arc::clone(&self.agent)
+    }
+
+    /// Returns the state shared by the client session handlers and the pollers
+    pub fn get_state(&self) -> GatewayState {
+        self.state.clone()
+    }
+
+    /// Keeps accepting incoming connections on all the listeners
+    /// The returned task terminates once all the listeners stopped accepting connections
+    pub async fn start_accepting_incoming_connections(
         &self,
-        tls_config: Option<TlsConfig>,
-        polling_interval: u64,
+        listener_definitions: Vec<ListenerDefinition>,
+        ws_listener_config: WsListenerConfig,
+        client_session_handler_config: ClientSessionHandlerConfig,
     ) -> JoinHandle<()> {
-        // spawn a task which keeps listening for incoming client connections
-        let gateway_address = self.address.clone();
-        let agent = Arc::clone(&self.agent);
-        let gateway_state = self.state.clone();
-        tokio::spawn(async move {
-            let mut ws_listener = WsListener::new(
-                &gateway_address,
-                agent,
-                gateway_state,
-                polling_interval,
-                tls_config,
-            )
-            .await;
+        // bind all the listeners before spawning any task so that the gateway fails immediately if an address is not available
+        let ws_listeners: Vec<WsListener> = listener_definitions
+            .iter()
+            .map(|listener_definition| {
+                WsListener::new(
+                    listener_definition,
+                    Arc::clone(&self.agent),
+                    self.state.clone(),
+                    client_session_handler_config.clone(),
+                    ws_listener_config.clone(),
+                    self.shutdown_token.clone(),
+                    self.task_tracker.clone(),
+                )
+            })
+            .collect();
 
+        // spawn a task for each listener which keeps listening for incoming client connections
+        // all the listeners share the same gateway state
+        let mut listener_tasks = JoinSet::new();
+        for mut ws_listener in ws_listeners {
+            listener_tasks.spawn(async move {
+                ws_listener.listen_for_incoming_requests().await;
+            });
+        }
+        tokio::spawn(async move {
             info!("Start accepting incoming connections");
-            ws_listener.listen_for_incoming_requests().await;
+            while listener_tasks.join_next().await.is_some() {}
             info!("Stopped accepting incoming connections");
         })
     }
+
+    /// Stops accepting incoming connections, closes all the client sessions
+    /// and waits for the session handlers and pollers to terminate, at most for 'graceful_shutdown_timeout'
+    pub async fn shutdown(&self, graceful_shutdown_timeout: Duration) {
+        info!("Shutting down the gateway");
+        // the listener stops accepting connections, the client sessions send a close frame to their clients
+        // and the pollers stop polling
+        self.shutdown_token.cancel();
+        // no more tasks are expected to be spawned, 'wait' returns as soon as all the tracked tasks terminate
+        self.task_tracker.close();
+
+        if timeout(graceful_shutdown_timeout, self.task_tracker.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "Graceful shutdown timed out with {} tasks still running",
+                self.task_tracker.len()
+            );
+        } else {
+            info!("All client sessions and pollers terminated");
+        }
+    }
 }
@@ -0,0 +1,24 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+/// Writes the content to a temporary file which then replaces the file at the path,
+/// so that a crash while writing never leaves a partially written file behind
+pub fn write_file_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temporary_path, path)?;
+    // the rename is durable only once the directory is synced as well
+    if let Some(directory) = path
+        .parent()
+        .filter(|directory| !directory.as_os_str().is_empty())
+    {
+        File::open(directory)?.sync_all()?;
+    }
+    Ok(())
+}
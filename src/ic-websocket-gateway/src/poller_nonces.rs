@@ -0,0 +1,158 @@
+use crate::persistence::write_file_atomically;
+use candid::Principal;
+use gateway_state::CanisterPrincipal;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::interval;
+use tracing::error;
+
+/// Path of the file storing the nonces of the pollers, under the data directory
+pub const POLLER_NONCES_PATH: &str = "./data/poller_nonces.json";
+
+/// Nonces from which the pollers resume polling their canisters after the gateway restarts
+/// The nonces are updated in memory after each polling iteration and written to disk in batches
+#[derive(Debug)]
+pub struct PollerNonces {
+    /// Path of the file storing the nonces
+    path: PathBuf,
+    /// Nonce of the next message to be polled from each canister
+    nonces: Mutex<HashMap<CanisterPrincipal, u64>>,
+    /// Whether the nonces changed since they were last written to disk
+    is_dirty: AtomicBool,
+}
+
+impl PollerNonces {
+    /// Loads the nonces stored in the file, if the file does not exist there is no nonce to resume from
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let nonces = if path.exists() {
+            let content = fs::read_to_string(path).map_err(|e| {
+                format!(
+                    "Could not read poller nonces file {}: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+            parse_nonces(&content)?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            nonces: Mutex::new(nonces),
+            is_dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the nonce from which the poller of the canister resumes polling, if any
+    pub fn get(&self, canister_id: &CanisterPrincipal) -> Option<u64> {
+        self.nonces
+            .lock()
+            .expect("poller nonces lock should not be poisoned")
+            .get(canister_id)
+            .copied()
+    }
+
+    /// Returns the nonces of all the canisters, sorted by canister principal
+    pub fn get_all(&self) -> BTreeMap<String, u64> {
+        self.nonces
+            .lock()
+            .expect("poller nonces lock should not be poisoned")
+            .iter()
+            .map(|(canister_id, nonce)| (canister_id.to_string(), *nonce))
+            .collect()
+    }
+
+    /// Sets the nonce of the next message to be polled from the canister
+    pub fn set(&self, canister_id: CanisterPrincipal, nonce: u64) {
+        let previous_nonce = self
+            .nonces
+            .lock()
+            .expect("poller nonces lock should not be poisoned")
+            .insert(canister_id, nonce);
+        if previous_nonce != Some(nonce) {
+            self.is_dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Removes the nonce of the canister, so that its next poller starts from the first message in its queue
+    /// Returns false if there is no nonce stored for the canister
+    pub fn remove(&self, canister_id: &CanisterPrincipal) -> bool {
+        let is_removed = self
+            .nonces
+            .lock()
+            .expect("poller nonces lock should not be poisoned")
+            .remove(canister_id)
+            .is_some();
+        if is_removed {
+            self.is_dirty.store(true, Ordering::Release);
+        }
+        is_removed
+    }
+
+    /// Removes the nonces of all the canisters
+    pub fn clear(&self) {
+        let mut nonces = self
+            .nonces
+            .lock()
+            .expect("poller nonces lock should not be poisoned");
+        if !nonces.is_empty() {
+            nonces.clear();
+            self.is_dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Writes the nonces to disk if they changed since they were last written
+    pub fn flush(&self) -> Result<(), String> {
+        if !self.is_dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // the nonces are copied so that the pollers are not blocked while the file is written
+        let nonces = self.get_all();
+        let content =
+            serde_json::to_vec_pretty(&nonces).expect("nonces should always be serializable");
+        write_file_atomically(&self.path, &content).map_err(|e| {
+            // the nonces are written again at the next flush
+            self.is_dirty.store(true, Ordering::Release);
+            format!(
+                "Could not write poller nonces file {}: {}",
+                self.path.display(),
+                e
+            )
+        })
+    }
+}
+
+fn parse_nonces(content: &str) -> Result<HashMap<CanisterPrincipal, u64>, String> {
+    let stored_nonces: BTreeMap<String, u64> = serde_json::from_str(content)
+        .map_err(|e| format!("Could not parse poller nonces: {}", e))?;
+    let mut nonces = HashMap::new();
+    for (canister_id, nonce) in stored_nonces {
+        let canister_id = Principal::from_text(&canister_id)
+            .map_err(|e| format!("Invalid canister principal {}: {}", canister_id, e))?;
+        nonces.insert(canister_id, nonce);
+    }
+    Ok(nonces)
+}
+
+/// Periodically writes the nonces which changed to disk
+pub async fn flush_poller_nonces_periodically(
+    poller_nonces: Arc<PollerNonces>,
+    flush_interval: Duration,
+) {
+    let mut flush_interval = interval(flush_interval);
+    loop {
+        flush_interval.tick().await;
+        if let Err(e) = poller_nonces.flush() {
+            error!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Signature starting the headers of version 2 of the PROXY protocol
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+/// Maximum length of a header of version 1 of the PROXY protocol, including the trailing CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+/// Reads the PROXY protocol (v1 or v2) header sent by a proxy at the beginning of the connection
+/// Returns the address of the client, or None if the proxy does not relay a client connection (e.g. health checks)
+/// The stream is left positioned at the first byte following the header
+pub async fn read_proxy_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<SocketAddr>, String> {
+    // both versions of the header are at least as long as the signature of version 2
+    let mut signature = [0; V2_SIGNATURE.len()];
+    stream
+        .read_exact(&mut signature)
+        .await
+        .map_err(|e| format!("Could not read PROXY header: {}", e))?;
+    if signature == V2_SIGNATURE {
+        let mut header = [0; 4];
+        stream
+            .read_exact(&mut header)
+            .await
+            .map_err(|e| format!("Could not read PROXY header: {}", e))?;
+        let mut addresses = vec![0; u16::from_be_bytes([header[2], header[3]]) as usize];
+        stream
+            .read_exact(&mut addresses)
+            .await
+            .map_err(|e| format!("Could not read PROXY header: {}", e))?;
+        parse_proxy_header_v2(header[0], header[1], &addresses)
+    } else if signature.starts_with(b"PROXY ") {
+        // the header is read byte by byte so that the data following it is not consumed
+        let mut line = signature.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(String::from("PROXY header is too long"));
+            }
+            let byte = stream
+                .read_u8()
+                .await
+                .map_err(|e| format!("Could not read PROXY header: {}", e))?;
+            line.push(byte);
+        }
+        parse_proxy_header_v1(&line)
+    } else {
+        Err(String::from(
+            "Connection does not start with a PROXY header",
+        ))
+    }
+}
+
+/// Parses a header of version 1 of the PROXY protocol, e.g. "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n"
+pub(crate) fn parse_proxy_header_v1(line: &[u8]) -> Result<Option<SocketAddr>, String> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.strip_suffix("\r\n"))
+        .ok_or_else(|| String::from("Invalid PROXY header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", protocol @ ("TCP4" | "TCP6"), source_ip, _, source_port, _] => {
+            let source_ip: IpAddr = source_ip
+                .parse()
+                .map_err(|_| format!("Invalid source address in PROXY header: {}", source_ip))?;
+            if source_ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(format!(
+                    "Source address {} does not match protocol {} in PROXY header",
+                    source_ip, protocol
+                ));
+            }
+            let source_port: u16 = source_port
+                .parse()
+                .map_err(|_| format!("Invalid source port in PROXY header: {}", source_port))?;
+            Ok(Some(SocketAddr::new(source_ip, source_port)))
+        },
+        _ => Err(format!("Invalid PROXY header: {}", line)),
+    }
+}
+
+/// Parses the fields following the signature of a header of version 2 of the PROXY protocol
+pub(crate) fn parse_proxy_header_v2(
+    version_command: u8,
+    family_protocol: u8,
+    addresses: &[u8],
+) -> Result<Option<SocketAddr>, String> {
+    if version_command >> 4 != 2 {
+        return Err(format!(
+            "Unsupported PROXY protocol version: {}",
+            version_command >> 4
+        ));
+    }
+    match version_command & 0x0F {
+        // LOCAL: the connection was opened by the proxy itself
+        0x0 => return Ok(None),
+        // PROXY: the connection is relayed on behalf of a client
+        0x1 => {},
+        command => return Err(format!("Unsupported PROXY command: {}", command)),
+    }
+    // the addresses may be followed by TLVs, which are ignored
+    let source_addr = match family_protocol >> 4 {
+        // AF_INET
+        0x1 => {
+            let addresses = addresses
+                .get(..12)
+                .ok_or_else(|| String::from("PROXY header is too short for IPv4 addresses"))?;
+            let source_ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let source_port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            SocketAddr::new(IpAddr::V4(source_ip), source_port)
+        },
+        // AF_INET6
+        0x2 => {
+            let addresses = addresses
+                .get(..36)
+                .ok_or_else(|| String::from("PROXY header is too short for IPv6 addresses"))?;
+            let mut source_ip = [0; 16];
+            source_ip.copy_from_slice(&addresses[..16]);
+            let source_port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::from(source_ip)), source_port)
+        },
+        // AF_UNSPEC and AF_UNIX do not carry an IP address
+        _ => return Ok(None),
+    };
+    Ok(Some(source_addr))
+}
@@ -0,0 +1,246 @@
+use candid::Principal;
+use canister_utils::ClientPrincipal;
+use dashmap::DashMap;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+use tracing::trace;
+
+/// Interval at which the buckets of the idle principals are removed
+pub const IDLE_BUCKETS_REMOVAL_INTERVAL_MS: u64 = 60_000;
+
+/// Token bucket which is refilled with a token every 'refill_interval', up to 'burst' tokens
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    /// Maximum number of tokens in the bucket
+    burst: f64,
+    /// Time needed to refill a single token
+    refill_interval: Duration,
+    /// Number of tokens currently in the bucket
+    tokens: f64,
+    /// Last time the bucket has been refilled
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    pub fn new(rate_limit: RateLimit, now: Instant) -> Self {
+        Self {
+            burst: rate_limit.burst as f64,
+            refill_interval: rate_limit.refill_interval,
+            tokens: rate_limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token from the bucket, if any
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    /// Returns true if the bucket is full, meaning that it has not been used recently
+    pub fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.burst
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled_tokens = if self.refill_interval.is_zero() {
+            self.burst
+        } else {
+            elapsed.as_secs_f64() / self.refill_interval.as_secs_f64()
+        };
+        self.tokens = (self.tokens + refilled_tokens).min(self.burst);
+        self.last_refill = now;
+    }
+}
+
+/// Maximum rate at which a client can send messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Number of messages which can be sent at once
+    pub burst: u32,
+    /// Minimum interval between messages, once the burst is exhausted
+    pub refill_interval: Duration,
+}
+
+/// Action taken when a client exceeds the rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// The message exceeding the limit is not relayed to the IC
+    /// The canister rejects all the following messages of the client, as their sequence numbers are out of order
+    Drop,
+    /// The session of the client is closed
+    Close,
+}
+
+impl RateLimitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitAction::Drop => "drop",
+            RateLimitAction::Close => "close",
+        }
+    }
+}
+
+impl FromStr for RateLimitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(RateLimitAction::Drop),
+            "close" => Ok(RateLimitAction::Close),
+            _ => Err(format!("Invalid rate limit action: {}", s)),
+        }
+    }
+}
+
+/// Limit exceeded by a client message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitScope {
+    /// Limit of the messages sent in the client session
+    Session,
+    /// Limit of the messages sent by all the sessions of the client principal
+    Principal,
+}
+
+impl RateLimitScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitScope::Session => "session",
+            RateLimitScope::Principal => "principal",
+        }
+    }
+}
+
+impl fmt::Display for RateLimitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Configuration of the rate limits of the client messages
+#[derive(Debug, Clone)]
+pub struct RateLimiterConfig {
+    /// Limit of each client session, not enforced if None
+    pub session_rate_limit: Option<RateLimit>,
+    /// Limit of each client principal, not enforced if None
+    pub principal_rate_limit: Option<RateLimit>,
+    /// Action taken when a client exceeds any of the limits
+    pub action: RateLimitAction,
+}
+
+/// Rate limiter shared by all the client sessions, keeping the bucket of each client principal
+#[derive(Debug)]
+pub struct ClientRateLimiter {
+    config: RateLimiterConfig,
+    // the guard returned when locking a dashmap is 'Send', therefore it is critical
+    // that it is not held accross .await points
+    principal_buckets: DashMap<ClientPrincipal, TokenBucket>,
+}
+
+impl ClientRateLimiter {
+    pub fn new(config: RateLimiterConfig) -> Self {
+        Self {
+            config,
+            principal_buckets: DashMap::new(),
+        }
+    }
+
+    /// Returns the rate limiter of a new client session
+    pub fn session_rate_limiter(self: &Arc<Self>) -> SessionRateLimiter {
+        SessionRateLimiter {
+            session_bucket: self
+                .config
+                .session_rate_limit
+                .map(|rate_limit| TokenBucket::new(rate_limit, Instant::now())),
+            client_rate_limiter: Arc::clone(self),
+        }
+    }
+
+    /// Returns true if the messages of each client principal are rate limited
+    pub fn is_principal_rate_limit_enforced(&self) -> bool {
+        self.config.principal_rate_limit.is_some()
+    }
+
+    /// Takes a token from the bucket of the client principal
+    /// The anonymous principal is not limited as it is shared by unrelated clients
+    fn try_acquire_principal_token(
+        &self,
+        client_principal: &ClientPrincipal,
+        now: Instant,
+    ) -> bool {
+        let Some(rate_limit) = self.config.principal_rate_limit else {
+            return true;
+        };
+        if *client_principal == Principal::anonymous() {
+            return true;
+        }
+        // the entry holds the lock on its shard only until the end of this function, which never yields
+        self.principal_buckets
+            .entry(*client_principal)
+            .or_insert_with(|| TokenBucket::new(rate_limit, now))
+            .try_acquire(now)
+    }
+
+    /// Removes the buckets of the principals which have not sent messages recently
+    /// so that the memory used by the rate limiter does not grow with the number of principals ever seen
+    pub fn remove_idle_buckets(&self) {
+        let now = Instant::now();
+        self.principal_buckets
+            .retain(|_, bucket| !bucket.is_full(now));
+        trace!(
+            "Rate limiter is tracking {} principals",
+            self.principal_buckets.len()
+        );
+    }
+}
+
+/// Removes the buckets of the idle principals until the gateway terminates
+pub async fn remove_idle_buckets_periodically(client_rate_limiter: Arc<ClientRateLimiter>) {
+    let mut removal_interval = interval(Duration::from_millis(IDLE_BUCKETS_REMOVAL_INTERVAL_MS));
+    loop {
+        removal_interval.tick().await;
+        client_rate_limiter.remove_idle_buckets();
+    }
+}
+
+/// Rate limiter of a client session
+pub struct SessionRateLimiter {
+    session_bucket: Option<TokenBucket>,
+    client_rate_limiter: Arc<ClientRateLimiter>,
+}
+
+impl SessionRateLimiter {
+    /// Returns an error with the exceeded limit if the client cannot send a message now
+    pub fn check(&mut self, client_principal: &ClientPrincipal) -> Result<(), RateLimitScope> {
+        let now = Instant::now();
+        if let Some(session_bucket) = &mut self.session_bucket {
+            if !session_bucket.try_acquire(now) {
+                return Err(RateLimitScope::Session);
+            }
+        }
+        if !self
+            .client_rate_limiter
+            .try_acquire_principal_token(client_principal, now)
+        {
+            return Err(RateLimitScope::Principal);
+        }
+        Ok(())
+    }
+
+    /// Returns the action to be taken when the client exceeds the limit
+    pub fn action(&self) -> RateLimitAction {
+        self.client_rate_limiter.config.action
+    }
+}
@@ -0,0 +1,79 @@
+use crate::ws_listener::ClientId;
+use canister_utils::{ClientKey, IcWsCanisterMessage};
+use dashmap::DashMap;
+use gateway_state::{CanisterPrincipal, ClientCloseReason, SessionToken};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+/// Session whose client lost its connection, kept in the gateway state until the client resumes it or its grace period expires
+/// The canister messages relayed by the poller in the meantime are buffered in the client channel
+#[derive(Debug)]
+pub struct DetachedSession {
+    /// Identifier of the connection which lost the session
+    pub client_id: ClientId,
+    /// Principal of the canister the client is connected to
+    pub canister_id: CanisterPrincipal,
+    /// Receiving side of the channel used by the poller to relay the canister messages to the session
+    pub client_channel_rx: Receiver<IcWsCanisterMessage>,
+    /// Receiving side of the channel used by the poller to request the session to be closed
+    pub client_close_rx: Receiver<ClientCloseReason>,
+    /// Token of the entry of the session in the gateway state, which is kept by the session resuming it
+    pub session_token: SessionToken,
+}
+
+/// Sessions waiting to be resumed by their clients on a new connection
+#[derive(Debug)]
+pub struct SessionResumption {
+    /// Time given to the clients to resume their session once their connection is lost
+    grace_period: Duration,
+    detached_sessions: DashMap<ClientKey, DetachedSession>,
+}
+
+impl SessionResumption {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            detached_sessions: DashMap::new(),
+        }
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Keeps the session until it is resumed or expired
+    pub fn detach(&self, client_key: ClientKey, detached_session: DetachedSession) {
+        self.detached_sessions.insert(client_key, detached_session);
+    }
+
+    /// Returns true if the client can resume its session with the canister
+    pub fn is_detached(&self, canister_id: CanisterPrincipal, client_key: &ClientKey) -> bool {
+        self.detached_sessions
+            .get(client_key)
+            .is_some_and(|detached_session| detached_session.canister_id == canister_id)
+    }
+
+    /// Hands the session over to the client which resumes it
+    /// Returns None if the session has already been resumed or expired in the meantime
+    pub fn resume(
+        &self,
+        canister_id: CanisterPrincipal,
+        client_key: &ClientKey,
+    ) -> Option<DetachedSession> {
+        self.detached_sessions
+            .remove_if(client_key, |_, detached_session| {
+                detached_session.canister_id == canister_id
+            })
+            .map(|(_, detached_session)| detached_session)
+    }
+
+    /// Removes the session detached by the connection once its grace period is over
+    /// Returns None if the session has been resumed in the meantime, even if it has then been detached again by another connection
+    pub fn expire(&self, client_key: &ClientKey, client_id: ClientId) -> Option<DetachedSession> {
+        self.detached_sessions
+            .remove_if(client_key, |_, detached_session| {
+                detached_session.client_id == client_id
+            })
+            .map(|(_, detached_session)| detached_session)
+    }
+}
@@ -0,0 +1,211 @@
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use candid::Principal;
+    use canister_utils::ClientKey;
+    use gateway_state::{
+        ClientCloseReason, ClientSender, DuplicateClientKeyPolicy, GatewayState, PollerState,
+    };
+    use hyper::{
+        body::to_bytes, header::AUTHORIZATION, Body, Method, Request, Response, StatusCode,
+    };
+    use serde_json::Value;
+    use tokio::sync::mpsc::{self, Receiver};
+    use tracing::Span;
+
+    use crate::admin_api::AdminApi;
+
+    const TOKEN: &str = "admin-token";
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    fn client_key() -> ClientKey {
+        ClientKey::new(Principal::anonymous(), 1)
+    }
+
+    /// Adds a client to the gateway state and returns the poller state and the receiver of the close requests
+    fn insert_client(gateway_state: &GatewayState) -> (PollerState, Receiver<ClientCloseReason>) {
+        let (client_close_tx, client_close_rx) = mpsc::channel(1);
+        let poller_state = gateway_state
+            .insert_client_channel_and_get_new_poller_state(
+                canister_id(),
+                client_key(),
+                ClientSender::new(
+                    mpsc::channel(100).0,
+                    Span::current(),
+                    client_close_tx,
+                    7,
+                    Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+                ),
+                None,
+                DuplicateClientKeyPolicy::Reject,
+            )
+            .unwrap()
+            .expect("must be some");
+        (poller_state, client_close_rx)
+    }
+
+    fn request(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header(AUTHORIZATION, format!("Bearer {}", TOKEN))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn json_body(response: Response<Body>) -> Value {
+        serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_reject_requests_without_token() {
+        let admin_api = AdminApi::new(GatewayState::new(), String::from(TOKEN));
+
+        let unauthorized_request = Request::get("/canisters").body(Body::empty()).unwrap();
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            admin_api.handle_request(&unauthorized_request).status()
+        );
+
+        let unauthorized_request = Request::get("/canisters")
+            .header(AUTHORIZATION, "Bearer not-the-token")
+            .body(Body::empty())
+            .unwrap();
+        let response = admin_api.handle_request(&unauthorized_request);
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        assert_eq!("Bearer", response.headers()["www-authenticate"]);
+
+        assert_eq!(
+            StatusCode::OK,
+            admin_api
+                .handle_request(&request(Method::GET, "/canisters"))
+                .status()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_list_canisters_and_clients() {
+        let gateway_state = GatewayState::new();
+        let (poller_state, _client_close_rx) = insert_client(&gateway_state);
+        poller_state.update_status(|status| {
+            status.next_message_nonce = 10;
+            status.error_streak = 2;
+            status.last_error = Some(String::from("Polling timed out"));
+        });
+        let admin_api = AdminApi::new(gateway_state, String::from(TOKEN));
+
+        let response = admin_api.handle_request(&request(Method::GET, "/canisters"));
+        assert_eq!(StatusCode::OK, response.status());
+        let canisters = json_body(response).await;
+        assert_eq!(1, canisters.as_array().unwrap().len());
+        let canister = &canisters[0];
+        assert_eq!(canister_id().to_string(), canister["canister_id"]);
+        assert_eq!(1, canister["clients_count"]);
+        assert_eq!(10, canister["poller"]["next_message_nonce"]);
+        assert_eq!(2, canister["poller"]["error_streak"]);
+        assert_eq!("Polling timed out", canister["poller"]["last_error"]);
+        assert_eq!(false, canister["poller"]["is_paused"]);
+        // the clients are returned only when a single canister is requested
+        assert!(canister.get("clients").is_none());
+
+        let response = admin_api.handle_request(&request(
+            Method::GET,
+            &format!("/canisters/{}", canister_id()),
+        ));
+        assert_eq!(StatusCode::OK, response.status());
+        let client = &json_body(response).await["clients"][0];
+        assert_eq!(client_key().to_string(), client["client_key"]);
+        assert_eq!(
+            Principal::anonymous().to_string(),
+            client["client_principal"]
+        );
+        assert_eq!(1, client["client_nonce"]);
+        assert_eq!(7, client["session_token"]);
+        assert_eq!("10.0.0.1", client["client_ip"]);
+        assert!(client["connected_at_ms"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn should_kick_clients() {
+        let gateway_state = GatewayState::new();
+        let (_, mut client_close_rx) = insert_client(&gateway_state);
+        let admin_api = AdminApi::new(gateway_state, String::from(TOKEN));
+
+        let response = admin_api.handle_request(&request(
+            Method::POST,
+            &format!("/canisters/{}/clients/{}/kick", canister_id(), client_key()),
+        ));
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(Ok(ClientCloseReason::Kicked), client_close_rx.try_recv());
+
+        let response = admin_api.handle_request(&request(
+            Method::POST,
+            &format!(
+                "/canisters/{}/clients/{}_2/kick",
+                canister_id(),
+                Principal::anonymous()
+            ),
+        ));
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let response = admin_api.handle_request(&request(
+            Method::POST,
+            &format!("/canisters/{}/kick", canister_id()),
+        ));
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(1, json_body(response).await["kicked_clients"]);
+        assert_eq!(Ok(ClientCloseReason::Kicked), client_close_rx.try_recv());
+    }
+
+    #[tokio::test]
+    async fn should_pause_and_resume_poller() {
+        let gateway_state = GatewayState::new();
+        let (poller_state, _client_close_rx) = insert_client(&gateway_state);
+        let admin_api = AdminApi::new(gateway_state, String::from(TOKEN));
+
+        let response = admin_api.handle_request(&request(
+            Method::POST,
+            &format!("/canisters/{}/pause", canister_id()),
+        ));
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(poller_state.is_paused());
+
+        let response = admin_api.handle_request(&request(
+            Method::POST,
+            &format!("/canisters/{}/resume", canister_id()),
+        ));
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(!poller_state.is_paused());
+    }
+
+    #[tokio::test]
+    async fn should_reject_unknown_canisters_and_routes() {
+        let admin_api = AdminApi::new(GatewayState::new(), String::from(TOKEN));
+        let paths = [
+            (Method::GET, format!("/canisters/{}", canister_id())),
+            (Method::POST, format!("/canisters/{}/pause", canister_id())),
+            (Method::POST, format!("/canisters/{}/kick", canister_id())),
+            (Method::GET, format!("/canisters/{}/pause", canister_id())),
+            (Method::GET, String::from("/clients")),
+        ];
+        for (method, path) in paths {
+            assert_eq!(
+                StatusCode::NOT_FOUND,
+                admin_api.handle_request(&request(method, &path)).status()
+            );
+        }
+
+        let response =
+            admin_api.handle_request(&request(Method::GET, "/canisters/not-a-principal"));
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let response = admin_api.handle_request(&request(
+            Method::POST,
+            &format!("/canisters/{}/clients/not-a-client-key/kick", canister_id()),
+        ));
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod test {
+    use candid::Principal;
+    use std::collections::HashSet;
+
+    use crate::canister_access::{parse_canister_list, CanisterAccessList};
+
+    fn canister(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
+    }
+
+    #[test]
+    fn should_parse_canister_list() {
+        let content = "
+            # game canisters
+            bkyz2-fmaaa-aaaaa-qaaaq-cai
+
+            bd3sg-teaaa-aaaaa-qaaba-cai
+        ";
+        assert_eq!(
+            Ok(HashSet::from([
+                canister("bkyz2-fmaaa-aaaaa-qaaaq-cai"),
+                canister("bd3sg-teaaa-aaaaa-qaaba-cai"),
+            ])),
+            parse_canister_list(content)
+        );
+        assert!(parse_canister_list("not-a-principal").is_err());
+    }
+
+    #[test]
+    fn should_allow_any_canister_without_lists() {
+        let access_list = CanisterAccessList::default();
+        assert!(access_list.is_allowed(&canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")));
+    }
+
+    #[test]
+    fn should_allow_only_canisters_in_allowlist() {
+        let access_list = CanisterAccessList::new(
+            Some(HashSet::from([canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")])),
+            HashSet::new(),
+        );
+        assert!(access_list.is_allowed(&canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")));
+        assert!(!access_list.is_allowed(&canister("bd3sg-teaaa-aaaaa-qaaba-cai")));
+    }
+
+    #[test]
+    fn should_deny_canisters_in_denylist() {
+        // the denylist takes precedence over the allowlist
+        let access_list = CanisterAccessList::new(
+            Some(HashSet::from([canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")])),
+            HashSet::from([canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")]),
+        );
+        assert!(!access_list.is_allowed(&canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")));
+
+        let access_list = CanisterAccessList::new(
+            None,
+            HashSet::from([canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")]),
+        );
+        assert!(!access_list.is_allowed(&canister("bkyz2-fmaaa-aaaaa-qaaaq-cai")));
+        assert!(access_list.is_allowed(&canister("bd3sg-teaaa-aaaaa-qaaba-cai")));
+    }
+}
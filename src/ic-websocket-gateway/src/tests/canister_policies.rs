@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod test {
+    use candid::Principal;
+
+    use crate::canister_policies::{CanisterPolicies, CanisterPolicy};
+
+    struct MockCanisterPolicy;
+
+    impl MockCanisterPolicy {
+        fn mock() -> CanisterPolicy {
+            CanisterPolicy {
+                polling_interval_ms: 100,
+                polling_timeout_ms: 5_000,
+                max_clients: None,
+                max_client_message_size: None,
+                client_queue_capacity: 100,
+            }
+        }
+    }
+
+    #[test]
+    fn should_apply_default_policy_to_all_canisters() {
+        let canister_policies = CanisterPolicies::new(MockCanisterPolicy::mock());
+        assert_eq!(
+            &MockCanisterPolicy::mock(),
+            canister_policies.get(&Principal::anonymous())
+        );
+    }
+
+    #[test]
+    fn should_override_default_policy() {
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let content = format!(
+            r#"{{
+                "default": {{ "max_clients": 1000 }},
+                "canisters": {{
+                    "{}": {{ "polling_interval_ms": 20, "client_queue_capacity": 500 }}
+                }}
+            }}"#,
+            canister_id
+        );
+        let canister_policies =
+            CanisterPolicies::parse(&content, MockCanisterPolicy::mock()).expect("must be valid");
+
+        // the fields which are not overridden are inherited from the default policy
+        let default_policy = CanisterPolicy {
+            max_clients: Some(1000),
+            ..MockCanisterPolicy::mock()
+        };
+        assert_eq!(
+            &default_policy,
+            canister_policies.get(&Principal::anonymous())
+        );
+        assert_eq!(
+            &CanisterPolicy {
+                polling_interval_ms: 20,
+                client_queue_capacity: 500,
+                ..default_policy
+            },
+            canister_policies.get(&canister_id)
+        );
+    }
+
+    #[test]
+    fn should_not_parse_invalid_policies() {
+        // invalid principal
+        assert!(CanisterPolicies::parse(
+            r#"{ "canisters": { "not-a-principal": {} } }"#,
+            MockCanisterPolicy::mock()
+        )
+        .is_err());
+        // unknown field
+        assert!(CanisterPolicies::parse(
+            r#"{ "default": { "polling_interval": 20 } }"#,
+            MockCanisterPolicy::mock()
+        )
+        .is_err());
+        // the queue of the clients of the canister could not hold any message
+        assert!(CanisterPolicies::parse(
+            r#"{ "canisters": { "aaaaa-aa": { "client_queue_capacity": 0 } } }"#,
+            MockCanisterPolicy::mock()
+        )
+        .is_err());
+        assert!(CanisterPolicies::parse(
+            r#"{ "default": { "client_queue_capacity": 0 } }"#,
+            MockCanisterPolicy::mock()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn should_check_polling_intervals_for_adaptive_polling() {
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let canister_policies = |polling_interval_ms: u64| {
+            CanisterPolicies::parse(
+                &format!(
+                    r#"{{ "canisters": {{ "{}": {{ "polling_interval_ms": {} }} }} }}"#,
+                    canister_id, polling_interval_ms
+                ),
+                MockCanisterPolicy::mock(),
+            )
+            .expect("must be valid")
+        };
+
+        assert!(canister_policies(1_000)
+            .check_adaptive_polling(1_000)
+            .is_ok());
+        // the polling interval of the canister could not grow
+        assert!(canister_policies(0).check_adaptive_polling(1_000).is_err());
+        // the polling interval of the canister exceeds the maximum one
+        assert!(canister_policies(2_000)
+            .check_adaptive_polling(1_000)
+            .is_err());
+        // the polling interval of the default policy exceeds the maximum one
+        assert!(canister_policies(10).check_adaptive_polling(10).is_err());
+    }
+}
@@ -3,23 +3,28 @@ mod test {
     use candid::Principal;
     use canister_utils::{
         ws_get_messages, CanisterOutputCertifiedMessages, CanisterOutputMessage,
-        CanisterWsGetMessagesArguments, ClientKey, IcWsCanisterMessage,
+        CanisterWsGetMessagesArguments, ClientKey, IcWsCanisterMessage, WebsocketMessage,
     };
     use futures_util::join;
-    use gateway_state::GatewayState;
+    use gateway_state::{
+        ClientCloseReason, ClientSender, DuplicateClientKeyPolicy, GatewayState, PollerState,
+    };
     use ic_agent::{agent::http_transport::ReqwestTransport, Agent};
     use lazy_static::lazy_static;
-    use std::{
-        sync::{Arc, Mutex},
-        thread,
-        time::Duration,
+    use std::{sync::Arc, thread, time::Duration};
+    use tokio::sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
     };
-    use tokio::sync::mpsc::{self, Receiver, Sender};
+    use tokio_util::sync::CancellationToken;
     use tracing::Span;
 
+    use crate::canister_policies::{CanisterPolicies, CanisterPolicy};
     use crate::canister_poller::{
-        get_nonce_from_message, CanisterPoller, PollingStatus, POLLING_TIMEOUT_MS,
+        get_nonce_from_message, CanisterPoller, PollerConfig, PollingStatus, SlowClientPolicy,
     };
+    use crate::close_outbox::{CloseOutbox, CloseOutboxConfig, PendingClose};
+    use crate::poller_nonces::PollerNonces;
 
     struct MockCanisterOutputCertifiedMessages(CanisterOutputCertifiedMessages);
 
@@ -65,6 +70,26 @@ mod test {
             }
         }
 
+        fn mock_with_sequence_num(
+            nonce: usize,
+            client_key: ClientKey,
+            sequence_num: u64,
+        ) -> CanisterOutputMessage {
+            let content = candid::encode_one(WebsocketMessage {
+                client_key: client_key.clone(),
+                sequence_num,
+                timestamp: 0,
+                is_service_message: false,
+                content: Vec::default(),
+            })
+            .unwrap();
+            CanisterOutputMessage {
+                client_key,
+                key: format!("_{}", nonce),
+                content,
+            }
+        }
+
         fn mock_with_key_error() -> CanisterOutputMessage {
             CanisterOutputMessage {
                 client_key: MockClientKey::mock(),
@@ -85,13 +110,62 @@ mod test {
         }
     }
 
+    const POLLING_TIMEOUT_MS: u64 = 5_000;
+
+    struct MockCanisterPolicy;
+
+    impl MockCanisterPolicy {
+        fn mock(polling_interval_ms: u64) -> CanisterPolicy {
+            CanisterPolicy {
+                polling_interval_ms,
+                polling_timeout_ms: POLLING_TIMEOUT_MS,
+                max_clients: None,
+                max_client_message_size: None,
+                client_queue_capacity: 100,
+            }
+        }
+    }
+
+    struct MockPollerConfig;
+
+    impl MockPollerConfig {
+        fn mock() -> PollerConfig {
+            PollerConfig {
+                max_polling_interval_ms: None,
+                polling_interval_growth_factor: 2.0,
+                slow_client_policy: SlowClientPolicy::Close,
+                max_restarts: 0,
+                restart_backoff_ms: 10,
+                max_restart_backoff_ms: 100,
+                verify_certificates: false,
+                poller_nonces: None,
+                close_outbox: Arc::new(CloseOutbox::new(
+                    Arc::new(
+                        Agent::builder()
+                            .with_transport(
+                                ReqwestTransport::create("http://127.0.0.1:4943").unwrap(),
+                            )
+                            .build()
+                            .unwrap(),
+                    ),
+                    CloseOutboxConfig {
+                        retry_backoff_ms: 10,
+                        max_retry_backoff_ms: 100,
+                        max_attempts: 1,
+                    },
+                )),
+            }
+        }
+    }
+
     lazy_static! {
-        // mockito::Server is behind a SYNC mutex so that only one test at the same time can access it
+        // mockito::Server is behind an async mutex so that only one test at the same time can access it
         // otherwise, as async tests are run on multiple threads, the mock response of one test might overwrite
         // the mock response of another, causing the test to fail
         // acquiring the mutex and the beginning of each test and dropping the guard only at the end,
         // ensures that only one test at the time can set the mock response
         // this enables running the tests without specifying each time "-- --test-threads=1"
+        // the mutex is async as the guard is held across the await points of the tests
         static ref MOCK_SERVER: Arc<Mutex<mockito::Server>> =
             Arc::new(Mutex::new(mockito::Server::new_with_port(51558)));
     }
@@ -100,17 +174,81 @@ mod test {
         polling_interval_ms: u64,
         client_channel_tx: Sender<IcWsCanisterMessage>,
     ) -> CanisterPoller {
-        let gateway_state: GatewayState = GatewayState::new();
+        create_poller_with_config(
+            MockPollerConfig::mock(),
+            polling_interval_ms,
+            client_channel_tx,
+            mpsc::channel(1).0,
+        )
+    }
 
-        let poller_state = gateway_state
-            .insert_client_channel_and_get_new_poller_state(
-                Principal::anonymous(),
+    fn create_poller_with_config(
+        poller_config: PollerConfig,
+        polling_interval_ms: u64,
+        client_channel_tx: Sender<IcWsCanisterMessage>,
+        client_close_tx: Sender<ClientCloseReason>,
+    ) -> CanisterPoller {
+        create_poller_with_clients(
+            poller_config,
+            polling_interval_ms,
+            vec![(
                 MockClientKey::mock(),
-                client_channel_tx,
-                Span::current(),
-            )
-            .expect("must be some");
+                ClientSender::new(client_channel_tx, Span::current(), client_close_tx, 0, None),
+            )],
+        )
+    }
+
+    fn create_poller_with_clients(
+        poller_config: PollerConfig,
+        polling_interval_ms: u64,
+        clients: Vec<(ClientKey, ClientSender)>,
+    ) -> CanisterPoller {
+        let gateway_state: GatewayState = GatewayState::new();
 
+        let mut poller_state = None;
+        for (client_key, client_sender) in clients {
+            // only the first client creates the poller state
+            let new_poller_state = gateway_state
+                .insert_client_channel_and_get_new_poller_state(
+                    Principal::anonymous(),
+                    client_key,
+                    client_sender,
+                    None,
+                    DuplicateClientKeyPolicy::Reject,
+                )
+                .expect("no limit on the number of clients");
+            poller_state = poller_state.or(new_poller_state);
+        }
+        create_poller_with_state(
+            poller_config,
+            polling_interval_ms,
+            gateway_state,
+            poller_state.expect("must be some"),
+        )
+    }
+
+    fn create_poller_with_state(
+        poller_config: PollerConfig,
+        polling_interval_ms: u64,
+        gateway_state: GatewayState,
+        poller_state: PollerState,
+    ) -> CanisterPoller {
+        create_poller_with_shutdown_token(
+            poller_config,
+            polling_interval_ms,
+            gateway_state,
+            poller_state,
+            CancellationToken::new(),
+        )
+    }
+
+    fn create_poller_with_shutdown_token(
+        poller_config: PollerConfig,
+        polling_interval_ms: u64,
+        gateway_state: GatewayState,
+        poller_state: PollerState,
+        shutdown_token: CancellationToken,
+    ) -> CanisterPoller {
         CanisterPoller::new(
             Arc::new(
                 Agent::builder()
@@ -121,7 +259,9 @@ mod test {
             Principal::anonymous(),
             poller_state,
             gateway_state,
-            polling_interval_ms,
+            poller_config,
+            &CanisterPolicies::new(MockCanisterPolicy::mock(polling_interval_ms)),
+            shutdown_token,
         )
     }
 
@@ -135,7 +275,7 @@ mod test {
         let msg_count = 10;
         let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(msg_count, 0));
         let path = "/ws_get_messages";
-        let mut guard = server.lock().unwrap();
+        let mut guard = server.lock().await;
         // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
         let mock = guard
             .mock("GET", path)
@@ -176,7 +316,7 @@ mod test {
             msg_count, 0,
         ));
         let path = "/ws_get_messages";
-        let mut guard = server.lock().unwrap();
+        let mut guard = server.lock().await;
         // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
         let mock = guard
             .mock("GET", path)
@@ -218,7 +358,7 @@ mod test {
         let msg_count = 10;
         let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(msg_count, 0));
         let path = "/ws_get_messages";
-        let mut guard = server.lock().unwrap();
+        let mut guard = server.lock().await;
         // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
         let mock = guard
             .mock("GET", path)
@@ -276,7 +416,7 @@ mod test {
             MockCanisterOutputCertifiedMessages::mock_n_with_not_end_of_queue(msg_count, 0),
         );
         let path = "/ws_get_messages";
-        let mut guard = server.lock().unwrap();
+        let mut guard = server.lock().await;
         // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
         let mock = guard
             .mock("GET", path)
@@ -322,11 +462,179 @@ mod test {
         drop(guard);
     }
 
+    #[tokio::test]
+    async fn should_increase_polling_interval_while_idle() {
+        let server = &*MOCK_SERVER;
+        let idle_polls = 4;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(0, 0));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(idle_polls)
+            .create_async()
+            .await;
+
+        let polling_interval_ms = 100;
+        let max_polling_interval_ms = 400;
+        let (client_channel_tx, _client_channel_rx): (
+            Sender<IcWsCanisterMessage>,
+            Receiver<IcWsCanisterMessage>,
+        ) = mpsc::channel(100);
+
+        let mut poller = create_poller_with_config(
+            PollerConfig {
+                max_polling_interval_ms: Some(max_polling_interval_ms),
+                ..MockPollerConfig::mock()
+            },
+            polling_interval_ms,
+            client_channel_tx,
+            mpsc::channel(1).0,
+        );
+
+        // the poller sleeps for 100, 200, 400 and again 400 milliseconds
+        let mut elapsed = Duration::default();
+        for _ in 0..idle_polls {
+            let start_polling_instant = tokio::time::Instant::now();
+            poller.poll_and_relay().await.expect("Failed to poll");
+            elapsed = tokio::time::Instant::now() - start_polling_instant;
+        }
+        println!("Elapsed after last idle poll: {:?}", elapsed);
+        assert!(
+            elapsed > Duration::from_millis(max_polling_interval_ms)
+                && elapsed
+                    < Duration::from_millis((1.1 * max_polling_interval_ms as f64).round() as u64)
+        );
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn should_request_slow_client_to_close() {
+        let server = &*MOCK_SERVER;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(10, 0));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // the client does not read its messages while the poller is relaying them
+        let (client_channel_tx, mut client_channel_rx): (
+            Sender<IcWsCanisterMessage>,
+            Receiver<IcWsCanisterMessage>,
+        ) = mpsc::channel(1);
+        let (client_close_tx, mut client_close_rx): (
+            Sender<ClientCloseReason>,
+            Receiver<ClientCloseReason>,
+        ) = mpsc::channel(1);
+
+        let mut poller = create_poller_with_config(
+            PollerConfig {
+                slow_client_policy: SlowClientPolicy::Close,
+                ..MockPollerConfig::mock()
+            },
+            100,
+            client_channel_tx,
+            client_close_tx,
+        );
+        poller.poll_and_relay().await.expect("Failed to poll");
+
+        assert_eq!(
+            client_close_rx.try_recv(),
+            Ok(ClientCloseReason::SlowClient)
+        );
+        // only the messages which fit in the queue are relayed
+        let (msg, _) = client_channel_rx.try_recv().expect("must be relayed");
+        assert_eq!(0, get_nonce_from_message(&msg.key).unwrap());
+        assert!(client_channel_rx.try_recv().is_err());
+        mock.assert_async().await;
+        mock.remove_async().await;
+
+        // until its session removes it from the poller state, the client does not get the newer messages
+        // even though its queue is not full anymore, as it already lost some of the previous ones
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(10, 10));
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+        poller.poll_and_relay().await.expect("Failed to poll");
+        assert!(client_channel_rx.try_recv().is_err());
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn should_drop_slow_client() {
+        let server = &*MOCK_SERVER;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(10, 0));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // the client does not read its messages while the poller is relaying them
+        let (client_channel_tx, mut client_channel_rx): (
+            Sender<IcWsCanisterMessage>,
+            Receiver<IcWsCanisterMessage>,
+        ) = mpsc::channel(1);
+        let (client_close_tx, mut client_close_rx): (
+            Sender<ClientCloseReason>,
+            Receiver<ClientCloseReason>,
+        ) = mpsc::channel(1);
+
+        let poller_config = PollerConfig {
+            slow_client_policy: SlowClientPolicy::Drop,
+            ..MockPollerConfig::mock()
+        };
+        let close_outbox = Arc::clone(&poller_config.close_outbox);
+        let mut poller =
+            create_poller_with_config(poller_config, 100, client_channel_tx, client_close_tx);
+        poller.poll_and_relay().await.expect("Failed to poll");
+
+        // the ws_close call is queued before the poller returns, so that it is not lost on shutdown
+        assert_eq!(
+            vec![PendingClose {
+                canister_id: Principal::anonymous(),
+                client_key: MockClientKey::mock(),
+                failed_attempts: 0,
+            }],
+            close_outbox.get_pending_closes()
+        );
+
+        // the client is removed from the poller state, which drops the poller side of the channels
+        let (msg, _) = client_channel_rx.recv().await.expect("must be relayed");
+        assert_eq!(0, get_nonce_from_message(&msg.key).unwrap());
+        assert!(client_channel_rx.recv().await.is_none());
+        assert!(client_close_rx.recv().await.is_none());
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
     #[tokio::test]
     async fn should_not_sleep_after_timeout() {
         let server = &*MOCK_SERVER;
         let path = "/ws_get_messages";
-        let mut guard = server.lock().unwrap();
+        let mut guard = server.lock().await;
         // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
         let mock = guard
             .mock("GET", path)
@@ -365,12 +673,13 @@ mod test {
     }
 
     #[tokio::test]
-    async fn should_terminate_polling_with_error() {
+    async fn should_discard_messages_with_invalid_certification() {
         let server = &*MOCK_SERVER;
         let msg_count = 10;
+        // the mock messages have neither a certificate nor a tree
         let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(msg_count, 0));
         let path = "/ws_get_messages";
-        let mut guard = server.lock().unwrap();
+        let mut guard = server.lock().await;
         // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
         let mock = guard
             .mock("GET", path)
@@ -385,8 +694,55 @@ mod test {
             Receiver<IcWsCanisterMessage>,
         ) = mpsc::channel(100);
 
+        let mut poller = create_poller_with_config(
+            PollerConfig {
+                verify_certificates: true,
+                ..MockPollerConfig::mock()
+            },
+            polling_interval_ms,
+            client_channel_tx,
+            mpsc::channel(1).0,
+        );
+
+        assert_eq!(
+            Ok(PollingStatus::InvalidCertification),
+            poller.poll_canister().await
+        );
+        // the batch is polled again instead of failing the poller
+        poller.poll_and_relay().await.expect("Failed to poll");
+        assert!(client_channel_rx.try_recv().is_err());
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn should_terminate_polling_with_error() {
+        let server = &*MOCK_SERVER;
+        let msg_count = 10;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n_with_key_error(
+            msg_count, 0,
+        ));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let polling_interval_ms = 100;
+        let (client_channel_tx, mut client_channel_rx): (
+            Sender<IcWsCanisterMessage>,
+            Receiver<IcWsCanisterMessage>,
+        ) = mpsc::channel(100);
+
+        // the poller is not restarted after failing
         let mut poller = create_poller(polling_interval_ms, client_channel_tx);
-        let handle = tokio::spawn(async move { poller.run_polling().await });
+        let handle = tokio::spawn(async move { poller.run_supervised_polling().await });
 
         let mut i = 0;
         while let Some((msg, _)) = client_channel_rx.recv().await {
@@ -397,20 +753,16 @@ mod test {
             assert_eq!(i, get_nonce_from_message(&msg.key).unwrap());
             i += 1;
         }
-        // when starting the second polling iteration, the poller terminates with an error
-        // therefore, 'client_channel_rx' receives 'None' and 'i' should be equal to 'msg_count'
-        // as the poller processed only the messages of the first polling iteration
-        assert_eq!(i as usize, msg_count);
+        // the poller terminates with an error before relaying the messages of the first polling iteration
+        // therefore, 'client_channel_rx' receives 'None' and 'i' should be equal to 0
+        assert_eq!(i, 0);
 
         // needed to make sure that the test fails in case the task panics
         let res = join!(handle).0.expect("task panicked");
-        // the poller should return an error as in the first iteration it polls the messages from 0 to 'msg_count - 1'
-        // in the second iteration it polls the messages which start again from 0 (as the mock server retruns the same messages)
-        // and therefore it returns an error as the poller was expecting the nonce to be equal to 'msg_count'
+        // the poller should return an error as the nonce of the last message cannot be parsed
         assert_eq!(
-            Err(format!(
-                "Non consecutive nonce: expected {}, got 0",
-                msg_count
+            Err(String::from(
+                "Could not parse nonce. Error: ParseIntError { kind: InvalidDigit }"
             )),
             res
         );
@@ -419,4 +771,363 @@ mod test {
         // just to make it explicit that the guard should be kept for the whole duration of the test
         drop(guard);
     }
+
+    #[tokio::test]
+    async fn should_restart_poller_until_budget_is_exhausted() {
+        let server = &*MOCK_SERVER;
+        let msg_count = 10;
+        let max_restarts = 2;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n_with_key_error(
+            msg_count, 0,
+        ));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        // the poller polls once before failing the first time and once more after each restart
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1 + max_restarts as usize)
+            .create_async()
+            .await;
+
+        let (client_channel_tx, mut client_channel_rx): (
+            Sender<IcWsCanisterMessage>,
+            Receiver<IcWsCanisterMessage>,
+        ) = mpsc::channel(100);
+
+        let mut poller = create_poller_with_config(
+            PollerConfig {
+                max_restarts,
+                ..MockPollerConfig::mock()
+            },
+            100,
+            client_channel_tx,
+            mpsc::channel(1).0,
+        );
+        let handle = tokio::spawn(async move { poller.run_supervised_polling().await });
+
+        let mut i = 0;
+        while let Some((msg, _)) = client_channel_rx.recv().await {
+            assert_eq!(i, get_nonce_from_message(&msg.key).unwrap());
+            i += 1;
+        }
+        // none of the restarted pollers relays the messages
+        // and the client is disconnected only once all the restarts failed
+        assert_eq!(i, 0);
+
+        // needed to make sure that the test fails in case the task panics
+        let res = join!(handle).0.expect("task panicked");
+        assert_eq!(
+            Err(String::from(
+                "Could not parse nonce. Error: ParseIntError { kind: InvalidDigit }"
+            )),
+            res
+        );
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn should_close_only_clients_affected_by_nonce_gap() {
+        let server = &*MOCK_SERVER;
+        let client_key = MockClientKey::mock();
+        let other_client_key = ClientKey::new(Principal::anonymous(), 1);
+        // the messages with nonces 3 and 4 have been deleted by the canister before being polled
+        // the message with nonce 3 was the second message for the first client
+        let body = serialize(CanisterOutputCertifiedMessages {
+            messages: vec![
+                MockCanisterOutputMessage::mock_with_sequence_num(0, client_key.clone(), 0),
+                MockCanisterOutputMessage::mock_with_sequence_num(1, other_client_key.clone(), 0),
+                MockCanisterOutputMessage::mock_with_sequence_num(2, other_client_key.clone(), 1),
+                MockCanisterOutputMessage::mock_with_sequence_num(5, client_key.clone(), 2),
+                MockCanisterOutputMessage::mock_with_sequence_num(6, other_client_key.clone(), 2),
+            ],
+            cert: Vec::default(),
+            tree: Vec::default(),
+            is_end_of_queue: Some(true),
+        });
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let (client_channel_tx, mut client_channel_rx) = mpsc::channel(100);
+        let (client_close_tx, mut client_close_rx) = mpsc::channel(1);
+        let (other_client_channel_tx, mut other_client_channel_rx) = mpsc::channel(100);
+        let (other_client_close_tx, mut other_client_close_rx) = mpsc::channel(1);
+        let mut poller = create_poller_with_clients(
+            MockPollerConfig::mock(),
+            10,
+            vec![
+                (
+                    client_key,
+                    ClientSender::new(client_channel_tx, Span::current(), client_close_tx, 0, None),
+                ),
+                (
+                    other_client_key,
+                    ClientSender::new(
+                        other_client_channel_tx,
+                        Span::current(),
+                        other_client_close_tx,
+                        1,
+                        None,
+                    ),
+                ),
+            ],
+        );
+        // the gap does not terminate the poller
+        poller.poll_and_relay().await.expect("Failed to poll");
+
+        // the first client is closed after the messages it received before the gap
+        let (msg, _): IcWsCanisterMessage = client_channel_rx.try_recv().expect("must be relayed");
+        assert_eq!(0, get_nonce_from_message(&msg.key).unwrap());
+        assert!(client_channel_rx.try_recv().is_err());
+        assert_eq!(
+            client_close_rx.try_recv(),
+            Ok(ClientCloseReason::MessagesLost)
+        );
+        // the other client receives all its messages and is not closed
+        for nonce in [1, 2, 6] {
+            let (msg, _): IcWsCanisterMessage =
+                other_client_channel_rx.try_recv().expect("must be relayed");
+            assert_eq!(nonce, get_nonce_from_message(&msg.key).unwrap());
+        }
+        assert!(other_client_close_rx.try_recv().is_err());
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn should_poll_again_from_first_nonce_after_backward_jump() {
+        let server = &*MOCK_SERVER;
+        let msg_count = 10;
+        let client_key = MockClientKey::mock();
+        let messages = (0..msg_count)
+            .map(|nonce| {
+                MockCanisterOutputMessage::mock_with_sequence_num(
+                    nonce,
+                    client_key.clone(),
+                    nonce as u64,
+                )
+            })
+            .collect();
+        // the mock server returns the same messages at each polling iteration
+        // therefore, from the second polling iteration, the nonces go back to 0
+        let body = serialize(CanisterOutputCertifiedMessages {
+            messages,
+            cert: Vec::default(),
+            tree: Vec::default(),
+            is_end_of_queue: Some(true),
+        });
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let (client_channel_tx, mut client_channel_rx): (
+            Sender<IcWsCanisterMessage>,
+            Receiver<IcWsCanisterMessage>,
+        ) = mpsc::channel(100);
+        let (client_close_tx, mut client_close_rx): (
+            Sender<ClientCloseReason>,
+            Receiver<ClientCloseReason>,
+        ) = mpsc::channel(1);
+        let mut poller = create_poller_with_config(
+            MockPollerConfig::mock(),
+            10,
+            client_channel_tx,
+            client_close_tx,
+        );
+
+        // the first polling iteration relays all the messages
+        poller.poll_and_relay().await.expect("Failed to poll");
+        // the second polling iteration detects the backward jump and does not relay the messages
+        poller.poll_and_relay().await.expect("Failed to poll");
+        // the third polling iteration polls from nonce 0 again, but the messages have already been relayed to the client
+        poller.poll_and_relay().await.expect("Failed to poll");
+
+        for i in 0..msg_count {
+            let (msg, _) = client_channel_rx.try_recv().expect("must be relayed");
+            assert_eq!(i as u64, get_nonce_from_message(&msg.key).unwrap());
+        }
+        assert!(client_channel_rx.try_recv().is_err());
+        assert!(client_close_rx.try_recv().is_err());
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn should_record_status_and_stop_polling_while_paused() {
+        let server = &*MOCK_SERVER;
+        let msg_count = 5;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(msg_count, 0));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        // the canister is not polled again once the poller is paused
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let gateway_state = GatewayState::new();
+        let poller_state = gateway_state
+            .insert_client_channel_and_get_new_poller_state(
+                Principal::anonymous(),
+                MockClientKey::mock(),
+                ClientSender::new(
+                    mpsc::channel(100).0,
+                    Span::current(),
+                    mpsc::channel(1).0,
+                    0,
+                    None,
+                ),
+                None,
+                DuplicateClientKeyPolicy::Reject,
+            )
+            .unwrap()
+            .expect("must be some");
+        let mut poller = create_poller_with_state(
+            MockPollerConfig::mock(),
+            10,
+            gateway_state.clone(),
+            Arc::clone(&poller_state),
+        );
+
+        assert_eq!(Ok(()), poller.poll_and_relay().await);
+        let status = poller_state.get_status();
+        assert_eq!(msg_count as u64, status.next_message_nonce);
+        assert_eq!(0, status.error_streak);
+        assert!(status.last_poll_time.is_some());
+
+        poller_state.pause();
+        let handle = tokio::spawn(async move { poller.run_polling().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // the paused poller terminates once its last client disconnects
+        gateway_state.remove_client(Principal::anonymous(), MockClientKey::mock(), 0);
+        let res = join!(handle).0.expect("task panicked");
+        assert_eq!(Ok(()), res);
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
+
+    fn insert_client_and_get_poller_state(gateway_state: &GatewayState) -> PollerState {
+        gateway_state
+            .insert_client_channel_and_get_new_poller_state(
+                Principal::anonymous(),
+                MockClientKey::mock(),
+                ClientSender::new(
+                    mpsc::channel(100).0,
+                    Span::current(),
+                    mpsc::channel(1).0,
+                    0,
+                    None,
+                ),
+                None,
+                DuplicateClientKeyPolicy::Reject,
+            )
+            .unwrap()
+            .expect("must be some")
+    }
+
+    #[tokio::test]
+    async fn should_resume_from_stored_nonce_only_if_clients_were_not_closed() {
+        let server = &*MOCK_SERVER;
+        let msg_count = 5;
+        let body = serialize(MockCanisterOutputCertifiedMessages::mock_n(msg_count, 0));
+        let path = "/ws_get_messages";
+        let mut guard = server.lock().await;
+        // do not drop the guard until the end of this test to make sure that no other test interleaves and overwrites the mock response
+        let mock = guard
+            .mock("GET", path)
+            .with_body(body)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let nonces_path = data_dir.path().join("poller_nonces.json");
+        let poller_nonces = Arc::new(PollerNonces::load(&nonces_path).unwrap());
+        let mut poller_config = MockPollerConfig::mock();
+        poller_config.poller_nonces = Some(Arc::clone(&poller_nonces));
+
+        let gateway_state = GatewayState::new();
+        let poller_state = insert_client_and_get_poller_state(&gateway_state);
+        let shutdown_token = CancellationToken::new();
+        let mut poller = create_poller_with_shutdown_token(
+            poller_config,
+            10,
+            gateway_state,
+            poller_state,
+            shutdown_token.clone(),
+        );
+        assert_eq!(Ok(()), poller.poll_and_relay().await);
+        assert_eq!(
+            Some(msg_count as u64),
+            poller_nonces.get(&Principal::anonymous())
+        );
+        poller_nonces.flush().unwrap();
+
+        // the gateway stopped without closing the clients, so the canister did not reset its queue
+        let restarted_poller_nonces = Arc::new(PollerNonces::load(&nonces_path).unwrap());
+        let mut restarted_poller_config = MockPollerConfig::mock();
+        restarted_poller_config.poller_nonces = Some(restarted_poller_nonces);
+        let restarted_gateway_state = GatewayState::new();
+        let restarted_poller_state = insert_client_and_get_poller_state(&restarted_gateway_state);
+        create_poller_with_state(
+            restarted_poller_config,
+            10,
+            restarted_gateway_state,
+            Arc::clone(&restarted_poller_state),
+        );
+        assert_eq!(
+            msg_count as u64,
+            restarted_poller_state.get_status().next_message_nonce
+        );
+
+        // the gateway shuts down gracefully, closing the clients, so the canister resets its queue
+        shutdown_token.cancel();
+        assert_eq!(Ok(()), poller.run_polling().await);
+        assert_eq!(None, poller_nonces.get(&Principal::anonymous()));
+        poller_nonces.flush().unwrap();
+
+        let restarted_poller_nonces = Arc::new(PollerNonces::load(&nonces_path).unwrap());
+        assert_eq!(None, restarted_poller_nonces.get(&Principal::anonymous()));
+        let mut restarted_poller_config = MockPollerConfig::mock();
+        restarted_poller_config.poller_nonces = Some(restarted_poller_nonces);
+        let restarted_gateway_state = GatewayState::new();
+        let restarted_poller_state = insert_client_and_get_poller_state(&restarted_gateway_state);
+        create_poller_with_state(
+            restarted_poller_config,
+            10,
+            restarted_gateway_state,
+            Arc::clone(&restarted_poller_state),
+        );
+        assert_eq!(0, restarted_poller_state.get_status().next_message_nonce);
+
+        mock.assert_async().await;
+        // just to make it explicit that the guard should be kept for the whole duration of the test
+        drop(guard);
+    }
 }
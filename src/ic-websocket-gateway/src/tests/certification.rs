@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod test {
+    use candid::Principal;
+    use canister_utils::{CanisterOutputCertifiedMessages, CanisterOutputMessage, ClientKey};
+    use ic_agent::{agent::http_transport::ReqwestTransport, Agent};
+    use ic_certification::{
+        hash_tree::{fork, label, leaf},
+        HashTree,
+    };
+    use sha2::{Digest, Sha256};
+
+    use crate::certification::{
+        verify_canister_output, verify_certified_messages, CertificationError,
+    };
+
+    fn message(key: &str, content: &[u8]) -> CanisterOutputMessage {
+        CanisterOutputMessage {
+            client_key: ClientKey::new(Principal::anonymous(), 0),
+            key: key.to_string(),
+            content: content.to_vec(),
+        }
+    }
+
+    /// Returns the tree certifying the hashes of the messages, as built by the CDK
+    fn messages_tree(messages: &[CanisterOutputMessage]) -> HashTree {
+        let subtree = messages
+            .iter()
+            .map(|message| {
+                label(
+                    message.key.as_str(),
+                    leaf(Sha256::digest(&message.content).to_vec()),
+                )
+            })
+            .reduce(fork)
+            .unwrap();
+        label("websocket", subtree)
+    }
+
+    #[test]
+    fn should_verify_certified_messages() {
+        let messages = vec![
+            message("gateway_0", b"hello"),
+            message("gateway_1", b"world"),
+        ];
+        let tree = messages_tree(&messages);
+        assert_eq!(Ok(()), verify_certified_messages(&tree, &messages));
+    }
+
+    #[test]
+    fn should_reject_tampered_message() {
+        let messages = vec![
+            message("gateway_0", b"hello"),
+            message("gateway_1", b"world"),
+        ];
+        let tree = messages_tree(&messages);
+        let tampered_messages = vec![
+            message("gateway_0", b"hello"),
+            message("gateway_1", b"forged"),
+        ];
+        assert_eq!(
+            Err(CertificationError::MessageNotCertified),
+            verify_certified_messages(&tree, &tampered_messages)
+        );
+    }
+
+    #[test]
+    fn should_reject_message_missing_from_tree() {
+        let tree = messages_tree(&[message("gateway_0", b"hello")]);
+        let messages = vec![
+            message("gateway_0", b"hello"),
+            message("gateway_1", b"world"),
+        ];
+        assert_eq!(
+            Err(CertificationError::MessageNotCertified),
+            verify_certified_messages(&tree, &messages)
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_certification() {
+        let agent = Agent::builder()
+            .with_transport(ReqwestTransport::create("http://127.0.0.1:4943").unwrap())
+            .build()
+            .unwrap();
+        let messages = vec![message("gateway_0", b"hello")];
+        let canister_output = CanisterOutputCertifiedMessages {
+            messages: messages.clone(),
+            cert: vec![],
+            tree: vec![],
+            is_end_of_queue: None,
+        };
+        assert_eq!(
+            Err(CertificationError::MalformedTree),
+            verify_canister_output(&agent, &Principal::anonymous(), &canister_output)
+        );
+
+        let canister_output = CanisterOutputCertifiedMessages {
+            tree: serde_cbor::to_vec(&messages_tree(&messages)).unwrap(),
+            ..canister_output
+        };
+        assert_eq!(
+            Err(CertificationError::MalformedCertificate),
+            verify_canister_output(&agent, &Principal::anonymous(), &canister_output)
+        );
+    }
+}
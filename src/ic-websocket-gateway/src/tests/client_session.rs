@@ -0,0 +1,301 @@
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        convert::Infallible,
+        future::pending,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use candid::Principal;
+    use futures_util::{SinkExt, StreamExt};
+    use hyper::{
+        body::to_bytes,
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+    use ic_agent::{
+        agent::{http_transport::ReqwestTransport, Envelope, EnvelopeContent},
+        Agent,
+    };
+    use tokio::{
+        io::{duplex, DuplexStream},
+        sync::Notify,
+        time::{sleep, timeout, Instant},
+    };
+    use tokio_tungstenite::{
+        tungstenite::{
+            protocol::{frame::coding::CloseCode, Role},
+            Message,
+        },
+        WebSocketStream,
+    };
+    use tokio_util::{sync::CancellationToken, task::TaskTracker};
+    use tracing::Span;
+
+    use crate::{
+        client_session::{
+            ClientMessagesPipeline, ClientSession, ClientSessionConfig, IcWsError,
+            IcWsSessionState, KeepaliveConfig,
+        },
+        rate_limiter::{ClientRateLimiter, RateLimitAction, RateLimiterConfig},
+    };
+
+    /// Calls received by the mock replica
+    #[derive(Default)]
+    struct ReceivedCalls {
+        /// Envelopes of the calls, in the order in which they were received
+        envelopes: Mutex<Vec<Vec<u8>>>,
+        /// Number of calls not accepted yet
+        pending_calls: AtomicUsize,
+        /// Maximum number of calls which were pending at the same time
+        max_pending_calls: AtomicUsize,
+        /// Notified whenever a call is received
+        received: Notify,
+    }
+
+    /// Starts a replica which accepts the calls after the delay, or never if None,
+    /// and returns its URL together with the calls it receives
+    fn start_replica(call_delay: Option<Duration>) -> (String, Arc<ReceivedCalls>) {
+        let received_calls = Arc::new(ReceivedCalls::default());
+        let make_service = {
+            let received_calls = Arc::clone(&received_calls);
+            make_service_fn(move |_| {
+                let received_calls = Arc::clone(&received_calls);
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let received_calls = Arc::clone(&received_calls);
+                        async move {
+                            let pending_calls =
+                                received_calls.pending_calls.fetch_add(1, Ordering::SeqCst) + 1;
+                            received_calls
+                                .max_pending_calls
+                                .fetch_max(pending_calls, Ordering::SeqCst);
+                            let envelope = to_bytes(request.into_body()).await.unwrap().to_vec();
+                            received_calls.envelopes.lock().unwrap().push(envelope);
+                            received_calls.received.notify_one();
+                            match call_delay {
+                                Some(call_delay) => sleep(call_delay).await,
+                                None => pending().await,
+                            }
+                            received_calls.pending_calls.fetch_sub(1, Ordering::SeqCst);
+                            Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(StatusCode::ACCEPTED)
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            )
+                        }
+                    }))
+                }
+            })
+        };
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let replica_url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (replica_url, received_calls)
+    }
+
+    fn agent(replica_url: &str) -> Arc<Agent> {
+        Arc::new(
+            Agent::builder()
+                .with_transport(ReqwestTransport::create(replica_url).unwrap())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    /// Returns a serialized envelope which differs from the other ones by its nonce
+    fn serialized_envelope(nonce: u8) -> Vec<u8> {
+        let envelope = Envelope {
+            content: Cow::Owned(EnvelopeContent::Call {
+                nonce: Some(vec![nonce]),
+                ingress_expiry: 0,
+                sender: Principal::anonymous(),
+                canister_id: canister_id(),
+                method_name: String::from("ws_message"),
+                arg: vec![],
+            }),
+            sender_pubkey: None,
+            sender_sig: None,
+            sender_delegation: None,
+        };
+        serde_cbor::to_vec(&envelope).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_relay_client_messages_in_order() {
+        let (replica_url, received_calls) = start_replica(Some(Duration::from_millis(20)));
+        let task_tracker = TaskTracker::new();
+        let client_messages_pipeline =
+            ClientMessagesPipeline::new(agent(&replica_url), 10, &task_tracker);
+
+        let envelopes: Vec<Vec<u8>> = (0..5).map(serialized_envelope).collect();
+        for envelope in &envelopes {
+            client_messages_pipeline
+                .enqueue(envelope.clone(), canister_id(), Span::current())
+                .expect("pipeline must not be full");
+        }
+        // the queued messages are still relayed after the session drops the pipeline
+        drop(client_messages_pipeline);
+        task_tracker.close();
+        timeout(Duration::from_secs(5), task_tracker.wait())
+            .await
+            .expect("relayer task must terminate");
+
+        assert_eq!(envelopes, *received_calls.envelopes.lock().unwrap());
+        // a message is relayed only once the previous one has been accepted
+        assert_eq!(1, received_calls.max_pending_calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn should_report_full_pipeline() {
+        // the replica never accepts the calls, so the relayer task is stuck on the first message
+        let (replica_url, received_calls) = start_replica(None);
+        let client_messages_pipeline =
+            ClientMessagesPipeline::new(agent(&replica_url), 2, &TaskTracker::new());
+
+        client_messages_pipeline
+            .enqueue(serialized_envelope(0), canister_id(), Span::current())
+            .expect("pipeline must not be full");
+        timeout(Duration::from_secs(5), received_calls.received.notified())
+            .await
+            .expect("first message must be relayed");
+
+        // the following messages wait in the pipeline until it is full
+        for nonce in 1..3 {
+            client_messages_pipeline
+                .enqueue(serialized_envelope(nonce), canister_id(), Span::current())
+                .expect("pipeline must not be full");
+        }
+        assert!(matches!(
+            client_messages_pipeline.enqueue(serialized_envelope(3), canister_id(), Span::current()),
+            Err(IcWsError::IcWsProtocol(e)) if e == "Too many client messages waiting to be relayed to the IC"
+        ));
+    }
+
+    const PING_INTERVAL: Duration = Duration::from_millis(100);
+    const PONG_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Starts a client session which sends pings to the client and returns the WebSocket of the client,
+    /// after reading the handshake message of the gateway, together with the task updating the session until it is closed
+    async fn start_keepalive_session() -> (
+        WebSocketStream<DuplexStream>,
+        tokio::task::JoinHandle<Result<ClientSession<DuplexStream>, IcWsError>>,
+    ) {
+        let (client_stream, gateway_stream) = duplex(4096);
+        let (ws_stream, mut client_ws_stream) = tokio::join!(
+            WebSocketStream::from_raw_socket(gateway_stream, Role::Server, None),
+            WebSocketStream::from_raw_socket(client_stream, Role::Client, None),
+        );
+        let rate_limiter = Arc::new(ClientRateLimiter::new(RateLimiterConfig {
+            session_rate_limit: None,
+            principal_rate_limit: None,
+            action: RateLimitAction::Close,
+        }));
+        let mut client_session = ClientSession::init(
+            0,
+            ws_stream,
+            agent("http://127.0.0.1:9"),
+            CancellationToken::new(),
+            &TaskTracker::new(),
+            ClientSessionConfig {
+                client_messages_pipeline_capacity: 10,
+                rate_limiter: rate_limiter.session_rate_limiter(),
+                keepalive_config: Some(KeepaliveConfig {
+                    ping_interval: PING_INTERVAL,
+                    pong_timeout: PONG_TIMEOUT,
+                }),
+            },
+        )
+        .await
+        .expect("session must start");
+        let session_task = tokio::spawn(async move {
+            loop {
+                if let Some(IcWsSessionState::Closed) = client_session.try_update_state().await? {
+                    // the session is returned so that its connection stays open while the client reads it
+                    return Ok(client_session);
+                }
+            }
+        });
+
+        let handshake_message = client_ws_stream.next().await.unwrap().unwrap();
+        assert!(handshake_message.is_binary());
+        (client_ws_stream, session_task)
+    }
+
+    #[tokio::test]
+    async fn should_send_ping_to_idle_client() {
+        let (mut client_ws_stream, _session_task) = start_keepalive_session().await;
+
+        let start = Instant::now();
+        let message = timeout(Duration::from_secs(5), client_ws_stream.next())
+            .await
+            .expect("ping must be sent")
+            .unwrap()
+            .unwrap();
+        assert!(message.is_ping());
+        assert!(start.elapsed() >= PING_INTERVAL - Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn should_keep_session_of_client_responding_to_pings() {
+        let (mut client_ws_stream, session_task) = start_keepalive_session().await;
+
+        // the WebSocket of the client responds to each ping with a pong while it is read
+        let mut pings = 0;
+        let reading = timeout(10 * (PING_INTERVAL + PONG_TIMEOUT), async {
+            while let Some(Ok(message)) = client_ws_stream.next().await {
+                assert!(message.is_ping());
+                pings += 1;
+            }
+        })
+        .await;
+        assert!(reading.is_err(), "session must not be closed");
+        assert!(pings > 1);
+        assert!(!session_task.is_finished());
+    }
+
+    #[tokio::test]
+    async fn should_reset_pong_deadline_when_client_sends_frame() {
+        let (mut client_ws_stream, session_task) = start_keepalive_session().await;
+
+        // any frame received before the pong deadline proves that the client is still connected
+        sleep(PING_INTERVAL + PONG_TIMEOUT / 2).await;
+        client_ws_stream
+            .send(Message::Pong(Vec::new()))
+            .await
+            .unwrap();
+        sleep(PONG_TIMEOUT).await;
+        assert!(!session_task.is_finished());
+    }
+
+    #[tokio::test]
+    async fn should_close_unresponsive_client() {
+        let (mut client_ws_stream, session_task) = start_keepalive_session().await;
+
+        // the client does not read the ping and therefore does not respond to it
+        let _client_session = timeout(2 * (PING_INTERVAL + PONG_TIMEOUT), session_task)
+            .await
+            .expect("session must be closed")
+            .unwrap()
+            .expect("session must be closed without error");
+
+        let ping = client_ws_stream.next().await.unwrap().unwrap();
+        assert!(ping.is_ping());
+        let Message::Close(Some(close_frame)) = client_ws_stream.next().await.unwrap().unwrap()
+        else {
+            panic!("close frame must be sent");
+        };
+        assert_eq!(CloseCode::Library(4007), close_frame.code);
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod test {
+    use std::{fs, sync::Arc};
+
+    use candid::Principal;
+    use canister_utils::ClientKey;
+    use ic_agent::{agent::http_transport::ReqwestTransport, Agent};
+    use tempfile::tempdir;
+
+    use crate::close_outbox::{CloseOutbox, CloseOutboxConfig, PendingClose};
+
+    /// Returns an agent connected to an address on which the IC is not reachable
+    fn unreachable_agent() -> Arc<Agent> {
+        Arc::new(
+            Agent::builder()
+                .with_transport(ReqwestTransport::create("http://127.0.0.1:9").unwrap())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn config(max_attempts: u32) -> CloseOutboxConfig {
+        CloseOutboxConfig {
+            retry_backoff_ms: 10,
+            max_retry_backoff_ms: 100,
+            max_attempts,
+        }
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_keep_close_pending_while_ic_is_unreachable() {
+        let close_outbox = CloseOutbox::new(unreachable_agent(), config(10));
+        let client_key = ClientKey::new(Principal::anonymous(), 1);
+        close_outbox.close(canister_id(), client_key.clone()).await;
+
+        assert_eq!(
+            vec![PendingClose {
+                canister_id: canister_id(),
+                client_key,
+                failed_attempts: 1,
+            }],
+            close_outbox.get_pending_closes()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_give_up_after_max_attempts() {
+        let close_outbox = CloseOutbox::new(unreachable_agent(), config(1));
+        close_outbox
+            .close(canister_id(), ClientKey::new(Principal::anonymous(), 1))
+            .await;
+
+        assert!(close_outbox.get_pending_closes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_cancel_close_when_client_registers_again() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("close_outbox.json");
+        let close_outbox = CloseOutbox::load(unreachable_agent(), config(10), &path).unwrap();
+        let client_key = ClientKey::new(Principal::anonymous(), 1);
+        close_outbox.close(canister_id(), client_key.clone()).await;
+        close_outbox
+            .close(canister_id(), ClientKey::new(Principal::anonymous(), 2))
+            .await;
+        close_outbox.flush().unwrap();
+
+        // the retry of the call would remove the new registration of the client
+        close_outbox.cancel(canister_id(), &client_key);
+        assert_eq!(
+            vec![ClientKey::new(Principal::anonymous(), 2)],
+            close_outbox
+                .get_pending_closes()
+                .into_iter()
+                .map(|pending_close| pending_close.client_key)
+                .collect::<Vec<_>>()
+        );
+
+        // the cancelled call is not retried after a restart either
+        close_outbox.flush().unwrap();
+        let reloaded_close_outbox =
+            CloseOutbox::load(unreachable_agent(), config(10), &path).unwrap();
+        assert_eq!(
+            close_outbox.get_pending_closes(),
+            reloaded_close_outbox.get_pending_closes()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_retry_persisted_closes_after_restart() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("close_outbox.json");
+        let close_outbox = CloseOutbox::load(unreachable_agent(), config(10), &path).unwrap();
+        close_outbox
+            .close(canister_id(), ClientKey::new(Principal::anonymous(), 1))
+            .await;
+        close_outbox
+            .close(canister_id(), ClientKey::new(Principal::anonymous(), 2))
+            .await;
+        close_outbox.flush().unwrap();
+
+        let reloaded_close_outbox =
+            CloseOutbox::load(unreachable_agent(), config(10), &path).unwrap();
+        assert_eq!(
+            close_outbox.get_pending_closes(),
+            reloaded_close_outbox.get_pending_closes()
+        );
+        assert_eq!(2, reloaded_close_outbox.get_pending_closes().len());
+    }
+
+    #[test]
+    fn should_reject_malformed_outbox_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("close_outbox.json");
+        fs::write(&path, "not json").unwrap();
+        assert!(CloseOutbox::load(unreachable_agent(), config(10), &path).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use crate::connection_authorizer::{
+        ClientCertificate, ClientCertificateSubjectAuthorizer, ConnectionAuthorizer,
+    };
+
+    fn client_addr() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 50000)
+    }
+
+    fn client_certificate(subject: &str) -> ClientCertificate {
+        ClientCertificate {
+            subject: subject.to_string(),
+        }
+    }
+
+    #[test]
+    fn should_authorize_any_client_without_allowed_subjects() {
+        let authorizer = ClientCertificateSubjectAuthorizer::new(vec![]);
+        assert!(authorizer.is_authorized(&client_addr(), None));
+        assert!(authorizer.is_authorized(
+            &client_addr(),
+            Some(&client_certificate("CN=internal-service"))
+        ));
+    }
+
+    #[test]
+    fn should_authorize_only_allowed_subjects() {
+        let authorizer = ClientCertificateSubjectAuthorizer::new(vec![String::from(
+            "CN=internal-service, O=Example",
+        )]);
+        assert!(authorizer.is_authorized(
+            &client_addr(),
+            Some(&client_certificate("CN=internal-service, O=Example"))
+        ));
+        assert!(!authorizer.is_authorized(
+            &client_addr(),
+            Some(&client_certificate("CN=other-service, O=Example"))
+        ));
+        // clients which do not present a certificate cannot be identified
+        assert!(!authorizer.is_authorized(&client_addr(), None));
+    }
+}
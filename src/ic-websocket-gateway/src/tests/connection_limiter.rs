@@ -0,0 +1,175 @@
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    use tokio_tungstenite::tungstenite::http::StatusCode;
+
+    use crate::{
+        connection_limiter::{ConnectionLimiter, ConnectionLimiterConfig, ConnectionRejection},
+        rate_limiter::RateLimit,
+    };
+
+    const MAX_CONCURRENT_HANDSHAKES: usize = 1000;
+    // long enough for the buckets not to be refilled while a test is running
+    const NEVER_REFILLED_INTERVAL_MS: u64 = 3_600_000;
+
+    fn ip(last_byte: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_byte))
+    }
+
+    #[test]
+    fn should_limit_concurrent_handshakes() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: 1,
+            max_connections_per_ip: None,
+            new_connections_per_ip_rate_limit: None,
+        });
+
+        let (handshake_permit, _ip_connection_permit) =
+            connection_limiter.try_accept(ip(1)).unwrap();
+        assert_eq!(
+            ConnectionRejection::TooManyHandshakes,
+            connection_limiter.try_accept(ip(2)).err().unwrap()
+        );
+
+        // once the handshake completes, another connection can perform it
+        drop(handshake_permit);
+        assert!(connection_limiter.try_accept(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn should_limit_connections_per_ip() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: MAX_CONCURRENT_HANDSHAKES,
+            max_connections_per_ip: Some(2),
+            new_connections_per_ip_rate_limit: None,
+        });
+
+        let (_, first_ip_connection_permit) = connection_limiter.try_accept(ip(1)).unwrap();
+        let (_, _second_ip_connection_permit) = connection_limiter.try_accept(ip(1)).unwrap();
+        assert_eq!(
+            ConnectionRejection::TooManyConnectionsFromIp,
+            connection_limiter.try_accept(ip(1)).err().unwrap()
+        );
+        // other IPs are not affected
+        assert!(connection_limiter.try_accept(ip(2)).is_ok());
+
+        // once a connection is closed, the IP can open another one
+        drop(first_ip_connection_permit);
+        assert!(connection_limiter.try_accept(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn should_release_ip_connection_permit_if_handshakes_are_saturated() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: 1,
+            max_connections_per_ip: Some(1),
+            new_connections_per_ip_rate_limit: None,
+        });
+
+        let (handshake_permit, _ip_connection_permit) =
+            connection_limiter.try_accept(ip(1)).unwrap();
+        assert_eq!(
+            ConnectionRejection::TooManyHandshakes,
+            connection_limiter.try_accept(ip(2)).err().unwrap()
+        );
+        drop(handshake_permit);
+        // the rejected connection did not count towards the limit of its IP
+        assert!(connection_limiter.try_accept(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn should_not_rate_limit_ip_if_handshakes_are_saturated() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: 1,
+            max_connections_per_ip: None,
+            new_connections_per_ip_rate_limit: Some(RateLimit {
+                burst: 1,
+                refill_interval: Duration::from_millis(NEVER_REFILLED_INTERVAL_MS),
+            }),
+        });
+
+        let (handshake_permit, _ip_connection_permit) =
+            connection_limiter.try_accept(ip(1)).unwrap();
+        assert_eq!(
+            ConnectionRejection::TooManyHandshakes,
+            connection_limiter.try_accept(ip(2)).err().unwrap()
+        );
+        drop(handshake_permit);
+        // the rejected connection did not use the token of its IP
+        assert!(connection_limiter.try_accept(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn should_release_handshake_permit_if_ip_is_rejected() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: 1,
+            max_connections_per_ip: Some(1),
+            new_connections_per_ip_rate_limit: None,
+        });
+
+        let (handshake_permit, _ip_connection_permit) =
+            connection_limiter.try_accept(ip(1)).unwrap();
+        drop(handshake_permit);
+        assert_eq!(
+            ConnectionRejection::TooManyConnectionsFromIp,
+            connection_limiter.try_accept(ip(1)).err().unwrap()
+        );
+        // the rejected connection does not hold the handshake permit
+        assert!(connection_limiter.try_accept(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn should_limit_new_connections_rate_per_ip() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: MAX_CONCURRENT_HANDSHAKES,
+            max_connections_per_ip: None,
+            new_connections_per_ip_rate_limit: Some(RateLimit {
+                burst: 2,
+                refill_interval: Duration::from_millis(NEVER_REFILLED_INTERVAL_MS),
+            }),
+        });
+
+        // the rate limit applies even if the previous connections are closed
+        assert!(connection_limiter.try_accept(ip(1)).is_ok());
+        assert!(connection_limiter.try_accept(ip(1)).is_ok());
+        assert_eq!(
+            ConnectionRejection::ConnectionRateExceeded,
+            connection_limiter.try_accept(ip(1)).err().unwrap()
+        );
+        assert!(connection_limiter.try_accept(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn should_check_ip_of_relayed_connections_separately() {
+        let connection_limiter = ConnectionLimiter::new(ConnectionLimiterConfig {
+            max_concurrent_handshakes: 1,
+            max_connections_per_ip: Some(1),
+            new_connections_per_ip_rate_limit: None,
+        });
+
+        // the IP of the client of a connection relayed by a proxy is checked once it is known
+        let _handshake_permit = connection_limiter.try_acquire_handshake_permit().unwrap();
+        let _ip_connection_permit = connection_limiter.try_accept_from_ip(ip(1)).unwrap();
+        assert_eq!(
+            ConnectionRejection::TooManyConnectionsFromIp,
+            connection_limiter.try_accept_from_ip(ip(1)).err().unwrap()
+        );
+        assert_eq!(
+            ConnectionRejection::TooManyHandshakes,
+            connection_limiter
+                .try_acquire_handshake_permit()
+                .err()
+                .unwrap()
+        );
+        assert_eq!(
+            StatusCode::TOO_MANY_REQUESTS,
+            ConnectionRejection::TooManyConnectionsFromIp
+                .to_error_response()
+                .status()
+        );
+    }
+}
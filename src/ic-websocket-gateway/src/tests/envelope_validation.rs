@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod test {
+    use candid::Principal;
+    use ic_agent::agent::EnvelopeContent;
+
+    use crate::envelope_validation::{
+        check_session_envelope, InvalidEnvelope, WS_MESSAGE_METHOD_NAME, WS_OPEN_METHOD_NAME,
+    };
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    fn client_principal() -> Principal {
+        Principal::self_authenticating([0; 32])
+    }
+
+    fn call_content(
+        canister_id: Principal,
+        sender: Principal,
+        method_name: &str,
+    ) -> EnvelopeContent {
+        EnvelopeContent::Call {
+            nonce: None,
+            ingress_expiry: 0,
+            sender,
+            canister_id,
+            method_name: method_name.to_string(),
+            arg: vec![],
+        }
+    }
+
+    #[test]
+    fn should_accept_envelope_of_the_session() {
+        assert_eq!(
+            Ok(()),
+            check_session_envelope(
+                &call_content(canister_id(), client_principal(), WS_MESSAGE_METHOD_NAME),
+                &canister_id(),
+                &client_principal(),
+                WS_MESSAGE_METHOD_NAME
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_for_other_canister() {
+        let other_canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        assert_eq!(
+            Err(InvalidEnvelope::CanisterMismatch),
+            check_session_envelope(
+                &call_content(
+                    other_canister_id,
+                    client_principal(),
+                    WS_MESSAGE_METHOD_NAME
+                ),
+                &canister_id(),
+                &client_principal(),
+                WS_MESSAGE_METHOD_NAME
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_of_other_sender() {
+        assert_eq!(
+            Err(InvalidEnvelope::SenderMismatch),
+            check_session_envelope(
+                &call_content(
+                    canister_id(),
+                    Principal::anonymous(),
+                    WS_MESSAGE_METHOD_NAME
+                ),
+                &canister_id(),
+                &client_principal(),
+                WS_MESSAGE_METHOD_NAME
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_calling_other_method() {
+        for method_name in [WS_OPEN_METHOD_NAME, "ws_close", "transfer"] {
+            assert_eq!(
+                Err(InvalidEnvelope::MethodNotAllowed),
+                check_session_envelope(
+                    &call_content(canister_id(), client_principal(), method_name),
+                    &canister_id(),
+                    &client_principal(),
+                    WS_MESSAGE_METHOD_NAME
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn should_reject_envelope_not_of_call_variant() {
+        let query_content = EnvelopeContent::Query {
+            ingress_expiry: 0,
+            sender: client_principal(),
+            canister_id: canister_id(),
+            method_name: WS_MESSAGE_METHOD_NAME.to_string(),
+            arg: vec![],
+            nonce: None,
+        };
+        assert_eq!(
+            Err(InvalidEnvelope::NotCall),
+            check_session_envelope(
+                &query_content,
+                &canister_id(),
+                &client_principal(),
+                WS_MESSAGE_METHOD_NAME
+            )
+        );
+    }
+}
@@ -0,0 +1,399 @@
+#[cfg(test)]
+mod test {
+    use std::{borrow::Cow, collections::BTreeMap, time::Duration};
+
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use candid::Principal;
+    use ed25519_dalek::{Signer, SigningKey};
+    use ic_agent::{
+        agent::{http_transport::ReqwestTransport, Envelope, EnvelopeContent},
+        identity::{Delegation, SignedDelegation},
+        Agent,
+    };
+    use serde_cbor::Value;
+    use sha2::{Digest, Sha256};
+
+    use crate::{envelope_validation::InvalidEnvelope, envelope_verification::verify_envelope};
+
+    /// DER prefix of the Ed25519 public keys
+    const ED25519_DER_PREFIX: [u8; 12] = [
+        0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+    ];
+
+    /// DER encoded OID of the WebAuthn public keys
+    const WEBAUTHN_OID_DER: [u8; 12] = [
+        0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xb8, 0x43, 0x01, 0x01,
+    ];
+
+    const NOW: Duration = Duration::from_secs(1_700_000_000);
+
+    fn agent() -> Agent {
+        Agent::builder()
+            .with_transport(ReqwestTransport::create("http://127.0.0.1:4943").unwrap())
+            .build()
+            .unwrap()
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn der_public_key(signing_key: &SigningKey) -> Vec<u8> {
+        [
+            ED25519_DER_PREFIX.as_slice(),
+            signing_key.verifying_key().as_bytes(),
+        ]
+        .concat()
+    }
+
+    fn call_content(sender: Principal, ingress_expiry: Duration) -> EnvelopeContent {
+        EnvelopeContent::Call {
+            nonce: None,
+            ingress_expiry: ingress_expiry.as_nanos() as u64,
+            sender,
+            canister_id: canister_id(),
+            method_name: String::from("ws_message"),
+            arg: vec![],
+        }
+    }
+
+    /// Returns the envelope signed by the key of the sender
+    fn signed_envelope(sender_key: &SigningKey, ingress_expiry: Duration) -> Envelope<'static> {
+        let sender_pubkey = der_public_key(sender_key);
+        let content = call_content(
+            Principal::self_authenticating(&sender_pubkey),
+            ingress_expiry,
+        );
+        let sender_sig = sender_key
+            .sign(&content.to_request_id().signable())
+            .to_vec();
+        Envelope {
+            content: Cow::Owned(content),
+            sender_pubkey: Some(sender_pubkey),
+            sender_sig: Some(sender_sig),
+            sender_delegation: None,
+        }
+    }
+
+    /// Returns the envelope of the sender signed by the session key it delegated to
+    fn delegated_envelope(
+        sender_key: &SigningKey,
+        session_key: &SigningKey,
+        expiration: Duration,
+        targets: Option<Vec<Principal>>,
+    ) -> Envelope<'static> {
+        let sender_pubkey = der_public_key(sender_key);
+        let delegation = Delegation {
+            pubkey: der_public_key(session_key),
+            expiration: expiration.as_nanos() as u64,
+            targets,
+        };
+        let signed_delegation = SignedDelegation {
+            signature: sender_key.sign(&delegation.signable()).to_vec(),
+            delegation,
+        };
+        let content = call_content(
+            Principal::self_authenticating(&sender_pubkey),
+            NOW + Duration::from_secs(60),
+        );
+        let sender_sig = session_key
+            .sign(&content.to_request_id().signable())
+            .to_vec();
+        Envelope {
+            content: Cow::Owned(content),
+            sender_pubkey: Some(sender_pubkey),
+            sender_sig: Some(sender_sig),
+            sender_delegation: Some(vec![signed_delegation]),
+        }
+    }
+
+    #[test]
+    fn should_accept_signed_envelope() {
+        let envelope = signed_envelope(&signing_key(1), NOW + Duration::from_secs(60));
+        assert_eq!(
+            Ok(()),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_accept_unsigned_envelope_of_anonymous_principal() {
+        let envelope = Envelope {
+            content: Cow::Owned(call_content(
+                Principal::anonymous(),
+                NOW + Duration::from_secs(60),
+            )),
+            sender_pubkey: None,
+            sender_sig: None,
+            sender_delegation: None,
+        };
+        assert_eq!(
+            Ok(()),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_expiry_out_of_window() {
+        let expired_envelope = signed_envelope(&signing_key(1), NOW - Duration::from_secs(120));
+        assert_eq!(
+            Err(InvalidEnvelope::Expired),
+            verify_envelope(&agent(), &expired_envelope, &canister_id(), NOW)
+        );
+        let far_envelope = signed_envelope(&signing_key(1), NOW + Duration::from_secs(10 * 60));
+        assert_eq!(
+            Err(InvalidEnvelope::ExpiryTooFar),
+            verify_envelope(&agent(), &far_envelope, &canister_id(), NOW)
+        );
+        // the clocks of the client and of the gateway might drift apart
+        let drifted_envelope = signed_envelope(&signing_key(1), NOW - Duration::from_secs(30));
+        assert_eq!(
+            Ok(()),
+            verify_envelope(&agent(), &drifted_envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_unsigned_envelope() {
+        let mut envelope = signed_envelope(&signing_key(1), NOW + Duration::from_secs(60));
+        envelope.sender_sig = None;
+        assert_eq!(
+            Err(InvalidEnvelope::MissingSignature),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_of_principal_not_matching_public_key() {
+        let mut envelope = signed_envelope(&signing_key(1), NOW + Duration::from_secs(60));
+        envelope.sender_pubkey = Some(der_public_key(&signing_key(2)));
+        assert_eq!(
+            Err(InvalidEnvelope::PrincipalMismatch),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_invalid_signature() {
+        let mut envelope = signed_envelope(&signing_key(1), NOW + Duration::from_secs(60));
+        envelope.sender_sig = Some(
+            signing_key(2)
+                .sign(&envelope.content.to_request_id().signable())
+                .to_vec(),
+        );
+        assert_eq!(
+            Err(InvalidEnvelope::InvalidSignature),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_unsupported_public_key() {
+        let mut envelope = signed_envelope(&signing_key(1), NOW + Duration::from_secs(60));
+        let sender_pubkey = vec![1, 2, 3];
+        envelope.content = Cow::Owned(call_content(
+            Principal::self_authenticating(&sender_pubkey),
+            NOW + Duration::from_secs(60),
+        ));
+        envelope.sender_pubkey = Some(sender_pubkey);
+        assert_eq!(
+            Err(InvalidEnvelope::UnsupportedPublicKey),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_accept_envelope_signed_by_delegated_key() {
+        let envelope = delegated_envelope(
+            &signing_key(1),
+            &signing_key(2),
+            NOW + Duration::from_secs(3600),
+            Some(vec![canister_id()]),
+        );
+        assert_eq!(
+            Ok(()),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_invalid_delegation() {
+        let mut envelope = delegated_envelope(
+            &signing_key(1),
+            &signing_key(2),
+            NOW + Duration::from_secs(3600),
+            None,
+        );
+        // the delegation is signed by a key other than the one of the sender
+        let signed_delegation = &mut envelope.sender_delegation.as_mut().unwrap()[0];
+        signed_delegation.signature = signing_key(3)
+            .sign(&signed_delegation.delegation.signable())
+            .to_vec();
+        assert_eq!(
+            Err(InvalidEnvelope::InvalidSignature),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_expired_delegation() {
+        let envelope = delegated_envelope(
+            &signing_key(1),
+            &signing_key(2),
+            NOW - Duration::from_secs(1),
+            None,
+        );
+        assert_eq!(
+            Err(InvalidEnvelope::DelegationExpired),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_delegation_to_other_canisters() {
+        let envelope = delegated_envelope(
+            &signing_key(1),
+            &signing_key(2),
+            NOW + Duration::from_secs(3600),
+            Some(vec![Principal::from_text("aaaaa-aa").unwrap()]),
+        );
+        assert_eq!(
+            Err(InvalidEnvelope::DelegationTargetMismatch),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    fn authenticator_key(seed: u8) -> p256::ecdsa::SigningKey {
+        p256::ecdsa::SigningKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    /// Returns the DER encoded COSE key of the authenticator
+    fn webauthn_der_public_key(authenticator_key: &p256::ecdsa::SigningKey) -> Vec<u8> {
+        let point = authenticator_key.verifying_key().to_encoded_point(false);
+        let cose_key = serde_cbor::to_vec(&Value::Map(BTreeMap::from([
+            (Value::Integer(1), Value::Integer(2)),
+            (Value::Integer(3), Value::Integer(-7)),
+            (Value::Integer(-1), Value::Integer(1)),
+            (
+                Value::Integer(-2),
+                Value::Bytes(point.x().unwrap().to_vec()),
+            ),
+            (
+                Value::Integer(-3),
+                Value::Bytes(point.y().unwrap().to_vec()),
+            ),
+        ])))
+        .unwrap();
+        // the COSE key is short enough for all the lengths to be encoded in a single byte
+        let algorithm = [
+            [0x30, WEBAUTHN_OID_DER.len() as u8].as_slice(),
+            &WEBAUTHN_OID_DER,
+        ]
+        .concat();
+        let key = [[0x03, cose_key.len() as u8 + 1, 0x00].as_slice(), &cose_key].concat();
+        [
+            [0x30, (algorithm.len() + key.len()) as u8].as_slice(),
+            &algorithm,
+            &key,
+        ]
+        .concat()
+    }
+
+    /// Returns the WebAuthn signature of the challenge by the authenticator
+    fn webauthn_signature(
+        authenticator_key: &p256::ecdsa::SigningKey,
+        challenge: &[u8],
+    ) -> Vec<u8> {
+        use p256::ecdsa::{signature::Signer, Signature};
+
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.get","challenge":"{}","origin":"https://identity.ic0.app"}}"#,
+            URL_SAFE_NO_PAD.encode(challenge)
+        );
+        // hash of the relying party ID, flags with the user present bit set and signature counter
+        let mut authenticator_data = vec![0; 37];
+        authenticator_data[32] = 0x01;
+        let signature: Signature = authenticator_key.sign(
+            &[
+                authenticator_data.as_slice(),
+                Sha256::digest(client_data_json.as_bytes()).as_slice(),
+            ]
+            .concat(),
+        );
+        let webauthn_signature = Value::Map(BTreeMap::from([
+            (
+                Value::Text(String::from("authenticator_data")),
+                Value::Bytes(authenticator_data),
+            ),
+            (
+                Value::Text(String::from("client_data_json")),
+                Value::Text(client_data_json),
+            ),
+            (
+                Value::Text(String::from("signature")),
+                Value::Bytes(signature.to_der().as_bytes().to_vec()),
+            ),
+        ]));
+        // the signature starts with the self-describe tag, as sent by the clients
+        [
+            [0xd9, 0xd9, 0xf7].as_slice(),
+            &serde_cbor::to_vec(&webauthn_signature).unwrap(),
+        ]
+        .concat()
+    }
+
+    /// Returns the envelope of the authenticator signed by the signing key
+    fn webauthn_envelope(
+        authenticator_key: &p256::ecdsa::SigningKey,
+        signing_key: &p256::ecdsa::SigningKey,
+    ) -> Envelope<'static> {
+        let sender_pubkey = webauthn_der_public_key(authenticator_key);
+        let content = call_content(
+            Principal::self_authenticating(&sender_pubkey),
+            NOW + Duration::from_secs(60),
+        );
+        let sender_sig = webauthn_signature(signing_key, &content.to_request_id().signable());
+        Envelope {
+            content: Cow::Owned(content),
+            sender_pubkey: Some(sender_pubkey),
+            sender_sig: Some(sender_sig),
+            sender_delegation: None,
+        }
+    }
+
+    #[test]
+    fn should_accept_envelope_signed_by_webauthn_authenticator() {
+        let envelope = webauthn_envelope(&authenticator_key(1), &authenticator_key(1));
+        assert_eq!(
+            Ok(()),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_envelope_with_forged_webauthn_signature() {
+        let envelope = webauthn_envelope(&authenticator_key(1), &authenticator_key(2));
+        assert_eq!(
+            Err(InvalidEnvelope::InvalidSignature),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+
+    #[test]
+    fn should_reject_webauthn_signature_of_other_challenge() {
+        let mut envelope = webauthn_envelope(&authenticator_key(1), &authenticator_key(1));
+        // the authenticator signed a valid WebAuthn assertion, but not for this envelope
+        envelope.sender_sig = Some(webauthn_signature(
+            &authenticator_key(1),
+            b"other challenge",
+        ));
+        assert_eq!(
+            Err(InvalidEnvelope::InvalidSignature),
+            verify_envelope(&agent(), &envelope, &canister_id(), NOW)
+        );
+    }
+}
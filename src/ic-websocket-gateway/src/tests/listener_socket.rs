@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf, str::FromStr};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+    };
+
+    use crate::listener_socket::{
+        ListenerAddress, ListenerDefinition, ListenerSocket, UNIX_SOCKET_PEER_ADDR,
+    };
+
+    #[test]
+    fn should_parse_listener_definitions() {
+        assert_eq!(
+            Ok(ListenerDefinition {
+                address: ListenerAddress::Tcp(String::from("0.0.0.0:8080")),
+                tls: false,
+            }),
+            ListenerDefinition::from_str("ws://0.0.0.0:8080")
+        );
+        assert_eq!(
+            Ok(ListenerDefinition {
+                address: ListenerAddress::Tcp(String::from("[::]:443")),
+                tls: true,
+            }),
+            ListenerDefinition::from_str("wss://[::]:443")
+        );
+        assert_eq!(
+            Ok(ListenerDefinition {
+                address: ListenerAddress::Unix(PathBuf::from("/run/ic-ws-gateway.sock")),
+                tls: false,
+            }),
+            ListenerDefinition::from_str("ws+unix:///run/ic-ws-gateway.sock")
+        );
+        assert_eq!(
+            Ok(ListenerDefinition {
+                address: ListenerAddress::Unix(PathBuf::from("/run/ic-ws-gateway.sock")),
+                tls: true,
+            }),
+            ListenerDefinition::from_str("wss+unix:///run/ic-ws-gateway.sock")
+        );
+        assert!(ListenerDefinition::from_str("0.0.0.0:8080").is_err());
+        assert!(ListenerDefinition::from_str("http://0.0.0.0:8080").is_err());
+        assert!(ListenerDefinition::from_str("wss://").is_err());
+    }
+
+    #[test]
+    fn should_display_listener_definitions() {
+        for definition in ["wss://[::]:443", "ws+unix:///run/ic-ws-gateway.sock"] {
+            assert_eq!(
+                definition,
+                ListenerDefinition::from_str(definition)
+                    .unwrap()
+                    .to_string()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_replace_stale_unix_socket() {
+        let dir = std::env::temp_dir().join("ic-ws-gateway-unix-listener");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gateway.sock");
+        let address = ListenerAddress::Unix(path.clone());
+
+        // the socket file is not removed when the listener is dropped
+        drop(ListenerSocket::bind(&address).unwrap());
+        assert!(path.exists());
+        let listener = ListenerSocket::bind(&address).unwrap();
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let (mut stream, peer_addr) = listener.accept().await.unwrap();
+        assert_eq!(UNIX_SOCKET_PEER_ADDR, peer_addr);
+        client.write_all(b"ping").await.unwrap();
+        let mut received = [0; 4];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(b"ping", &received);
+    }
+
+    #[tokio::test]
+    async fn should_tell_unix_sockets_apart() {
+        // the limits per IP are not applied to the peers of a Unix domain socket
+        let dir = std::env::temp_dir().join("ic-ws-gateway-unix-listener-kind");
+        fs::create_dir_all(&dir).unwrap();
+        let unix_listener =
+            ListenerSocket::bind(&ListenerAddress::Unix(dir.join("gateway.sock"))).unwrap();
+        assert!(unix_listener.is_unix());
+        let tcp_listener =
+            ListenerSocket::bind(&ListenerAddress::Tcp(String::from("127.0.0.1:0"))).unwrap();
+        assert!(!tcp_listener.is_unix());
+    }
+
+    #[tokio::test]
+    async fn should_not_replace_other_files() {
+        let dir = std::env::temp_dir().join("ic-ws-gateway-unix-listener-file");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gateway.sock");
+        fs::write(&path, "not a socket").unwrap();
+
+        assert!(ListenerSocket::bind(&ListenerAddress::Unix(path.clone())).is_err());
+        assert_eq!("not a socket", fs::read_to_string(&path).unwrap());
+    }
+}
@@ -0,0 +1,348 @@
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        collections::BTreeMap,
+        convert::Infallible,
+        future::pending,
+        net::SocketAddr,
+        path::Path,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use candid::Principal;
+    use canister_utils::{CanisterWsOpenArguments, ClientKey};
+    use futures_util::{SinkExt, StreamExt};
+    use gateway_state::{ClientSender, DuplicateClientKeyPolicy, GatewayState};
+    use hyper::{
+        body::to_bytes,
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+    use ic_agent::agent::{Envelope, EnvelopeContent};
+    use ic_identity::{get_identity_from_key_pair, load_key_pair};
+    use serde::{Deserialize, Serialize};
+    use tokio::{
+        net::UnixStream,
+        sync::{mpsc, Notify},
+        time::{timeout, Instant},
+    };
+    use tokio_tungstenite::{
+        client_async,
+        tungstenite::{protocol::frame::coding::CloseCode, Message},
+        MaybeTlsStream, WebSocketStream,
+    };
+    use tracing::Span;
+
+    use crate::{
+        canister_access::CanisterAccessControl,
+        canister_policies::{CanisterPolicies, CanisterPolicy},
+        canister_poller::{PollerConfig, SlowClientPolicy},
+        client_session_handler::ClientSessionHandlerConfig,
+        close_outbox::{CloseOutbox, CloseOutboxConfig},
+        connection_authorizer::ClientCertificateSubjectAuthorizer,
+        connection_limiter::{ConnectionLimiter, ConnectionLimiterConfig},
+        listener_socket::{ListenerAddress, ListenerDefinition},
+        manager::Manager,
+        rate_limiter::{ClientRateLimiter, RateLimitAction, RateLimiterConfig},
+        trusted_proxies::TrustedProxies,
+        upgrade_request::UpgradeRequestPolicy,
+        ws_listener::WsListenerConfig,
+    };
+
+    /// Message sent by the client to relay an envelope to the IC
+    #[derive(Serialize)]
+    struct ClientRequest<'a> {
+        envelope: Envelope<'a>,
+    }
+
+    /// Calls received by the mock replica
+    #[derive(Default)]
+    struct ReceivedCalls {
+        /// Names of the methods called, in the order in which the calls were received
+        method_names: Mutex<Vec<String>>,
+        /// Notified whenever a call is received
+        received: Notify,
+    }
+
+    impl ReceivedCalls {
+        fn count(&self, method_name: &str) -> usize {
+            self.method_names
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|received_method_name| *received_method_name == method_name)
+                .count()
+        }
+
+        async fn wait_for(&self, method_name: &str) {
+            loop {
+                let received = self.received.notified();
+                if self.count(method_name) > 0 {
+                    return;
+                }
+                received.await;
+            }
+        }
+    }
+
+    /// Starts a replica which accepts all the calls, except the ws_close calls if 'answer_ws_close' is false,
+    /// and returns its URL together with the calls it receives
+    fn start_replica(answer_ws_close: bool) -> (String, Arc<ReceivedCalls>) {
+        let received_calls = Arc::new(ReceivedCalls::default());
+        let make_service = {
+            let received_calls = Arc::clone(&received_calls);
+            make_service_fn(move |_| {
+                let received_calls = Arc::clone(&received_calls);
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let received_calls = Arc::clone(&received_calls);
+                        async move {
+                            let path = request.uri().path().to_string();
+                            if path.ends_with("/status") {
+                                // the agent fetches the root key of a local replica from its status
+                                let status = serde_cbor::Value::Map(BTreeMap::from([(
+                                    serde_cbor::Value::Text(String::from("root_key")),
+                                    serde_cbor::Value::Bytes(vec![0; 32]),
+                                )]));
+                                return Ok::<_, Infallible>(Response::new(Body::from(
+                                    serde_cbor::to_vec(&status).unwrap(),
+                                )));
+                            }
+                            if path.ends_with("/query") {
+                                pending::<()>().await;
+                            }
+                            if !path.ends_with("/call") {
+                                // the result of the ws_close calls is not checked by the test
+                                return Ok(Response::builder()
+                                    .status(StatusCode::NOT_FOUND)
+                                    .body(Body::empty())
+                                    .unwrap());
+                            }
+                            let body = to_bytes(request.into_body()).await.unwrap();
+                            let envelope: Envelope = serde_cbor::from_slice(&body).unwrap();
+                            let EnvelopeContent::Call { method_name, .. } = &*envelope.content
+                            else {
+                                panic!("replica received a call which is not an update call");
+                            };
+                            received_calls
+                                .method_names
+                                .lock()
+                                .unwrap()
+                                .push(method_name.clone());
+                            received_calls.received.notify_waiters();
+                            if method_name == "ws_close" && !answer_ws_close {
+                                pending::<()>().await;
+                            }
+                            Ok(Response::builder()
+                                .status(StatusCode::ACCEPTED)
+                                .body(Body::empty())
+                                .unwrap())
+                        }
+                    }))
+                }
+            })
+        };
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let replica_url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (replica_url, received_calls)
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    fn client_session_handler_config(manager: &Manager) -> ClientSessionHandlerConfig {
+        let close_outbox = Arc::new(CloseOutbox::new(
+            manager.get_agent(),
+            CloseOutboxConfig {
+                retry_backoff_ms: 10,
+                max_retry_backoff_ms: 100,
+                max_attempts: 1,
+            },
+        ));
+        ClientSessionHandlerConfig {
+            client_messages_pipeline_capacity: 10,
+            canister_policies: Arc::new(CanisterPolicies::new(CanisterPolicy {
+                polling_interval_ms: 100,
+                polling_timeout_ms: 5_000,
+                max_clients: None,
+                max_client_message_size: None,
+                client_queue_capacity: 100,
+            })),
+            canister_access: Arc::new(CanisterAccessControl::load(None, None).unwrap()),
+            poller_config: PollerConfig {
+                max_polling_interval_ms: None,
+                polling_interval_growth_factor: 2.0,
+                slow_client_policy: SlowClientPolicy::Close,
+                max_restarts: 0,
+                restart_backoff_ms: 10,
+                max_restart_backoff_ms: 100,
+                verify_certificates: false,
+                poller_nonces: None,
+                close_outbox: Arc::clone(&close_outbox),
+            },
+            client_rate_limiter: Arc::new(ClientRateLimiter::new(RateLimiterConfig {
+                session_rate_limit: None,
+                principal_rate_limit: None,
+                action: RateLimitAction::Close,
+            })),
+            upgrade_timeout_ms: 5_000,
+            upgrade_request_policy: Arc::new(UpgradeRequestPolicy::new(vec![], vec![])),
+            keepalive_config: None,
+            session_resumption: None,
+            duplicate_client_key_policy: DuplicateClientKeyPolicy::Reject,
+            verify_envelopes: false,
+            close_outbox,
+        }
+    }
+
+    fn ws_listener_config() -> WsListenerConfig {
+        WsListenerConfig {
+            tls_acceptor: None,
+            connection_authorizer: Arc::new(ClientCertificateSubjectAuthorizer::new(vec![])),
+            connection_limiter: Arc::new(ConnectionLimiter::new(ConnectionLimiterConfig {
+                max_concurrent_handshakes: 10,
+                max_connections_per_ip: None,
+                new_connections_per_ip_rate_limit: None,
+            })),
+            trusted_proxies: Arc::new(TrustedProxies::new(vec![], false, false)),
+        }
+    }
+
+    /// Registers a client of the canister without any session, so that the clients connecting to the canister
+    /// do not start a poller, which would poll the mock server of the poller tests
+    fn register_canister(gateway_state: &GatewayState) {
+        gateway_state
+            .insert_client_channel_and_get_new_poller_state(
+                canister_id(),
+                ClientKey::new(Principal::anonymous(), u64::MAX),
+                ClientSender::new(
+                    mpsc::channel(1).0,
+                    Span::current(),
+                    mpsc::channel(1).0,
+                    u64::MAX,
+                    None,
+                ),
+                None,
+                DuplicateClientKeyPolicy::Reject,
+            )
+            .unwrap();
+    }
+
+    /// Starts a gateway listening on a Unix domain socket in 'data_dir'
+    /// and returns its manager together with the path of the socket
+    async fn start_gateway(replica_url: String, data_dir: &Path) -> (Manager, String) {
+        let key_pair_path = data_dir.join("key_pair");
+        let identity =
+            get_identity_from_key_pair(load_key_pair(key_pair_path.to_str().unwrap()).unwrap());
+        let manager = Manager::new(replica_url, identity).await;
+        register_canister(&manager.get_state());
+
+        let socket_path = data_dir.join("gateway.sock");
+        manager
+            .start_accepting_incoming_connections(
+                vec![ListenerDefinition {
+                    address: ListenerAddress::Unix(socket_path.clone()),
+                    tls: false,
+                }],
+                ws_listener_config(),
+                client_session_handler_config(&manager),
+            )
+            .await;
+        (manager, socket_path.to_str().unwrap().to_string())
+    }
+
+    /// Connects a client to the gateway and opens its IC WS session
+    async fn open_session(socket_path: &str) -> WebSocketStream<MaybeTlsStream<UnixStream>> {
+        let stream = UnixStream::connect(socket_path).await.unwrap();
+        let (mut ws_stream, _) = client_async("ws://localhost/", MaybeTlsStream::Plain(stream))
+            .await
+            .unwrap();
+
+        #[derive(Deserialize)]
+        struct GatewayHandshakeMessage {
+            gateway_principal: Principal,
+        }
+        let Some(Ok(Message::Binary(bytes))) = ws_stream.next().await else {
+            panic!("gateway did not send the handshake message");
+        };
+        let handshake_message: GatewayHandshakeMessage = serde_cbor::from_slice(&bytes).unwrap();
+
+        let ws_open_arguments = CanisterWsOpenArguments {
+            client_nonce: 0,
+            gateway_principal: handshake_message.gateway_principal,
+        };
+        let client_request = ClientRequest {
+            envelope: Envelope {
+                content: Cow::Owned(EnvelopeContent::Call {
+                    nonce: None,
+                    ingress_expiry: 0,
+                    sender: Principal::anonymous(),
+                    canister_id: canister_id(),
+                    method_name: String::from("ws_open"),
+                    arg: candid::encode_one(ws_open_arguments).unwrap(),
+                }),
+                sender_pubkey: None,
+                sender_sig: None,
+                sender_delegation: None,
+            },
+        };
+        ws_stream
+            .send(Message::Binary(
+                serde_cbor::to_vec(&client_request).unwrap(),
+            ))
+            .await
+            .unwrap();
+        ws_stream
+    }
+
+    #[tokio::test]
+    async fn should_call_ws_close_for_open_sessions_on_shutdown() {
+        let (replica_url, received_calls) = start_replica(true);
+        let data_dir = tempfile::tempdir().unwrap();
+        let (manager, socket_path) = start_gateway(replica_url, data_dir.path()).await;
+
+        let mut ws_stream = open_session(&socket_path).await;
+        // the client is registered in the gateway state before the WS open message is relayed to the canister
+        timeout(Duration::from_secs(5), received_calls.wait_for("ws_open"))
+            .await
+            .expect("WS open message was not relayed");
+        assert_eq!(0, received_calls.count("ws_close"));
+
+        let graceful_shutdown_timeout = Duration::from_secs(5);
+        let start = Instant::now();
+        manager.shutdown(graceful_shutdown_timeout).await;
+        assert!(start.elapsed() < graceful_shutdown_timeout);
+
+        // the session is closed before the gateway terminates
+        assert_eq!(1, received_calls.count("ws_close"));
+        let Some(Ok(Message::Close(Some(close_frame)))) = ws_stream.next().await else {
+            panic!("client did not receive a close frame");
+        };
+        assert_eq!(CloseCode::Away, close_frame.code);
+    }
+
+    #[tokio::test]
+    async fn should_stop_waiting_for_sessions_after_graceful_shutdown_timeout() {
+        let (replica_url, received_calls) = start_replica(false);
+        let data_dir = tempfile::tempdir().unwrap();
+        let (manager, socket_path) = start_gateway(replica_url, data_dir.path()).await;
+
+        let _ws_stream = open_session(&socket_path).await;
+        timeout(Duration::from_secs(5), received_calls.wait_for("ws_open"))
+            .await
+            .expect("WS open message was not relayed");
+
+        // the session never terminates as the canister does not answer the ws_close call
+        let graceful_shutdown_timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        manager.shutdown(graceful_shutdown_timeout).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= graceful_shutdown_timeout);
+        assert!(elapsed < graceful_shutdown_timeout * 5);
+        assert_eq!(1, received_calls.count("ws_close"));
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use candid::Principal;
+    use tempfile::tempdir;
+
+    use crate::poller_nonces::PollerNonces;
+
+    fn canister_id() -> Principal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    #[test]
+    fn should_start_without_nonces_if_file_does_not_exist() {
+        let dir = tempdir().unwrap();
+        let poller_nonces = PollerNonces::load(dir.path().join("poller_nonces.json")).unwrap();
+        assert_eq!(None, poller_nonces.get(&canister_id()));
+    }
+
+    #[test]
+    fn should_resume_from_flushed_nonces() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("poller_nonces.json");
+        let poller_nonces = PollerNonces::load(&path).unwrap();
+        poller_nonces.set(canister_id(), 10);
+        poller_nonces.set(Principal::anonymous(), 20);
+        // the nonces are not written until they are flushed
+        assert!(!path.exists());
+        poller_nonces.flush().unwrap();
+
+        let reloaded_poller_nonces = PollerNonces::load(&path).unwrap();
+        assert_eq!(Some(10), reloaded_poller_nonces.get(&canister_id()));
+        assert_eq!(
+            Some(20),
+            reloaded_poller_nonces.get(&Principal::anonymous())
+        );
+        // no temporary file is left behind
+        assert_eq!(1, fs::read_dir(path.parent().unwrap()).unwrap().count());
+    }
+
+    #[test]
+    fn should_reset_nonces() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("poller_nonces.json");
+        let poller_nonces = PollerNonces::load(&path).unwrap();
+        poller_nonces.set(canister_id(), 10);
+        poller_nonces.set(Principal::anonymous(), 20);
+        poller_nonces.flush().unwrap();
+
+        assert!(poller_nonces.remove(&canister_id()));
+        assert!(!poller_nonces.remove(&canister_id()));
+        poller_nonces.flush().unwrap();
+        let reloaded_poller_nonces = PollerNonces::load(&path).unwrap();
+        assert_eq!(None, reloaded_poller_nonces.get(&canister_id()));
+        assert_eq!(
+            Some(20),
+            reloaded_poller_nonces.get(&Principal::anonymous())
+        );
+
+        reloaded_poller_nonces.clear();
+        reloaded_poller_nonces.flush().unwrap();
+        assert!(PollerNonces::load(&path).unwrap().get_all().is_empty());
+    }
+
+    #[test]
+    fn should_reject_malformed_nonces_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("poller_nonces.json");
+        fs::write(&path, r#"{ "not-a-principal": 10 }"#).unwrap();
+        assert!(PollerNonces::load(&path).is_err());
+        fs::write(&path, "not json").unwrap();
+        assert!(PollerNonces::load(&path).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::proxy_protocol::{parse_proxy_header_v1, parse_proxy_header_v2, read_proxy_header};
+
+    const V2_SIGNATURE: [u8; 12] = [
+        0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+    ];
+
+    fn socket_addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    /// Builds a v2 header relaying a TCP over IPv4 connection from 192.0.2.1:56324 to 198.51.100.1:443
+    fn proxy_header_v2_ipv4() -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        header.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1]);
+        header.extend_from_slice(&56324u16.to_be_bytes());
+        header.extend_from_slice(&443u16.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn should_parse_proxy_header_v1() {
+        assert_eq!(
+            Ok(Some(socket_addr("192.0.2.1:56324"))),
+            parse_proxy_header_v1(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n")
+        );
+        assert_eq!(
+            Ok(Some(socket_addr("[2001:db8::1]:56324"))),
+            parse_proxy_header_v1(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n")
+        );
+        assert_eq!(Ok(None), parse_proxy_header_v1(b"PROXY UNKNOWN\r\n"));
+        assert!(
+            parse_proxy_header_v1(b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n").is_err()
+        );
+        assert!(parse_proxy_header_v1(b"PROXY TCP4 192.0.2.1 198.51.100.1 70000 443\r\n").is_err());
+        assert!(parse_proxy_header_v1(b"PROXY TCP4 192.0.2.1\r\n").is_err());
+    }
+
+    #[test]
+    fn should_parse_proxy_header_v2() {
+        let header = proxy_header_v2_ipv4();
+        assert_eq!(
+            Ok(Some(socket_addr("192.0.2.1:56324"))),
+            parse_proxy_header_v2(header[12], header[13], &header[16..])
+        );
+
+        let mut addresses = vec![0; 36];
+        addresses[0] = 0x20;
+        addresses[1] = 0x01;
+        addresses[15] = 0x01;
+        addresses[32..34].copy_from_slice(&56324u16.to_be_bytes());
+        assert_eq!(
+            Ok(Some(socket_addr("[2001::1]:56324"))),
+            parse_proxy_header_v2(0x21, 0x21, &addresses)
+        );
+
+        // LOCAL command, e.g. health checks of the proxy
+        assert_eq!(Ok(None), parse_proxy_header_v2(0x20, 0x00, &[]));
+        // unsupported version
+        assert!(parse_proxy_header_v2(0x11, 0x11, &header[16..]).is_err());
+        // addresses too short for the family
+        assert!(parse_proxy_header_v2(0x21, 0x11, &header[16..20]).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_read_proxy_header_without_consuming_following_data() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1";
+        assert_eq!(
+            Ok(Some(socket_addr("192.0.2.1:56324"))),
+            read_proxy_header(&mut stream).await
+        );
+        assert_eq!(b"GET / HTTP/1.1", stream);
+
+        let mut data = proxy_header_v2_ipv4();
+        data.extend_from_slice(b"GET / HTTP/1.1");
+        let mut stream: &[u8] = &data;
+        assert_eq!(
+            Ok(Some(socket_addr("192.0.2.1:56324"))),
+            read_proxy_header(&mut stream).await
+        );
+        assert_eq!(b"GET / HTTP/1.1", stream);
+    }
+
+    #[tokio::test]
+    async fn should_not_read_invalid_proxy_header() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nHost: gateway.example\r\n";
+        assert!(read_proxy_header(&mut stream).await.is_err());
+
+        // the header must end within the maximum length of a v1 header
+        let mut stream: &[u8] = &[b"PROXY ".as_slice(), &[b'1'; 200]].concat();
+        assert!(read_proxy_header(&mut stream).await.is_err());
+
+        // the connection is closed before the end of the header
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1";
+        assert!(read_proxy_header(&mut stream).await.is_err());
+    }
+}
@@ -0,0 +1,135 @@
+#[cfg(test)]
+mod test {
+    use candid::Principal;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::rate_limiter::{
+        ClientRateLimiter, RateLimit, RateLimitAction, RateLimitScope, RateLimiterConfig,
+        TokenBucket,
+    };
+
+    const REFILL_INTERVAL_MS: u64 = 100;
+    // long enough for the buckets not to be refilled while a test is running
+    const NEVER_REFILLED_INTERVAL_MS: u64 = 3_600_000;
+
+    fn rate_limit(burst: u32, refill_interval_ms: u64) -> RateLimit {
+        RateLimit {
+            burst,
+            refill_interval: Duration::from_millis(refill_interval_ms),
+        }
+    }
+
+    fn client_rate_limiter(
+        session_rate_limit: Option<RateLimit>,
+        principal_rate_limit: Option<RateLimit>,
+    ) -> Arc<ClientRateLimiter> {
+        Arc::new(ClientRateLimiter::new(RateLimiterConfig {
+            session_rate_limit,
+            principal_rate_limit,
+            action: RateLimitAction::Drop,
+        }))
+    }
+
+    #[test]
+    fn should_allow_burst_and_then_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(rate_limit(2, REFILL_INTERVAL_MS), start);
+
+        assert!(bucket.try_acquire(start));
+        assert!(bucket.try_acquire(start));
+        assert!(!bucket.try_acquire(start));
+
+        // half of a token has been refilled
+        let now = start + Duration::from_millis(REFILL_INTERVAL_MS / 2);
+        assert!(!bucket.try_acquire(now));
+
+        let now = start + Duration::from_millis(REFILL_INTERVAL_MS);
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+    }
+
+    #[test]
+    fn should_not_refill_more_than_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(rate_limit(2, REFILL_INTERVAL_MS), start);
+        assert!(bucket.try_acquire(start));
+        assert!(!bucket.is_full(start));
+
+        let now = start + Duration::from_millis(10 * REFILL_INTERVAL_MS);
+        assert!(bucket.is_full(now));
+        assert!(bucket.try_acquire(now));
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+    }
+
+    #[test]
+    fn should_limit_each_session_independently() {
+        let client_rate_limiter =
+            client_rate_limiter(Some(rate_limit(1, NEVER_REFILLED_INTERVAL_MS)), None);
+        let client_principal = Principal::from_slice(&[1]);
+        let mut first_session = client_rate_limiter.session_rate_limiter();
+        let mut second_session = client_rate_limiter.session_rate_limiter();
+
+        assert_eq!(Ok(()), first_session.check(&client_principal));
+        assert_eq!(
+            Err(RateLimitScope::Session),
+            first_session.check(&client_principal)
+        );
+        assert_eq!(Ok(()), second_session.check(&client_principal));
+    }
+
+    #[test]
+    fn should_limit_all_sessions_of_principal() {
+        let client_rate_limiter =
+            client_rate_limiter(None, Some(rate_limit(1, NEVER_REFILLED_INTERVAL_MS)));
+        let client_principal = Principal::from_slice(&[1]);
+        let mut first_session = client_rate_limiter.session_rate_limiter();
+        let mut second_session = client_rate_limiter.session_rate_limiter();
+
+        assert_eq!(Ok(()), first_session.check(&client_principal));
+        assert_eq!(
+            Err(RateLimitScope::Principal),
+            second_session.check(&client_principal)
+        );
+        // other principals have their own bucket
+        assert_eq!(Ok(()), second_session.check(&Principal::from_slice(&[2])));
+    }
+
+    #[test]
+    fn should_not_limit_anonymous_principal() {
+        let client_rate_limiter =
+            client_rate_limiter(None, Some(rate_limit(1, NEVER_REFILLED_INTERVAL_MS)));
+        let mut session = client_rate_limiter.session_rate_limiter();
+
+        assert_eq!(Ok(()), session.check(&Principal::anonymous()));
+        assert_eq!(Ok(()), session.check(&Principal::anonymous()));
+    }
+
+    #[test]
+    fn should_not_limit_without_rate_limits() {
+        let client_rate_limiter = client_rate_limiter(None, None);
+        let mut session = client_rate_limiter.session_rate_limiter();
+        for _ in 0..100 {
+            assert_eq!(Ok(()), session.check(&Principal::from_slice(&[1])));
+        }
+    }
+
+    #[test]
+    fn should_keep_buckets_of_active_principals() {
+        let client_rate_limiter =
+            client_rate_limiter(None, Some(rate_limit(1, NEVER_REFILLED_INTERVAL_MS)));
+        let client_principal = Principal::from_slice(&[1]);
+        let mut session = client_rate_limiter.session_rate_limiter();
+        assert_eq!(Ok(()), session.check(&client_principal));
+
+        // the bucket is not full, therefore removing it would reset the limit of the principal
+        client_rate_limiter.remove_idle_buckets();
+        assert_eq!(
+            Err(RateLimitScope::Principal),
+            session.check(&client_principal)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use candid::Principal;
+    use canister_utils::{CanisterToClientMessage, ClientKey, IcWsCanisterMessage};
+    use gateway_state::{CanisterPrincipal, ClientCloseReason};
+    use tokio::sync::mpsc::{self, Sender};
+    use tracing::Span;
+
+    use crate::session_resumption::{DetachedSession, SessionResumption};
+
+    fn canister_id() -> CanisterPrincipal {
+        Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap()
+    }
+
+    fn client_key() -> ClientKey {
+        ClientKey::new(Principal::anonymous(), 0)
+    }
+
+    fn detached_session(
+        client_id: u64,
+        canister_id: CanisterPrincipal,
+    ) -> (DetachedSession, Sender<IcWsCanisterMessage>) {
+        let (client_channel_tx, client_channel_rx) = mpsc::channel(10);
+        let (_, client_close_rx) = mpsc::channel::<ClientCloseReason>(1);
+        (
+            DetachedSession {
+                client_id,
+                canister_id,
+                client_channel_rx,
+                client_close_rx,
+                session_token: client_id,
+            },
+            client_channel_tx,
+        )
+    }
+
+    fn canister_message(key: &str) -> IcWsCanisterMessage {
+        (
+            CanisterToClientMessage {
+                key: key.to_string(),
+                content: vec![],
+                cert: vec![],
+                tree: vec![],
+            },
+            Span::none(),
+        )
+    }
+
+    #[test]
+    fn should_replay_buffered_messages_to_resumed_session() {
+        let session_resumption = SessionResumption::new(Duration::from_secs(30));
+        let (detached_session, client_channel_tx) = detached_session(1, canister_id());
+        session_resumption.detach(client_key(), detached_session);
+
+        // the poller keeps relaying the canister messages while the client is disconnected
+        client_channel_tx
+            .try_send(canister_message("gateway_0"))
+            .unwrap();
+        client_channel_tx
+            .try_send(canister_message("gateway_1"))
+            .unwrap();
+
+        assert!(session_resumption.is_detached(canister_id(), &client_key()));
+        let mut resumed_session = session_resumption
+            .resume(canister_id(), &client_key())
+            .unwrap();
+        assert_eq!(
+            "gateway_0",
+            resumed_session.client_channel_rx.try_recv().unwrap().0.key
+        );
+        assert_eq!(
+            "gateway_1",
+            resumed_session.client_channel_rx.try_recv().unwrap().0.key
+        );
+
+        // a session can be resumed only once
+        assert!(!session_resumption.is_detached(canister_id(), &client_key()));
+        assert!(session_resumption
+            .resume(canister_id(), &client_key())
+            .is_none());
+    }
+
+    #[test]
+    fn should_not_resume_session_with_other_canister() {
+        let session_resumption = SessionResumption::new(Duration::from_secs(30));
+        let (detached_session, _client_channel_tx) = detached_session(1, canister_id());
+        session_resumption.detach(client_key(), detached_session);
+
+        let other_canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        assert!(!session_resumption.is_detached(other_canister_id, &client_key()));
+        assert!(session_resumption
+            .resume(other_canister_id, &client_key())
+            .is_none());
+        assert!(session_resumption.is_detached(canister_id(), &client_key()));
+    }
+
+    #[test]
+    fn should_expire_only_session_detached_by_same_connection() {
+        let session_resumption = SessionResumption::new(Duration::from_secs(30));
+        let (detached_session, _client_channel_tx) = detached_session(1, canister_id());
+        session_resumption.detach(client_key(), detached_session);
+
+        // the session is resumed by connection 2, which then loses its connection as well
+        let resumed_session = session_resumption
+            .resume(canister_id(), &client_key())
+            .unwrap();
+        session_resumption.detach(
+            client_key(),
+            DetachedSession {
+                client_id: 2,
+                ..resumed_session
+            },
+        );
+
+        // the grace period given to connection 1 does not apply to the session detached by connection 2
+        assert!(session_resumption.expire(&client_key(), 1).is_none());
+        assert!(session_resumption.is_detached(canister_id(), &client_key()));
+        assert!(session_resumption.expire(&client_key(), 2).is_some());
+        assert!(!session_resumption.is_detached(canister_id(), &client_key()));
+    }
+}
@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, SocketAddr};
+
+    use tokio_tungstenite::tungstenite::handshake::server::Request;
+
+    use crate::trusted_proxies::TrustedProxies;
+
+    fn trusted_proxies(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(
+            networks
+                .iter()
+                .map(|network| network.parse().unwrap())
+                .collect(),
+            true,
+            true,
+        )
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn request(forwarded_for: &[&str]) -> Request {
+        let mut request = Request::builder().uri("/");
+        for value in forwarded_for {
+            request = request.header("X-Forwarded-For", *value);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn should_trust_only_listed_networks() {
+        let trusted_proxies = trusted_proxies(&["10.0.0.0/8", "2001:db8::/32"]);
+        assert!(trusted_proxies.is_trusted(&ip("10.1.2.3")));
+        assert!(trusted_proxies.is_trusted(&ip("2001:db8::1")));
+        // IPv4 clients of a dual stack listener
+        assert!(trusted_proxies.is_trusted(&ip("::ffff:10.1.2.3")));
+        assert!(!trusted_proxies.is_trusted(&ip("192.0.2.1")));
+
+        let peer_addr: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        assert!(!trusted_proxies.expects_proxy_header(&peer_addr));
+        assert!(!trusted_proxies.expects_forwarded_for(&peer_addr));
+    }
+
+    #[test]
+    fn should_not_expect_disabled_headers() {
+        let trusted_proxies = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()], false, true);
+        let peer_addr: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        assert!(!trusted_proxies.expects_proxy_header(&peer_addr));
+        assert!(trusted_proxies.expects_forwarded_for(&peer_addr));
+    }
+
+    #[test]
+    fn should_get_rightmost_untrusted_forwarded_ip() {
+        let trusted_proxies = trusted_proxies(&["10.0.0.0/8"]);
+        assert_eq!(
+            Some(ip("192.0.2.1")),
+            trusted_proxies.get_forwarded_client_ip(&request(&["192.0.2.1"]))
+        );
+        // the addresses on the left of the client could have been forged
+        assert_eq!(
+            Some(ip("192.0.2.1")),
+            trusted_proxies
+                .get_forwarded_client_ip(&request(&["198.51.100.1, 192.0.2.1, 10.0.0.2"]))
+        );
+        // the header may be repeated and the addresses may have a port
+        assert_eq!(
+            Some(ip("2001:db8::1")),
+            trusted_proxies.get_forwarded_client_ip(&request(&[
+                "198.51.100.1",
+                "[2001:db8::1]:56324, 10.0.0.2"
+            ]))
+        );
+        // requests sent through trusted proxies only
+        assert_eq!(
+            Some(ip("10.0.0.3")),
+            trusted_proxies.get_forwarded_client_ip(&request(&["10.0.0.3, 10.0.0.2"]))
+        );
+    }
+
+    #[test]
+    fn should_not_get_invalid_forwarded_ip() {
+        let trusted_proxies = trusted_proxies(&["10.0.0.0/8"]);
+        assert_eq!(None, trusted_proxies.get_forwarded_client_ip(&request(&[])));
+        assert_eq!(
+            None,
+            trusted_proxies.get_forwarded_client_ip(&request(&["unknown, 10.0.0.2"]))
+        );
+    }
+}
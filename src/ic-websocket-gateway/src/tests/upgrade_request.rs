@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod test {
+    use tokio_tungstenite::tungstenite::{handshake::server::Request, http::StatusCode};
+
+    use crate::upgrade_request::{UpgradeRejection, UpgradeRequestPolicy};
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::builder().uri("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap()
+    }
+
+    fn to_strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn should_accept_any_request_without_policy() {
+        let policy = UpgradeRequestPolicy::default();
+        assert_eq!(Ok(()), policy.validate(&request(&[])));
+        assert_eq!(
+            Ok(()),
+            policy.validate(&request(&[
+                ("Origin", "https://evil.example"),
+                ("Host", "gateway.example:8080")
+            ]))
+        );
+    }
+
+    #[test]
+    fn should_accept_only_allowed_origins() {
+        let policy = UpgradeRequestPolicy::new(to_strings(&["https://app.example"]), vec![]);
+        assert_eq!(
+            Ok(()),
+            policy.validate(&request(&[("Origin", "https://App.example")]))
+        );
+        assert_eq!(
+            Err(UpgradeRejection::OriginNotAllowed),
+            policy.validate(&request(&[("Origin", "https://evil.example")]))
+        );
+        assert_eq!(
+            Err(UpgradeRejection::OriginNotAllowed),
+            policy.validate(&request(&[]))
+        );
+    }
+
+    #[test]
+    fn should_accept_only_allowed_hosts() {
+        let policy = UpgradeRequestPolicy::new(vec![], to_strings(&["gateway.example", "[::1]"]));
+        assert_eq!(
+            Ok(()),
+            policy.validate(&request(&[("Host", "gateway.example")]))
+        );
+        assert_eq!(
+            Ok(()),
+            policy.validate(&request(&[("Host", "GATEWAY.example:443")]))
+        );
+        assert_eq!(Ok(()), policy.validate(&request(&[("Host", "[::1]:8080")])));
+        assert_eq!(
+            Err(UpgradeRejection::HostNotAllowed),
+            policy.validate(&request(&[("Host", "other.example")]))
+        );
+        assert_eq!(
+            Err(UpgradeRejection::MissingHost),
+            policy.validate(&request(&[]))
+        );
+    }
+
+    #[test]
+    fn should_refuse_with_http_status() {
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            UpgradeRejection::OriginNotAllowed
+                .to_error_response()
+                .status()
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            UpgradeRejection::MissingHost.to_error_response().status()
+        );
+        assert_eq!(
+            StatusCode::MISDIRECTED_REQUEST,
+            UpgradeRejection::HostNotAllowed
+                .to_error_response()
+                .status()
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use crate::connection_authorizer::ClientCertificate;
+use metrics::counter;
+use rustls::{
+    server::{
+        AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello,
+        ResolvesServerCert,
+    },
+    sign::{any_supported_type, CertifiedKey},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::time::interval;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{debug, error, info};
+
+/// Paths to certificate and certificate key
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub certificate_pem_path: String,
+    pub certificate_key_pem_path: String,
+}
+
+/// Whether the clients have to present a certificate in the TLS handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuthMode {
+    /// The clients are not asked for a certificate
+    Off,
+    /// The clients may present a certificate, which is verified if presented
+    Optional,
+    /// The handshakes with the clients which do not present a valid certificate are aborted
+    Required,
+}
+
+impl ClientAuthMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientAuthMode::Off => "off",
+            ClientAuthMode::Optional => "optional",
+            ClientAuthMode::Required => "required",
+        }
+    }
+}
+
+impl FromStr for ClientAuthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ClientAuthMode::Off),
+            "optional" => Ok(ClientAuthMode::Optional),
+            "required" => Ok(ClientAuthMode::Required),
+            _ => Err(format!("Invalid client authentication mode: {}", s)),
+        }
+    }
+}
+
+/// Configuration of the authentication of the clients with their TLS certificate (mutual TLS)
+#[derive(Debug, Clone)]
+pub struct ClientAuthConfig {
+    pub mode: ClientAuthMode,
+    /// Path of the bundle of the CA certificates the client certificates must be issued by
+    pub ca_bundle_pem_path: Option<String>,
+}
+
+/// Last modification times of the certificate and key files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ModificationTimes {
+    certificate: SystemTime,
+    certificate_key: SystemTime,
+}
+
+/// Modification times used to detect when the files have to be reloaded
+#[derive(Debug, Default)]
+struct FilesWatch {
+    /// Modification times of the files when they were last loaded
+    loaded: Option<ModificationTimes>,
+    /// Modification times observed at the previous check, if different from the loaded ones
+    observed: Option<ModificationTimes>,
+}
+
+/// Certificate which can be replaced while the gateway is running, without affecting the established connections
+pub struct ReloadableCertificate {
+    tls_config: TlsConfig,
+    /// Certificate presented in the new TLS handshakes
+    // the lock is never held across an '.await', therefore a sync lock can be used
+    certified_key: RwLock<Arc<CertifiedKey>>,
+    files_watch: Mutex<FilesWatch>,
+}
+
+impl ReloadableCertificate {
+    /// Loads the certificate and key from their files
+    pub fn load(tls_config: TlsConfig) -> Result<Self, String> {
+        let modification_times = get_modification_times(&tls_config).ok();
+        let certified_key = load_certified_key(&tls_config)?;
+        Ok(Self {
+            tls_config,
+            certified_key: RwLock::new(Arc::new(certified_key)),
+            files_watch: Mutex::new(FilesWatch {
+                loaded: modification_times,
+                observed: None,
+            }),
+        })
+    }
+
+    /// Reloads the certificate and key from their files
+    /// If they cannot be loaded, the current certificate is kept
+    pub fn reload(&self) -> Result<(), String> {
+        let modification_times = get_modification_times(&self.tls_config).ok();
+        let certified_key = load_certified_key(&self.tls_config)?;
+        *self
+            .certified_key
+            .write()
+            .expect("lock should not be poisoned") = Arc::new(certified_key);
+        *self
+            .files_watch
+            .lock()
+            .expect("lock should not be poisoned") = FilesWatch {
+            loaded: modification_times,
+            observed: None,
+        };
+        info!(
+            "Reloaded TLS certificate {}",
+            self.tls_config.certificate_pem_path
+        );
+        Ok(())
+    }
+
+    /// Reloads the certificate if its files have been modified since they were last loaded
+    /// Returns true if the files have been reloaded
+    pub fn reload_if_modified(&self) -> Result<bool, String> {
+        let modification_times = get_modification_times(&self.tls_config)?;
+        {
+            let mut files_watch = self
+                .files_watch
+                .lock()
+                .expect("lock should not be poisoned");
+            if files_watch.loaded == Some(modification_times) {
+                files_watch.observed = None;
+                return Ok(false);
+            }
+            // the certificate and key are usually written one after the other (e.g. by certbot),
+            // therefore they are reloaded only once they have not changed since the previous check
+            if files_watch.observed != Some(modification_times) {
+                files_watch.observed = Some(modification_times);
+                return Ok(false);
+            }
+            // if the new files cannot be loaded, they are not retried until they are modified again
+            files_watch.loaded = Some(modification_times);
+            files_watch.observed = None;
+        }
+        let certified_key = load_certified_key(&self.tls_config)?;
+        *self
+            .certified_key
+            .write()
+            .expect("lock should not be poisoned") = Arc::new(certified_key);
+        info!(
+            "Reloaded modified TLS certificate {}",
+            self.tls_config.certificate_pem_path
+        );
+        Ok(true)
+    }
+
+    /// Returns the certificate presented in the new TLS handshakes
+    pub(crate) fn get(&self) -> Arc<CertifiedKey> {
+        Arc::clone(
+            &self
+                .certified_key
+                .read()
+                .expect("lock should not be poisoned"),
+        )
+    }
+}
+
+/// Certificate served for the server names of an entry of the SNI certificates file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificateEntry {
+    /// Server names of the certificate, which may start with a '*.' wildcard matching a single label
+    pub server_names: Vec<String>,
+    pub certificate_pem_path: String,
+    pub certificate_key_pem_path: String,
+}
+
+/// Content of the SNI certificates file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SniCertificatesFile {
+    certificates: Vec<SniCertificateEntry>,
+}
+
+/// Loads the entries from a JSON file of the form:
+/// { "certificates": [ { "server_names": [<names>], "certificate_pem_path": <path>, "certificate_key_pem_path": <path> } ] }
+pub fn load_sni_certificate_entries(path: &str) -> Result<Vec<SniCertificateEntry>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read SNI certificates file {}: {}", path, e))?;
+    parse_sni_certificate_entries(&content)
+}
+
+pub(crate) fn parse_sni_certificate_entries(
+    content: &str,
+) -> Result<Vec<SniCertificateEntry>, String> {
+    let sni_certificates_file: SniCertificatesFile = serde_json::from_str(content)
+        .map_err(|e| format!("Could not parse SNI certificates: {}", e))?;
+    Ok(sni_certificates_file.certificates)
+}
+
+/// Certificates presented in the TLS handshakes, selected by the server name sent by the client (SNI)
+pub struct TlsCertificates {
+    /// Certificate presented to the clients which do not send a server name or whose server name is unknown
+    default: Option<Arc<ReloadableCertificate>>,
+    /// Certificates keyed by their lowercase server name
+    by_server_name: HashMap<String, Arc<ReloadableCertificate>>,
+    /// If true, the handshakes with an unknown server name are aborted instead of presenting the default certificate
+    reject_unknown_server_names: bool,
+}
+
+impl TlsCertificates {
+    /// Loads the default certificate and the certificates of the SNI entries
+    pub fn load(
+        default: Option<TlsConfig>,
+        sni_certificate_entries: Vec<SniCertificateEntry>,
+        reject_unknown_server_names: bool,
+    ) -> Result<Self, String> {
+        let default = default
+            .map(ReloadableCertificate::load)
+            .transpose()?
+            .map(Arc::new);
+        let mut by_server_name = HashMap::new();
+        for sni_certificate_entry in sni_certificate_entries {
+            // the certificate is shared by all the server names of the entry, so that it is loaded only once
+            let certificate = Arc::new(ReloadableCertificate::load(TlsConfig {
+                certificate_pem_path: sni_certificate_entry.certificate_pem_path,
+                certificate_key_pem_path: sni_certificate_entry.certificate_key_pem_path,
+            })?);
+            for server_name in sni_certificate_entry.server_names {
+                let server_name = server_name.to_ascii_lowercase();
+                if by_server_name
+                    .insert(server_name.clone(), Arc::clone(&certificate))
+                    .is_some()
+                {
+                    return Err(format!(
+                        "Server name {} has more than one certificate",
+                        server_name
+                    ));
+                }
+            }
+        }
+        Ok(Self {
+            default,
+            by_server_name,
+            reject_unknown_server_names,
+        })
+    }
+
+    /// Returns the certificate to be presented to a client sending 'server_name', None if the handshake must be aborted
+    pub(crate) fn get(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let Some(server_name) = server_name else {
+            return self.default.as_ref().map(|default| default.get());
+        };
+        let server_name = server_name.to_ascii_lowercase();
+        let wildcard_server_name = server_name
+            .split_once('.')
+            .map(|(_, parent_domain)| format!("*.{}", parent_domain));
+        let certificate = self.by_server_name.get(&server_name).or_else(|| {
+            wildcard_server_name
+                .and_then(|wildcard_server_name| self.by_server_name.get(&wildcard_server_name))
+        });
+        match certificate {
+            Some(certificate) => Some(certificate.get()),
+            None if self.reject_unknown_server_names => None,
+            None => self.default.as_ref().map(|default| default.get()),
+        }
+    }
+
+    /// Returns all the certificates, each only once even if it has multiple server names
+    fn certificates(&self) -> Vec<&Arc<ReloadableCertificate>> {
+        let mut certificates: Vec<&Arc<ReloadableCertificate>> = Vec::new();
+        for certificate in self.default.iter().chain(self.by_server_name.values()) {
+            if !certificates
+                .iter()
+                .any(|loaded| Arc::ptr_eq(loaded, certificate))
+            {
+                certificates.push(certificate);
+            }
+        }
+        certificates
+    }
+
+    /// Reloads all the certificates from their files
+    /// The certificates which cannot be loaded are kept, without affecting the reload of the others
+    pub fn reload(&self) {
+        for certificate in self.certificates() {
+            if let Err(e) = certificate.reload() {
+                // keep serving the previous certificate so that the new connections can still be accepted
+                error!("Could not reload TLS certificate: {}", e);
+            }
+        }
+    }
+
+    /// Reloads the certificates whose files have been modified since they were last loaded
+    pub fn reload_if_modified(&self) {
+        for certificate in self.certificates() {
+            if let Err(e) = certificate.reload_if_modified() {
+                error!("Could not reload TLS certificate: {}", e);
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for TlsCertificates {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certificate = self.get(client_hello.server_name());
+        if certificate.is_none() {
+            counter!("connections_rejected", "reason" => "unknown_server_name").increment(1);
+            debug!(
+                "Aborted TLS handshake for unknown server name {:?}",
+                client_hello.server_name()
+            );
+        }
+        certificate
+    }
+}
+
+/// Creates the acceptor performing the TLS handshakes with the current certificates
+pub fn create_tls_acceptor(
+    tls_certificates: Arc<TlsCertificates>,
+    client_auth_config: &ClientAuthConfig,
+) -> Result<TlsAcceptor, String> {
+    let server_config_builder = ServerConfig::builder().with_safe_defaults();
+    info!(
+        "TLS client authentication: {}",
+        client_auth_config.mode.as_str()
+    );
+    let server_config_builder = match client_auth_config.mode {
+        ClientAuthMode::Off => server_config_builder.with_no_client_auth(),
+        ClientAuthMode::Optional | ClientAuthMode::Required => {
+            let ca_bundle_pem_path = client_auth_config
+                .ca_bundle_pem_path
+                .as_deref()
+                .ok_or_else(|| {
+                    String::from("A client CA bundle is required to authenticate the clients")
+                })?;
+            let client_ca_roots = load_client_ca_roots(ca_bundle_pem_path)?;
+            let client_cert_verifier = if client_auth_config.mode == ClientAuthMode::Required {
+                AllowAnyAuthenticatedClient::new(client_ca_roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(client_ca_roots).boxed()
+            };
+            server_config_builder.with_client_cert_verifier(client_cert_verifier)
+        },
+    };
+    let server_config = server_config_builder.with_cert_resolver(tls_certificates);
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn load_client_ca_roots(path: &str) -> Result<RootCertStore, String> {
+    let mut client_ca_roots = RootCertStore::empty();
+    for ca_certificate in load_certificate_chain(path)? {
+        client_ca_roots
+            .add(&ca_certificate)
+            .map_err(|e| format!("Invalid CA certificate in {}: {}", path, e))?;
+    }
+    Ok(client_ca_roots)
+}
+
+/// Returns the identity of the client if it presented a certificate in the TLS handshake
+/// The certificate has already been verified during the handshake
+pub fn get_client_certificate<IO>(tls_stream: &TlsStream<IO>) -> Option<ClientCertificate> {
+    let (_, server_connection) = tls_stream.get_ref();
+    let end_entity_certificate = server_connection.peer_certificates()?.first()?;
+    let (_, certificate) = x509_parser::parse_x509_certificate(&end_entity_certificate.0).ok()?;
+    Some(ClientCertificate {
+        subject: certificate.subject().to_string(),
+    })
+}
+
+/// Checks whether the certificate files have been modified every 'reload_interval' and if so reloads them
+pub async fn reload_certificates_periodically(
+    tls_certificates: Arc<TlsCertificates>,
+    reload_interval: Duration,
+) {
+    let mut reload_interval = interval(reload_interval);
+    loop {
+        reload_interval.tick().await;
+        tls_certificates.reload_if_modified();
+    }
+}
+
+fn get_modification_times(tls_config: &TlsConfig) -> Result<ModificationTimes, String> {
+    let get_modification_time = |path: &str| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("Could not read metadata of {}: {}", path, e))
+    };
+    Ok(ModificationTimes {
+        certificate: get_modification_time(&tls_config.certificate_pem_path)?,
+        certificate_key: get_modification_time(&tls_config.certificate_key_pem_path)?,
+    })
+}
+
+fn load_certified_key(tls_config: &TlsConfig) -> Result<CertifiedKey, String> {
+    let certificate_chain = load_certificate_chain(&tls_config.certificate_pem_path)?;
+    let private_key = load_private_key(&tls_config.certificate_key_pem_path)?;
+    let signing_key = any_supported_type(&private_key).map_err(|e| {
+        format!(
+            "Unsupported private key {}: {}",
+            tls_config.certificate_key_pem_path, e
+        )
+    })?;
+    Ok(CertifiedKey::new(certificate_chain, signing_key))
+}
+
+fn load_certificate_chain(path: &str) -> Result<Vec<Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+    let certificate_chain: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("Could not parse certificates in {}: {}", path, e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificate_chain.is_empty() {
+        return Err(format!("No certificate found in {}", path));
+    }
+    Ok(certificate_chain)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("Could not parse private key in {}: {}", path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", path))
+}
@@ -0,0 +1,76 @@
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use tokio_tungstenite::tungstenite::{handshake::server::Request, http::header::HeaderName};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Proxies in front of the gateway which are trusted to tell the address of the clients
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    /// Ranges of the addresses of the trusted proxies
+    networks: Vec<IpNet>,
+    /// Whether the connections from the trusted proxies start with a PROXY protocol (v1 or v2) header
+    proxy_protocol: bool,
+    /// Whether the address of the client is taken from the X-Forwarded-For header of the upgrade requests
+    /// received from the trusted proxies
+    forwarded_for: bool,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>, proxy_protocol: bool, forwarded_for: bool) -> Self {
+        Self {
+            networks,
+            proxy_protocol,
+            forwarded_for,
+        }
+    }
+
+    /// Returns true if the address belongs to a trusted proxy
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        // IPv4 clients of a dual stack listener have an IPv4-mapped IPv6 address
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Returns true if the connections from the peer start with a PROXY protocol header
+    pub fn expects_proxy_header(&self, peer_addr: &SocketAddr) -> bool {
+        self.proxy_protocol && self.is_trusted(&peer_addr.ip())
+    }
+
+    /// Returns true if the address of the client of a connection from the peer is in the X-Forwarded-For header
+    pub fn expects_forwarded_for(&self, peer_addr: &SocketAddr) -> bool {
+        self.forwarded_for && self.is_trusted(&peer_addr.ip())
+    }
+
+    /// Returns the address of the client which sent the request through the trusted proxies,
+    /// None if the request does not have a valid X-Forwarded-For header
+    pub fn get_forwarded_client_ip(&self, request: &Request) -> Option<IpAddr> {
+        // each proxy appends the address it received the request from,
+        // therefore only the addresses appended by the trusted proxies, on the right, can be relied upon
+        let forwarded_ips: Vec<&str> = request
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let mut client_ip = None;
+        for forwarded_ip in forwarded_ips.iter().rev() {
+            let ip = parse_forwarded_ip(forwarded_ip)?;
+            client_ip = Some(ip);
+            if !self.is_trusted(&ip) {
+                break;
+            }
+        }
+        client_ip
+    }
+}
+
+/// Parses an address of the X-Forwarded-For header, which may be followed by a port
+fn parse_forwarded_ip(forwarded_ip: &str) -> Option<IpAddr> {
+    forwarded_ip
+        .parse::<IpAddr>()
+        .or_else(|_| forwarded_ip.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
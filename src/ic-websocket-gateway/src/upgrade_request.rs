@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request},
+    http::{header, Response, StatusCode},
+};
+
+/// Reason why a WebSocket upgrade request is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeRejection {
+    /// The request does not have an allowed Origin header
+    OriginNotAllowed,
+    /// The request does not have a Host header
+    MissingHost,
+    /// The Host header of the request is not one of the allowed host names
+    HostNotAllowed,
+}
+
+impl UpgradeRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpgradeRejection::OriginNotAllowed => "origin_not_allowed",
+            UpgradeRejection::MissingHost => "missing_host",
+            UpgradeRejection::HostNotAllowed => "host_not_allowed",
+        }
+    }
+
+    /// Returns the HTTP response refusing the upgrade request
+    pub fn to_error_response(self) -> ErrorResponse {
+        let status = match self {
+            UpgradeRejection::OriginNotAllowed => StatusCode::FORBIDDEN,
+            UpgradeRejection::MissingHost => StatusCode::BAD_REQUEST,
+            UpgradeRejection::HostNotAllowed => StatusCode::MISDIRECTED_REQUEST,
+        };
+        let mut response = Response::new(Some(String::from(self.as_str())));
+        *response.status_mut() = status;
+        response
+    }
+}
+
+/// Policy applied to the HTTP requests upgrading the connections to WebSocket
+#[derive(Debug, Clone, Default)]
+pub struct UpgradeRequestPolicy {
+    /// If set, only the requests whose Origin header is in the set are accepted
+    allowed_origins: Option<HashSet<String>>,
+    /// If set, only the requests whose Host header (without the port) is in the set are accepted
+    allowed_hosts: Option<HashSet<String>>,
+}
+
+impl UpgradeRequestPolicy {
+    /// Creates the policy from the allowed Origin values and Host names,
+    /// the corresponding header is not checked if the list is empty
+    pub fn new(allowed_origins: Vec<String>, allowed_hosts: Vec<String>) -> Self {
+        let to_set = |values: Vec<String>| {
+            (!values.is_empty()).then(|| values.iter().map(String::as_str).map(normalize).collect())
+        };
+        Self {
+            allowed_origins: to_set(allowed_origins),
+            allowed_hosts: to_set(allowed_hosts),
+        }
+    }
+
+    /// Checks the headers of the upgrade request against the policy
+    pub fn validate(&self, request: &Request) -> Result<(), UpgradeRejection> {
+        if let Some(allowed_origins) = &self.allowed_origins {
+            // clients which do not send the Origin header are refused, as they cannot be told apart from the others
+            let origin = get_header(request, header::ORIGIN).map(normalize);
+            if !origin.is_some_and(|origin| allowed_origins.contains(&origin)) {
+                return Err(UpgradeRejection::OriginNotAllowed);
+            }
+        }
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            let host = get_header(request, header::HOST).ok_or(UpgradeRejection::MissingHost)?;
+            if !allowed_hosts.contains(&normalize(strip_port(host))) {
+                return Err(UpgradeRejection::HostNotAllowed);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_header(request: &Request, name: header::HeaderName) -> Option<&str> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Origins and host names are case insensitive
+fn normalize(value: &str) -> String {
+    value.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+/// Returns the host name of a Host header, which may be followed by a port
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 address, e.g. [::1]:8080
+        return match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        };
+    }
+    match host.rsplit_once(':') {
+        Some((host_name, _port)) => host_name,
+        None => host,
+    }
+}
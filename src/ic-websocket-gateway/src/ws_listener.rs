@@ -1,104 +1,156 @@
-use crate::client_session_handler::ClientSessionHandler;
+use crate::{
+    client_session_handler::{ClientSessionHandler, ClientSessionHandlerConfig},
+    connection_authorizer::ConnectionAuthorizer,
+    connection_limiter::{ConnectionLimiter, HandshakePermit, IpConnectionPermit},
+    listener_socket::{ListenerDefinition, ListenerSocket, SocketStream},
+    proxy_protocol::read_proxy_header,
+    tls::get_client_certificate,
+    trusted_proxies::TrustedProxies,
+};
 use gateway_state::GatewayState;
 use ic_agent::Agent;
-use native_tls::Identity;
-use std::{fs, net::SocketAddr, sync::Arc, time::Duration};
+use metrics::counter;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
-    net::{TcpListener, TcpStream},
     select,
     sync::mpsc::{self, Receiver, Sender},
     time::timeout,
 };
-use tokio_native_tls::{TlsAcceptor, TlsStream};
-use tracing::{debug, error, info, span, warn, Instrument, Level, Span};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, error, field, info, span, warn, Instrument, Level, Span};
 
-/// Possible TCP streams.
+/// Possible streams of the accepted connections.
 pub enum CustomStream {
-    Tcp(TcpStream),
-    TcpWithTls(TlsStream<TcpStream>),
+    Plain(SocketStream),
+    WithTls(TlsStream<SocketStream>),
 }
 
-/// Paths to certificate and certificate key
-pub struct TlsConfig {
-    pub certificate_pem_path: String,
-    pub certificate_key_pem_path: String,
+/// Configuration shared by all the listeners
+#[derive(Clone)]
+pub struct WsListenerConfig {
+    /// Acceptor performing the TLS handshakes on the TLS listeners, required if any listener uses TLS
+    pub tls_acceptor: Option<TlsAcceptor>,
+    /// Hook deciding whether the connections which completed the TLS handshake can be upgraded to WebSocket
+    pub connection_authorizer: Arc<dyn ConnectionAuthorizer>,
+    /// Limiter of the incoming connections of all the listeners
+    pub connection_limiter: Arc<ConnectionLimiter>,
+    /// Proxies trusted to tell the address of the clients
+    pub trusted_proxies: Arc<TrustedProxies>,
 }
 
 type TlsAcceptorTimeout = Duration;
 
+/// Time given to a trusted proxy to send the PROXY protocol header
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Identifier of the client connection
 pub type ClientId = u64;
 
+/// Client ID assigned to the next client connection, shared by all the listeners so that the IDs are unique
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Address of the client of an accepted connection
+pub enum ClientAddress {
+    /// Address of the peer of the connection, or the one sent by a trusted proxy in the PROXY protocol header
+    Known(SocketAddr),
+    /// The connection is relayed by a trusted proxy which sends the address of the client
+    /// in the X-Forwarded-For header of the upgrade request
+    Forwarded {
+        proxy_addr: SocketAddr,
+        /// Whether the limits per IP apply to the address of the proxy if the upgrade request does not have the header,
+        /// which is not the case on a Unix domain socket
+        is_proxy_ip_limited: bool,
+        trusted_proxies: Arc<TrustedProxies>,
+        /// Limiter checking the IP of the client once it is known
+        connection_limiter: Arc<ConnectionLimiter>,
+    },
+}
+
 /// Contains the information of an accepted connection needed to start a client session handler
 pub struct AcceptedConnection {
     /// Identifier of the client connection
     pub client_id: ClientId,
     /// TCP stream
     pub stream: CustomStream,
+    /// Address of the client
+    pub client_address: ClientAddress,
     /// Tracing span of the connection
     pub span: AcceptedConnectionSpan,
+    /// Permit held until the WebSocket handshake completes
+    pub handshake_permit: HandshakePermit,
+    /// Permit held until the connection is closed,
+    /// None if the address of the client is known only from the upgrade request
+    /// or if the connection was accepted on a Unix domain socket without a client address from a trusted proxy
+    pub ip_connection_permit: Option<IpConnectionPermit>,
 }
 
 pub type AcceptedConnectionSpan = Span;
 
-/// Listener of incoming TCP connections
+/// Listener of incoming connections
 pub struct WsListener {
-    // Listener of incoming TCP connectionsx
-    listener: TcpListener,
+    // Socket on which the incoming connections are accepted
+    listener: ListenerSocket,
     // TLS acceptor (if enabled)
     tls_acceptor: Option<TlsAcceptor>,
+    /// Hook deciding whether the accepted connections can be upgraded to WebSocket
+    connection_authorizer: Arc<dyn ConnectionAuthorizer>,
+    /// Limiter of the incoming connections
+    connection_limiter: Arc<ConnectionLimiter>,
+    /// Proxies trusted to tell the address of the clients
+    trusted_proxies: Arc<TrustedProxies>,
     /// Agent used to interact with the IC
     agent: Arc<Agent>,
     /// State of the gateway
     gateway_state: GatewayState,
-    // Polling interval in milliseconds
-    polling_interval_ms: u64,
-    // Client ID assigned to the next client connection
-    next_client_id: ClientId,
+    /// Configuration of the client session handlers
+    client_session_handler_config: ClientSessionHandlerConfig,
+    /// Token cancelled when the gateway is shutting down
+    shutdown_token: CancellationToken,
+    /// Tracker of the client session handlers and pollers tasks
+    task_tracker: TaskTracker,
 }
 
 impl WsListener {
-    pub async fn new(
-        gateway_address: &str,
+    pub fn new(
+        listener_definition: &ListenerDefinition,
         agent: Arc<Agent>,
         gateway_state: GatewayState,
-        polling_interval_ms: u64,
-        tls_config: Option<TlsConfig>,
+        client_session_handler_config: ClientSessionHandlerConfig,
+        ws_listener_config: WsListenerConfig,
+        shutdown_token: CancellationToken,
+        task_tracker: TaskTracker,
     ) -> Self {
-        let listener = TcpListener::bind(&gateway_address)
-            .await
-            .expect("Can't listen on this address");
-        let tls_acceptor = {
-            if let Some(tls_config) = tls_config {
-                let chain =
-                    fs::read(tls_config.certificate_pem_path).expect("Can't read certificate");
-                let privkey =
-                    fs::read(tls_config.certificate_key_pem_path).expect("Can't read private key");
-                let tls_identity =
-                    Identity::from_pkcs8(&chain, &privkey).expect("Can't create a TLS identity");
-                let acceptor = TlsAcceptor::from(
-                    native_tls::TlsAcceptor::builder(tls_identity)
-                        .build()
-                        .expect("Can't create a TLS acceptor from the TLS identity"),
-                );
-                info!("TLS enabled");
-                Some(acceptor)
-            } else {
-                info!("TLS disabled");
-                None
-            }
-        };
+        let listener = ListenerSocket::bind(&listener_definition.address)
+            .unwrap_or_else(|e| panic!("Can't listen on {}: {}", listener_definition, e));
+        let tls_acceptor = listener_definition.tls.then(|| {
+            ws_listener_config
+                .tls_acceptor
+                .expect("TLS listeners require a TLS certificate")
+        });
+        info!("Listening on {}", listener_definition);
         Self {
             listener,
             tls_acceptor,
+            connection_authorizer: ws_listener_config.connection_authorizer,
+            connection_limiter: ws_listener_config.connection_limiter,
+            trusted_proxies: ws_listener_config.trusted_proxies,
             agent,
             gateway_state,
-            polling_interval_ms,
-            next_client_id: 0,
+            client_session_handler_config,
+            shutdown_token,
+            task_tracker,
         }
     }
 
-    /// Accepts incoming connections
+    /// Accepts incoming connections until the gateway is shutting down
     pub async fn listen_for_incoming_requests(&mut self) {
         // [ws listener task]                [tls acceptor task]
         // tls_acceptor_channel_rx    <----- tls_acceptor_channel_tx
@@ -111,48 +163,157 @@ impl WsListener {
 
         loop {
             select! {
-                Ok((stream, client_addr)) = self.listener.accept() => {
-                    self.accept_connection(
-                        client_addr,
-                        stream,
-                        tls_acceptor_channel_tx.clone(),
-                    );
-                    self.next_client_id += 1;
+                Ok((stream, peer_addr)) = self.listener.accept() => {
+                    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+                    // the limits are checked before spawning any task for the connection
+                    // so that a flood of connections cannot exhaust the resources of the gateway
+                    // the limits of the IP of a connection relayed by a trusted proxy are checked once the address of the client is known
+                    // the peers of a Unix domain socket all share the same address, so they are not subject to the limits per IP
+                    // unless a trusted proxy tells the address of the client
+                    let is_relayed_by_proxy = self.trusted_proxies.expects_proxy_header(&peer_addr)
+                        || self.trusted_proxies.expects_forwarded_for(&peer_addr);
+                    let permits = if is_relayed_by_proxy || self.listener.is_unix() {
+                        self.connection_limiter
+                            .try_acquire_handshake_permit()
+                            .map(|handshake_permit| (handshake_permit, None))
+                    } else {
+                        self.connection_limiter
+                            .try_accept(peer_addr.ip())
+                            .map(|(handshake_permit, ip_connection_permit)| (handshake_permit, Some(ip_connection_permit)))
+                    };
+                    match permits {
+                        Ok((handshake_permit, ip_connection_permit)) => {
+                            self.accept_connection(
+                                client_id,
+                                peer_addr,
+                                stream,
+                                handshake_permit,
+                                ip_connection_permit,
+                                tls_acceptor_channel_tx.clone(),
+                            );
+                        },
+                        Err(rejection) => {
+                            // the TCP stream is dropped and therefore the connection is closed
+                            counter!("connections_rejected", "reason" => rejection.as_str()).increment(1);
+                            debug!("Rejected connection from {}: {}", peer_addr.ip(), rejection.as_str());
+                        },
+                    }
                 },
                 Some(AcceptedConnection {
                     client_id,
                     stream,
-                    span: accept_client_connection_span
+                    client_address,
+                    span: accept_client_connection_span,
+                    handshake_permit,
+                    ip_connection_permit,
                 }) = tls_acceptor_channel_rx.recv() => {
                     accept_client_connection_span.in_scope(|| {
                         // the client connection has been accepted and therefore the connection handler has to be started
-                        self.start_session_handler(client_id, stream);
+                        self.start_session_handler(client_id, stream, client_address, handshake_permit, ip_connection_permit);
                     });
+                },
+                _ = self.shutdown_token.cancelled() => {
+                    // connections which are still performing the TLS handshake are dropped
+                    // together with the receiving side of the channel
+                    info!("Gateway shutting down. Not accepting new connections");
+                    break;
                 }
             }
         }
     }
 
-    /// Performs the TLS handshake in a separate task
+    /// Reads the PROXY protocol header and performs the TLS handshake in a separate task
     /// because it could take several seconds to complete
     /// and this would otherwise block other incoming connections
     fn accept_connection(
         &self,
-        client_addr: SocketAddr,
-        stream: TcpStream,
+        client_id: ClientId,
+        peer_addr: SocketAddr,
+        mut stream: SocketStream,
+        handshake_permit: HandshakePermit,
+        ip_connection_permit: Option<IpConnectionPermit>,
         tls_acceptor_channel_tx: Sender<AcceptedConnection>,
     ) {
         let accept_client_connection_span = span!(
             Level::DEBUG,
             "Accept Connection",
-            client_addr = ?client_addr.ip(),
-            client_id = self.next_client_id,
+            peer_addr = ?peer_addr.ip(),
+            client_addr = field::Empty,
+            client_id,
             cargo_version = env!("CARGO_PKG_VERSION"),
+            client_certificate_subject = field::Empty,
         );
-        let client_id = self.next_client_id;
         let tls_acceptor = self.tls_acceptor.clone();
+        let connection_authorizer = Arc::clone(&self.connection_authorizer);
+        let trusted_proxies = Arc::clone(&self.trusted_proxies);
+        let connection_limiter = Arc::clone(&self.connection_limiter);
+        let is_unix_socket = self.listener.is_unix();
         tokio::spawn(
             async move {
+                // the address of the peer of a Unix domain socket is not the one of the client
+                let mut is_client_ip_known = !is_unix_socket;
+                let client_addr = if trusted_proxies.expects_proxy_header(&peer_addr) {
+                    match timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut stream)).await {
+                        Ok(Ok(Some(source_addr))) => {
+                            is_client_ip_known = true;
+                            source_addr
+                        },
+                        // the proxy does not know the address of the client, e.g. for its health checks
+                        Ok(Ok(None)) => peer_addr,
+                        Ok(Err(e)) => {
+                            counter!("connections_rejected", "reason" => "invalid_proxy_header")
+                                .increment(1);
+                            error!("Failed to accept connection: {:?}", e);
+                            return;
+                        },
+                        Err(e) => {
+                            counter!("connections_rejected", "reason" => "proxy_header_timeout")
+                                .increment(1);
+                            error!("Reading PROXY header timed out: {:?}", e);
+                            return;
+                        },
+                    }
+                } else {
+                    peer_addr
+                };
+                let (client_address, ip_connection_permit) = match ip_connection_permit {
+                    Some(ip_connection_permit) => (
+                        ClientAddress::Known(client_addr),
+                        Some(ip_connection_permit),
+                    ),
+                    // the address of the client is known only once the upgrade request is received
+                    None if trusted_proxies.expects_forwarded_for(&client_addr) => (
+                        ClientAddress::Forwarded {
+                            proxy_addr: client_addr,
+                            is_proxy_ip_limited: is_client_ip_known,
+                            trusted_proxies,
+                            connection_limiter,
+                        },
+                        None,
+                    ),
+                    // the limits per IP would apply to all the clients of the Unix domain socket at once
+                    None if !is_client_ip_known => (ClientAddress::Known(client_addr), None),
+                    None => match connection_limiter.try_accept_from_ip(client_addr.ip()) {
+                        Ok(ip_connection_permit) => (
+                            ClientAddress::Known(client_addr),
+                            Some(ip_connection_permit),
+                        ),
+                        Err(rejection) => {
+                            counter!("connections_rejected", "reason" => rejection.as_str())
+                                .increment(1);
+                            debug!(
+                                "Rejected connection from {}: {}",
+                                client_addr.ip(),
+                                rejection.as_str()
+                            );
+                            return;
+                        },
+                    },
+                };
+                if let ClientAddress::Known(client_addr) = &client_address {
+                    Span::current().record("client_addr", field::debug(client_addr.ip()));
+                }
+
                 let custom_stream = match tls_acceptor {
                     Some(ref acceptor) => {
                         match timeout(TlsAcceptorTimeout::from_secs(10), acceptor.accept(stream))
@@ -160,7 +321,14 @@ impl WsListener {
                         {
                             Ok(Ok(tls_stream)) => {
                                 debug!("Accepted TLS connection");
-                                Ok(CustomStream::TcpWithTls(tls_stream))
+                                let client_certificate = get_client_certificate(&tls_stream);
+                                if let Some(client_certificate) = &client_certificate {
+                                    Span::current().record(
+                                        "client_certificate_subject",
+                                        client_certificate.subject.as_str(),
+                                    );
+                                }
+                                Ok((CustomStream::WithTls(tls_stream), client_certificate))
                             },
                             Ok(Err(e)) => Err(format!("TLS handshake failed: {:?}", e)),
                             Err(e) => Err(format!("Accepting TLS connection timed out: {:?}", e)),
@@ -168,19 +336,34 @@ impl WsListener {
                     },
                     None => {
                         debug!("Accepted connection without TLS");
-                        Ok(CustomStream::Tcp(stream))
+                        Ok((CustomStream::Plain(stream), None))
                     },
                 };
                 match custom_stream {
-                    Ok(custom_stream) => {
-                        tls_acceptor_channel_tx
+                    Ok((custom_stream, client_certificate)) => {
+                        if !connection_authorizer
+                            .is_authorized(&client_addr, client_certificate.as_ref())
+                        {
+                            counter!("connections_rejected", "reason" => "not_authorized")
+                                .increment(1);
+                            debug!("Refused connection not authorized by the gateway");
+                            return;
+                        }
+                        if tls_acceptor_channel_tx
                             .send(AcceptedConnection {
                                 client_id,
                                 stream: custom_stream,
+                                client_address,
                                 span: Span::current(),
+                                handshake_permit,
+                                ip_connection_permit,
                             })
                             .await
-                            .expect("ws listener's side of the channel dropped");
+                            .is_err()
+                        {
+                            // the ws listener's side of the channel is dropped only when the gateway is shutting down
+                            debug!("Dropped accepted connection as the gateway is shutting down");
+                        }
                     },
                     Err(e) => {
                         error!("Failed to accept connection: {:?}", e);
@@ -192,32 +375,52 @@ impl WsListener {
     }
 
     /// Spawns a new session handler
-    fn start_session_handler(&self, client_id: ClientId, stream: CustomStream) {
+    fn start_session_handler(
+        &self,
+        client_id: ClientId,
+        stream: CustomStream,
+        client_address: ClientAddress,
+        handshake_permit: HandshakePermit,
+        ip_connection_permit: Option<IpConnectionPermit>,
+    ) {
         debug!("Spawning new connection handler");
-        let client_session_handler_span =
-            span!(parent: &Span::current(),Level::DEBUG, "Client Session Handler", client_id);
+        let client_session_handler_span = span!(parent: &Span::current(),Level::DEBUG, "Client Session Handler", client_id, client_addr = field::Empty);
+        if let ClientAddress::Known(client_addr) = &client_address {
+            client_session_handler_span.record("client_addr", field::debug(client_addr.ip()));
+        }
 
         let agent = Arc::clone(&self.agent);
         let gateway_state = self.gateway_state.clone();
-        let polling_interval_ms = self.polling_interval_ms;
+        let client_session_handler_config = self.client_session_handler_config.clone();
+        let shutdown_token = self.shutdown_token.clone();
+        let task_tracker = self.task_tracker.clone();
         // spawn a session handler task for each incoming client connection
-        tokio::spawn(
+        // the task is tracked so that the gateway can wait for the session to be closed before shutting down
+        self.task_tracker.spawn(
             async move {
-                let mut client_session_handler =
-                    ClientSessionHandler::new(client_id, agent, gateway_state, polling_interval_ms);
+                // the connection counts towards the limit of its IP until the session handler terminates
+                let _ip_connection_permit = ip_connection_permit;
+                let mut client_session_handler = ClientSessionHandler::new(
+                    client_id,
+                    agent,
+                    gateway_state,
+                    client_session_handler_config,
+                    shutdown_token,
+                    task_tracker,
+                );
                 debug!("Started client session handler task");
 
                 if let Err(e) = {
                     match stream {
-                        CustomStream::Tcp(stream) => {
+                        CustomStream::Plain(stream) => {
                             client_session_handler
-                                .start_session(stream)
+                                .start_session(stream, client_address, handshake_permit)
                                 .instrument(Span::current())
                                 .await
                         },
-                        CustomStream::TcpWithTls(stream) => {
+                        CustomStream::WithTls(stream) => {
                             client_session_handler
-                                .start_session(stream)
+                                .start_session(stream, client_address, handshake_permit)
                                 .instrument(Span::current())
                                 .await
                         },